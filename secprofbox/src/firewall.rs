@@ -1,8 +1,9 @@
 use crate::state::{Config, Connection, ConnectionId, LanAccess, SecProfile, State, WatchState};
 use color_eyre::eyre::Error;
 use macaddr::MacAddr;
-use std::{fmt::Write, future::Future, net::IpAddr};
+use std::{fmt::Write, future::Future, net::IpAddr, time::Duration};
 use tokio::{process::Command, task::JoinSet};
+use tracing::warn;
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
 pub enum Zone {
@@ -225,88 +226,86 @@ pub async fn maintain_iptables(state: WatchState) -> Result<(), Error> {
     .await
 }
 
+/// How long to wait for LuCI or `uci` to finish editing /etc/config/firewall.
+const FIREWALL_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
 pub async fn write_basic_firewall_config(_cfg: &Config) -> Result<(), Error> {
-    use uciedit::openwrt::FirewallRule;
+    use uciedit::openwrt::types::IpMask;
     use uciedit::openwrt::FirewallTarget::{ACCEPT, REJECT};
-    use uciedit::rewrite_config;
+    use uciedit::openwrt::{network, network::Interface};
+    use uciedit::openwrt::{FirewallRule, FirewallRulePatch};
+    use uciedit::{parse_config_async, rewrite_config_async};
 
     const LAN_RULE_NAME: &str = "reject lan->lan unless accepted by start-wrt secprofs";
     const WAN_RULE_NAME: &str = "reject lan->wan unless accepted by start-wrt secprofs";
     const LOCALHOST_LAN_RULE_NAME: &str = "accept lan->localhost to allow admin access";
     const LOCALHOST_WAN_RULE_NAME: &str = "accept localhost->wan to allow admin access";
 
-    rewrite_config("/etc/config/firewall", |mut ctx| {
-        let mut found_lan_rule = false;
-        let mut found_wan_rule = false;
-        let mut found_localhost_lan_rule = false;
-        let mut found_localhost_wan_rule = false;
-        while ctx.step() {
-            if ctx.ty() != "rule" {
-                continue;
-            }
-            let Ok(mut rule) = ctx.get::<FirewallRule>() else {
+    // the reject rules don't depend on the network config, so a broken one can't hold them up,
+    // and only the lan interface is read so that nothing else in there can
+    let lan = parse_config_async(network::PATH, |ctx| {
+        ctx.sections_of::<Interface>()
+            .find(|section| section.name().as_deref() == Some("lan"))
+            .map(|section| section.get::<Interface>())
+            .transpose()
+    });
+    let router = match lan.await {
+        Ok(lan) => lan.and_then(|lan| lan.ipv4_address()),
+        Err(err) => {
+            warn!("can't read {}: {err:#}", network::PATH);
+            None
+        }
+    };
+
+    let rule = |name: &str, dest: &str, target| FirewallRule {
+        name: Some(name.into()),
+        src: Some("lan".into()),
+        dest: Some(dest.into()),
+        target,
+        ..Default::default()
+    };
+    let mut rules = vec![
+        rule(LAN_RULE_NAME, "lan", REJECT),
+        rule(WAN_RULE_NAME, "wan", REJECT),
+    ];
+    match router {
+        Some(router) => rules.push(FirewallRule {
+            dest_ip: vec![IpMask::from(IpAddr::from(router)).into()],
+            ..rule(LOCALHOST_LAN_RULE_NAME, "lan", ACCEPT)
+        }),
+        None => warn!("no static IPv4 address for lan, not adding {LOCALHOST_LAN_RULE_NAME:?}"),
+    }
+    // TODO: add LOCALHOST_WAN_RULE_NAME if it's missing, but what should it be?
+
+    // only the target is ours to flip, the user may have narrowed a rule down in LuCI
+    let target = |_: &FirewallRule, rule: FirewallRule| FirewallRulePatch {
+        target: Some(Some(rule.target)),
+        ..Default::default()
+    };
+    let changes = rewrite_config_async("/etc/config/firewall", FIREWALL_LOCK_TIMEOUT, |mut ctx| {
+        // a localhost->wan rule is only kept accepting, see the TODO above
+        let mut updated = Vec::new();
+        while let Some(mut section) = ctx.next_of::<FirewallRule>() {
+            let Ok(rule) = section.get::<FirewallRule>() else {
                 continue;
             };
-            if rule.name == LAN_RULE_NAME {
-                found_lan_rule = true;
-                rule.target = REJECT;
-                ctx.set(rule)?;
-            } else if rule.name == WAN_RULE_NAME {
-                found_wan_rule = true;
-                rule.target = REJECT;
-                ctx.set(rule)?;
-            } else if rule.name == LOCALHOST_LAN_RULE_NAME {
-                found_localhost_lan_rule = true;
-                rule.target = ACCEPT;
-                ctx.set(rule)?;
-            } else if rule.name == LOCALHOST_WAN_RULE_NAME {
-                found_localhost_wan_rule = true;
-                rule.target = ACCEPT;
-                ctx.set(rule)?;
-            }
-        }
-        if !found_lan_rule {
-            ctx.push(
-                FirewallRule {
-                    name: LAN_RULE_NAME.into(),
-                    src: "lan".into(),
-                    dest: "lan".into(),
-                    target: REJECT,
+            if rule.name.as_deref() == Some(LOCALHOST_WAN_RULE_NAME) && rule.target != ACCEPT {
+                section.patch(FirewallRulePatch {
+                    target: Some(Some(ACCEPT)),
                     ..Default::default()
-                },
-                None::<String>,
-            )?;
-        }
-        if !found_wan_rule {
-            ctx.push(
-                FirewallRule {
-                    name: WAN_RULE_NAME.into(),
-                    src: "lan".into(),
-                    dest: "wan".into(),
-                    target: REJECT,
-                    ..Default::default()
-                },
-                None::<String>,
-            )?;
-        }
-        if !found_localhost_lan_rule {
-            ctx.push(
-                FirewallRule {
-                    name: LOCALHOST_LAN_RULE_NAME.into(),
-                    src: "lan".into(),
-                    dest: "lan".into(),
-                    dest_ip: Some("192.168.1.1".into()),
-                    target: ACCEPT,
-                    ..Default::default()
-                },
-                None::<String>,
-            )?;
-        }
-        if !found_localhost_wan_rule {
-            // TODO: what should this be?
+                })?;
+                updated.push(section.id());
+            }
         }
-        Ok(())
-    })?;
+        let mut changes =
+            ctx.reconcile_patch("rule", rules, |rule| rule.name.clone(), |_| false, target)?;
+        changes.updated.extend(updated);
+        Ok(changes)
+    })
+    .await?;
+    if changes.is_empty() {
+        return Ok(());
+    }
 
     Command::new("/etc/init.d/firewall")
         .arg("reload")
@@ -3,11 +3,14 @@ use color_eyre::eyre::{bail, Context, Error};
 use macaddr::MacAddr;
 use serde::Deserialize;
 use std::collections::{BTreeSet, HashMap};
+use std::fmt::{self, Display};
 use std::net::IpAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 use tokio::process::Command;
 use tokio::signal::unix::{signal, SignalKind};
-use tracing::info;
+use tracing::{info, warn};
+use uciedit::UciSection;
 
 #[derive(Debug, Default)]
 pub struct Connection {
@@ -78,71 +81,102 @@ pub fn set_config(state: &WatchState, config: Config) {
 pub const CONFIG_PATH: &str = "/etc/config/secprof";
 pub const WPA_PASSWORDS_PATH: &str = "/etc/hostapd.wpa_psk";
 
+/// The sections of [`CONFIG_PATH`]. The UI's schema for them in `ui/src/schema` is generated
+/// from this, see `test_ui_schema`.
+#[derive(UciSection)]
+pub enum UciSecprof {
+    Profile {
+        lan_access: Flag,
+        wan_access: Flag,
+        #[uci(split)]
+        lan_whitelist: Vec<String>,
+    },
+    #[uci(ty = "wpapassword")]
+    WpaPassword { password: String, profile: String },
+}
+
 pub fn load_config() -> Result<Config, Error> {
-    use uciedit::{parse_config, UciSection};
-
-    #[derive(UciSection)]
-    #[uci(ty = "profile")]
-    pub struct UciSecProfile {
-        lan_access: u8,
-        wan_access: u8,
-        lan_whitelist: Option<String>,
+    uciedit::parse_config(CONFIG_PATH, read_config)
+        .with_context(|| format!("loading config {CONFIG_PATH}"))
+}
+
+/// A boolean option. Besides OpenWrt's spellings, this takes any number like older configs
+/// did, where everything above `0` is true.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Flag(pub bool);
+
+impl FromStr for Flag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        match uciedit::parse_bool(s) {
+            Ok(flag) => Ok(Flag(flag)),
+            Err(err) => s.parse::<u8>().map(|n| Flag(n > 0)).map_err(|_| err),
+        }
     }
+}
 
-    #[derive(UciSection)]
-    #[uci(ty = "wpapassword")]
-    pub struct UciWpaPassword {
-        password: String,
-        profile: String,
+impl Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.0 { "1" } else { "0" })
     }
+}
 
+/// Reads the profiles and passwords of a config, skipping sections of any other type and
+/// sections that don't read, so that one bad section doesn't take the others down with it.
+fn read_config(ctx: uciedit::Sections) -> Result<Config, Error> {
     let mut config = Config::default();
-    // TODO: use read_sections instead of rewrite_sections (once implemented)
-    parse_config(CONFIG_PATH, |mut ctx| {
-        while ctx.step() {
-            if let Ok(UciSecProfile {
-                lan_access,
-                wan_access,
+    for section in ctx.sections_of::<UciSecprof>() {
+        let read = match section.get() {
+            Ok(read) => read,
+            Err(err) => {
+                warn!("skipping secprof section {}: {err:#}", section.id());
+                continue;
+            }
+        };
+        match read {
+            UciSecprof::Profile {
+                lan_access: Flag(lan_access),
+                wan_access: Flag(wan_access),
                 lan_whitelist,
-            }) = ctx.get()
-            {
-                let Some(name) = ctx.name() else {
+            } => {
+                let Some(name) = section.name() else {
                     bail!("all security profiles must be named")
                 };
                 config.profiles.insert(
                     name.into_owned(),
                     SecProfile {
-                        lan: if lan_access > 0 {
+                        lan: if lan_access {
                             LanAccess::AllDevices
-                        } else if let Some(whitelist) = lan_whitelist {
+                        } else if !lan_whitelist.is_empty() {
+                            // older configs separate profiles with commas
                             LanAccess::OtherProfile(
-                                whitelist.split(',').map(|s| s.to_owned()).collect(),
+                                lan_whitelist
+                                    .iter()
+                                    .flat_map(|s| s.split(','))
+                                    .map(|s| s.to_owned())
+                                    .collect(),
                             )
                         } else {
                             LanAccess::NoDevices
                         },
-                        wan: wan_access > 0,
+                        wan: wan_access,
                     },
                 );
-                continue;
             }
-            if let Ok(UciWpaPassword { password, profile }) = ctx.get() {
-                let Some(name) = ctx.name() else {
+            UciSecprof::WpaPassword { password, profile } => {
+                let Some(name) = section.name() else {
                     bail!("all wpa passwords must be named")
                 };
                 if name.contains(char::is_whitespace) {
                     bail!("keyid {name:?} can not contain whitespace");
                 }
-                let name = name.into_owned();
                 config
                     .keyids
-                    .insert(name.clone(), KeyId { profile, password });
-                continue;
+                    .insert(name.into_owned(), KeyId { profile, password });
             }
         }
-        Ok(())
-    })
-    .with_context(|| format!("loading config {CONFIG_PATH}"))?;
+    }
     Ok(config)
 }
 
@@ -185,3 +219,50 @@ pub async fn maintain_wpa_passwords(mut state: WatchState) -> Result<(), Error>
         state.changed().await;
     }
 }
+
+/// The UI's copies of the schema have to match [`UciSecprof`]. Run the tests with
+/// `UPDATE_SCHEMA=1` to regenerate them.
+#[test]
+fn test_ui_schema() {
+    use std::path::Path;
+    use uciedit::schema::{json_schema, typescript, UciSchema};
+
+    let sections = UciSecprof::schema();
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../ui/src/schema");
+    let files = [
+        ("secprof.schema.json", json_schema("secprof", &sections)),
+        ("secprof.d.ts", typescript("secprof", &sections)),
+    ];
+    for (file, generated) in files {
+        let path = dir.join(file);
+        if std::env::var_os("UPDATE_SCHEMA").is_some() {
+            std::fs::write(&path, generated).unwrap();
+            continue;
+        }
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            current == generated,
+            "{} is out of date, rerun the tests with UPDATE_SCHEMA=1",
+            path.display()
+        );
+    }
+}
+
+#[test]
+fn test_read_config() {
+    let config = "config profile kids\n\toption lan_access 0\n\toption wan_access 1\n\nconfig schedule bedtime\n\toption start 21:00\n\nconfig wpapassword kid1\n\toption password hunter22\n\toption profile kids\n";
+    let config = uciedit::parse_config_string(config, read_config).unwrap();
+    assert!(config.profiles["kids"].wan);
+    assert_eq!(config.keyids["kid1"].profile, "kids");
+
+    // a bad section is skipped, and older configs count up from 1
+    let config = "config profile broken\n\toption wan_access 1\n\nconfig profile legacy\n\toption lan_access 2\n\toption wan_access 0\n\nconfig wpapassword kid1\n\toption password hunter22\n\toption profile legacy\n";
+    let config = uciedit::parse_config_string(config, read_config).unwrap();
+    assert!(!config.profiles.contains_key("broken"));
+    assert!(matches!(
+        config.profiles["legacy"].lan,
+        LanAccess::AllDevices
+    ));
+    assert!(!config.profiles["legacy"].wan);
+    assert_eq!(config.keyids["kid1"].profile, "legacy");
+}
@@ -0,0 +1,953 @@
+//! A work-alike of OpenWrt's `uci` command line tool, built on the delta staging area so that
+//! provisioning scripts can run against a checkout of `/etc/config` instead of a router. The
+//! output of every command matches `uci` byte for byte.
+//!
+//! Two commands `uci` doesn't have compare the config with the stock one in `/rom/etc/config`:
+//! `diff` lists what was customized like `uci changes` would, and `defaults` prints a
+//! `/etc/uci-defaults` script that redoes it on a fresh image.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use uciedit::delta::{Delta, DeltaCmd, Staging};
+use uciedit::overlay::{
+    defaults_script, diff_dirs, packages, PackageDiff, PackageStatus, ROM_CONFDIR,
+};
+use uciedit::query::{anonymous_name, is_valid_name, is_valid_type, SectionSelector, UciPath};
+use uciedit::{format_lines, parse_lines, quote, Error, Line, ParseError, UciValue};
+
+const USAGE: &str = "\
+Usage: uci [<options>] <command> [<arguments>]
+
+Commands:
+\texport     [<config>]
+\timport     [<config>]
+\tchanges    [<config>]
+\tcommit     [<config>]
+\tadd        <config> <section-type>
+\tadd_list   <config>.<section>.<option>=<string>
+\tdel_list   <config>.<section>.<option>=<string>
+\tshow       [<config>[.<section>[.<option>]]]
+\tget        <config>.<section>[.<option>]
+\tset        <config>.<section>[.<option>]=<value>
+\tdelete     <config>[.<section>[[.<option>][=<id>]]]
+\trename     <config>.<section>[.<option>]=<name>
+\trevert     <config>[.<section>[.<option>]]
+\treorder    <config>.<section>=<position>
+\tdiff       [<config>]
+\tdefaults   [<config>]
+
+Options:
+\t-c <path>  set the search path for config files (default: /etc/config)
+\t-d <str>   set the delimiter for list values in uci show
+\t-f <file>  use <file> as input instead of stdin
+\t-m         when importing, merge data into an existing package
+\t-n         name unnamed sections on export (default)
+\t-N         don't name unnamed sections
+\t-P <path>  use <path> for config change files (default: /tmp/.uci)
+\t-t <path>  same as -P
+\t-r <path>  set the stock config files to diff against (default: /rom/etc/config)
+\t-q         quiet mode (don't print error messages)
+\t-s         force strict mode (stop on parser errors, default)
+\t-S         disable strict mode
+\t-X         do not use extended syntax on 'show'
+
+";
+
+/// How a command failed. `uci` prints libuci's error strings, so that is all we print too.
+enum Failure {
+    /// Bad arguments: print the usage and exit with 255.
+    Usage,
+    /// Print `uci: <message>` and exit with 1.
+    Uci(String),
+    /// Exit with 1 without a message, which `uci` does for some malformed arguments.
+    Silent,
+}
+
+const NOT_FOUND: &str = "Entry not found";
+const INVALID: &str = "Invalid argument";
+
+fn not_found() -> Failure {
+    Failure::Uci(NOT_FOUND.into())
+}
+
+fn invalid() -> Failure {
+    Failure::Uci(INVALID.into())
+}
+
+impl From<Error> for Failure {
+    fn from(err: Error) -> Self {
+        if let Some(parse) = err.downcast_ref::<ParseError>() {
+            return Failure::from(parse.clone());
+        }
+        match err.downcast_ref::<io::Error>() {
+            Some(io) if io.kind() == io::ErrorKind::NotFound => not_found(),
+            Some(_) => Failure::Uci("I/O error".into()),
+            None => Failure::Uci(format!("Parse error ({err:#})")),
+        }
+    }
+}
+
+impl From<ParseError> for Failure {
+    fn from(err: ParseError) -> Self {
+        // libuci counts the byte from 0
+        Failure::Uci(format!(
+            "Parse error ({}) at line {}, byte {}",
+            err.kind,
+            err.line,
+            err.column - 1
+        ))
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(err: io::Error) -> Self {
+        Failure::from(Error::from(err))
+    }
+}
+
+struct Cli {
+    staging: Staging,
+    rom: PathBuf,
+    delimiter: String,
+    input: Option<String>,
+    merge: bool,
+    export_names: bool,
+    quiet: bool,
+    extended: bool,
+}
+
+/// A section as `uci` sees it, with staged changes applied.
+struct Section {
+    name: String,
+    ty: String,
+    anonymous: bool,
+    options: Vec<(String, UciValue)>,
+}
+
+/// A `<config>[.<section>[.<option>]][=<value>]` argument.
+struct Arg {
+    package: String,
+    path: Option<UciPath>,
+    value: Option<String>,
+}
+
+impl Arg {
+    fn parse(arg: &str) -> Result<Self, Failure> {
+        let (path, value) = match arg.split_once('=') {
+            Some((path, value)) => (path, Some(value.to_owned())),
+            None => (arg, None),
+        };
+        let (package, path) = match path.split_once('.') {
+            Some((package, path)) => (package, Some(path.parse().map_err(|_| invalid())?)),
+            None => (path, None),
+        };
+        if package.is_empty() || package.contains('/') || package.starts_with('.') {
+            return Err(invalid());
+        }
+        Ok(Arg {
+            package: package.to_owned(),
+            path,
+            value,
+        })
+    }
+
+    fn option(&self) -> Option<&str> {
+        self.path.as_ref()?.option.as_deref()
+    }
+}
+
+impl Cli {
+    fn packages(&self) -> Result<Vec<String>, Failure> {
+        Ok(packages(&self.staging.confdir)?)
+    }
+
+    /// How the committed packages differ from the stock ones, or just `package`.
+    fn overlay(&self, package: Option<&str>) -> Result<Vec<PackageDiff>, Failure> {
+        let mut diffs = diff_dirs(&self.rom, &self.staging.confdir)?;
+        if let Some(package) = package {
+            let exists = |dir: &PathBuf| dir.join(package).is_file();
+            if !exists(&self.rom) && !exists(&self.staging.confdir) {
+                return Err(not_found());
+            }
+            diffs.retain(|diff| diff.package == package);
+        }
+        Ok(diffs)
+    }
+
+    /// Loads a package with its staged changes, and resolves the section `path` refers to.
+    fn load(
+        &self,
+        package: &str,
+        path: Option<&UciPath>,
+    ) -> Result<(Vec<Section>, Option<String>), Failure> {
+        let loaded = self.staging.parse_config_named(package, |ctx, anonymous| {
+            let mut sections = Vec::new();
+            for section in ctx.iter() {
+                let name = section.id();
+                sections.push(Section {
+                    anonymous: anonymous.contains(&name),
+                    name,
+                    ty: section.ty().into_owned(),
+                    options: section.options(),
+                });
+            }
+            let resolved = match path {
+                Some(path) => ctx.resolve(&path.to_string())?,
+                None => None,
+            };
+            Ok((sections, resolved))
+        });
+        Ok(loaded?)
+    }
+
+    /// Loads a package and finds the section an argument names, which must exist.
+    fn section(&self, arg: &Arg) -> Result<(Vec<Section>, usize), Failure> {
+        let (sections, resolved) = self.load(&arg.package, arg.path.as_ref())?;
+        let index = resolved
+            .and_then(|name| sections.iter().position(|s| s.name == name))
+            .ok_or_else(not_found)?;
+        Ok((sections, index))
+    }
+
+    fn stage(
+        &self,
+        arg: &Arg,
+        cmd: DeltaCmd,
+        section: &str,
+        value: Option<&str>,
+    ) -> Result<(), Failure> {
+        let delta = Delta {
+            cmd,
+            section: section.to_owned(),
+            option: arg.option().map(str::to_owned),
+            value: value.map(str::to_owned),
+        };
+        Ok(self.staging.stage(&arg.package, &[delta])?)
+    }
+
+    fn run(&self, command: &str, args: &[String], out: &mut dyn Write) -> Result<(), Failure> {
+        match (command, args) {
+            ("show", []) => {
+                for package in self.packages()? {
+                    self.show(&Arg::parse(&package)?, out)?;
+                }
+                Ok(())
+            }
+            ("show", [arg]) => self.show(&Arg::parse(arg)?, out),
+            ("get", [arg]) => self.get(&Arg::parse(arg)?, out),
+            ("set", [arg]) => self.set(&Arg::parse(arg)?),
+            ("add", [package, ty]) => {
+                Arg::parse(package)?;
+                if !is_valid_type(ty) {
+                    return Err(invalid());
+                }
+                let name = self.staging.add(package, ty)?;
+                writeln!(out, "{name}")?;
+                Ok(())
+            }
+            ("add_list", [arg]) => self.add_list(&Arg::parse(arg)?),
+            ("del_list", [arg]) => self.del_list(&Arg::parse(arg)?),
+            ("delete", [arg]) => self.delete(&Arg::parse(arg)?),
+            ("rename", [arg]) => self.rename(&Arg::parse(arg)?),
+            ("reorder", [arg]) => self.reorder(&Arg::parse(arg)?),
+            ("export", []) => {
+                for package in self.packages()? {
+                    self.export(&package, out)?;
+                }
+                Ok(())
+            }
+            ("export", [package]) => self.export(&Arg::parse(package)?.package, out),
+            ("import", []) => self.import(None),
+            ("import", [package]) => self.import(Some(&Arg::parse(package)?.package)),
+            ("changes", []) => {
+                for package in self.packages()? {
+                    self.changes(&package, out)?;
+                }
+                Ok(())
+            }
+            ("changes", [package]) => self.changes(&Arg::parse(package)?.package, out),
+            ("commit", []) => {
+                for package in self.staging.changed_packages()? {
+                    self.staging.commit(&package)?;
+                }
+                Ok(())
+            }
+            ("commit", [package]) => Ok(self.staging.commit(&Arg::parse(package)?.package)?),
+            ("revert", [arg]) => self.revert(&Arg::parse(arg)?),
+            ("diff", []) => self.diff(None, out),
+            ("diff", [package]) => self.diff(Some(&Arg::parse(package)?.package), out),
+            ("defaults", []) => self.defaults(None, out),
+            ("defaults", [package]) => self.defaults(Some(&Arg::parse(package)?.package), out),
+            _ => Err(Failure::Usage),
+        }
+    }
+
+    fn show(&self, arg: &Arg, out: &mut dyn Write) -> Result<(), Failure> {
+        let Some(path) = &arg.path else {
+            let (sections, _) = self.load(&arg.package, None)?;
+            let mut text = String::new();
+            for (i, section) in sections.iter().enumerate() {
+                let name = match section.anonymous && self.extended {
+                    true => {
+                        let index = sections[..i].iter().filter(|s| s.ty == section.ty).count();
+                        format!("@{}[{index}]", section.ty)
+                    }
+                    false => section.name.clone(),
+                };
+                self.show_section(&mut text, &arg.package, &name, section);
+            }
+            out.write_all(text.as_bytes())?;
+            return Ok(());
+        };
+        let (sections, index) = self.section(arg)?;
+        let section = &sections[index];
+        let mut text = String::new();
+        match &path.option {
+            Some(option) => {
+                let (_, value) = section
+                    .options
+                    .iter()
+                    .find(|(name, _)| name == option)
+                    .ok_or_else(not_found)?;
+                self.show_option(&mut text, &arg.package, &section.name, option, value);
+            }
+            // `uci` prints the real name here, even for `@type[n]` paths
+            None => self.show_section(&mut text, &arg.package, &section.name, section),
+        }
+        out.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    fn show_section(&self, text: &mut String, package: &str, name: &str, section: &Section) {
+        writeln!(text, "{package}.{name}={}", section.ty).unwrap();
+        for (option, value) in &section.options {
+            self.show_option(text, package, name, option, value);
+        }
+    }
+
+    fn show_option(
+        &self,
+        text: &mut String,
+        package: &str,
+        name: &str,
+        option: &str,
+        value: &UciValue,
+    ) {
+        write!(text, "{package}.{name}.{option}=").unwrap();
+        match value {
+            UciValue::Option(value) => text.push_str(&quote(value)),
+            UciValue::List(items) => {
+                let items: Vec<_> = items.iter().map(|item| quote(item)).collect();
+                text.push_str(&items.join(&self.delimiter));
+            }
+        }
+        text.push('\n');
+    }
+
+    fn get(&self, arg: &Arg, out: &mut dyn Write) -> Result<(), Failure> {
+        if arg.value.is_some() {
+            return Err(Failure::Silent);
+        }
+        if arg.path.is_none() {
+            self.load(&arg.package, None)?;
+            return Ok(());
+        }
+        let (sections, index) = self.section(arg)?;
+        let section = &sections[index];
+        let value = match arg.option() {
+            None => section.ty.clone(),
+            Some(option) => {
+                let (_, value) = section
+                    .options
+                    .iter()
+                    .find(|(name, _)| name == option)
+                    .ok_or_else(not_found)?;
+                match value {
+                    UciValue::Option(value) => value.clone(),
+                    UciValue::List(items) => {
+                        let items: Vec<_> = items
+                            .iter()
+                            .map(|item| match item.contains([' ', '\t', '\r', '\n']) {
+                                true => quote(item),
+                                false => item.clone(),
+                            })
+                            .collect();
+                        items.join(&self.delimiter)
+                    }
+                }
+            }
+        };
+        writeln!(out, "{value}")?;
+        Ok(())
+    }
+
+    fn set(&self, arg: &Arg) -> Result<(), Failure> {
+        let (Some(path), Some(value)) = (&arg.path, &arg.value) else {
+            return Err(invalid());
+        };
+        let (sections, resolved) = self.load(&arg.package, Some(path))?;
+        let section = resolved.and_then(|name| sections.into_iter().find(|s| s.name == name));
+        let Some(section) = section else {
+            // only a named section can be created, and only by giving its type
+            return match (&path.section, &path.option) {
+                (_, _) if value.is_empty() => Ok(()),
+                (SectionSelector::Named(name), None)
+                    if is_valid_name(name) && is_valid_type(value) =>
+                {
+                    self.stage(arg, DeltaCmd::Change, name, Some(value))
+                }
+                (SectionSelector::Named(_), _) => Err(invalid()),
+                (SectionSelector::Indexed { .. }, _) => Err(not_found()),
+            };
+        };
+        let Some(option) = &path.option else {
+            if value.is_empty() {
+                return self.stage(arg, DeltaCmd::Remove, &section.name, None);
+            }
+            if !is_valid_type(value) {
+                return Err(invalid());
+            }
+            if section.ty != *value {
+                self.stage(arg, DeltaCmd::Change, &section.name, Some(value))?;
+            }
+            return Ok(());
+        };
+        if !is_valid_name(option) {
+            return Err(invalid());
+        }
+        let current = section.options.iter().find(|(name, _)| name == option);
+        match current {
+            None if value.is_empty() => Ok(()),
+            Some(_) if value.is_empty() => self.stage(arg, DeltaCmd::Remove, &section.name, None),
+            Some((_, UciValue::Option(current))) if current == value => Ok(()),
+            _ => self.stage(arg, DeltaCmd::Change, &section.name, Some(value)),
+        }
+    }
+
+    fn add_list(&self, arg: &Arg) -> Result<(), Failure> {
+        let (Some(option), Some(value)) = (arg.option(), &arg.value) else {
+            return Err(invalid());
+        };
+        if !is_valid_name(option) {
+            return Err(invalid());
+        }
+        let (sections, index) = self.section(arg)?;
+        self.stage(arg, DeltaCmd::ListAdd, &sections[index].name, Some(value))
+    }
+
+    fn del_list(&self, arg: &Arg) -> Result<(), Failure> {
+        let (Some(option), Some(value)) = (arg.option(), &arg.value) else {
+            return Err(invalid());
+        };
+        let (sections, index) = self.section(arg)?;
+        let section = &sections[index];
+        let is_list = section
+            .options
+            .iter()
+            .any(|(name, value)| name == option && matches!(value, UciValue::List(_)));
+        match is_list {
+            true => self.stage(arg, DeltaCmd::ListDel, &section.name, Some(value)),
+            false => Ok(()),
+        }
+    }
+
+    fn delete(&self, arg: &Arg) -> Result<(), Failure> {
+        if arg.path.is_none() {
+            return Err(invalid());
+        }
+        let (sections, index) = self.section(arg)?;
+        let section = &sections[index];
+        let Some(option) = arg.option() else {
+            return self.stage(arg, DeltaCmd::Remove, &section.name, None);
+        };
+        let (_, current) = section
+            .options
+            .iter()
+            .find(|(name, _)| name == option)
+            .ok_or_else(not_found)?;
+        match (&arg.value, current) {
+            (None, _) => self.stage(arg, DeltaCmd::Remove, &section.name, None),
+            (Some(value), UciValue::List(_)) if value.parse::<i32>().is_ok() => {
+                self.stage(arg, DeltaCmd::Remove, &section.name, Some(value))
+            }
+            (Some(_), _) => Err(Failure::Silent),
+        }
+    }
+
+    fn rename(&self, arg: &Arg) -> Result<(), Failure> {
+        let Some(name) = arg.value.as_deref().filter(|name| is_valid_name(name)) else {
+            return Err(invalid());
+        };
+        let (sections, index) = self.section(arg)?;
+        let section = &sections[index];
+        if let Some(option) = arg.option() {
+            if !section.options.iter().any(|(name, _)| name == option) {
+                return Err(not_found());
+            }
+        } else if sections
+            .iter()
+            .any(|s| s.name == name && s.name != section.name)
+        {
+            return Err(Failure::Uci("Duplicate entry".into()));
+        }
+        self.stage(arg, DeltaCmd::Rename, &section.name, Some(name))
+    }
+
+    fn reorder(&self, arg: &Arg) -> Result<(), Failure> {
+        let Some(position) = arg.value.as_deref().and_then(|v| v.parse::<usize>().ok()) else {
+            return Err(invalid());
+        };
+        if arg.option().is_some() {
+            return Err(invalid());
+        }
+        let (sections, index) = self.section(arg)?;
+        self.stage(
+            arg,
+            DeltaCmd::Reorder,
+            &sections[index].name,
+            Some(&position.to_string()),
+        )
+    }
+
+    fn export(&self, package: &str, out: &mut dyn Write) -> Result<(), Failure> {
+        let escape = |s: &str| s.replace('\'', "'\\''");
+        let (sections, _) = self.load(package, None)?;
+        let mut text = format!("package {}\n", escape(package));
+        for section in &sections {
+            write!(text, "\nconfig {}", escape(&section.ty)).unwrap();
+            if !section.anonymous || self.export_names {
+                write!(text, " {}", quote(&section.name)).unwrap();
+            }
+            text.push('\n');
+            for (option, value) in &section.options {
+                match value {
+                    UciValue::Option(value) => {
+                        writeln!(text, "\toption {} {}", escape(option), quote(value)).unwrap();
+                    }
+                    UciValue::List(items) => {
+                        for item in items {
+                            writeln!(text, "\tlist {} {}", escape(option), quote(item)).unwrap();
+                        }
+                    }
+                }
+            }
+        }
+        text.push('\n');
+        out.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Replaces packages with config read from stdin, or with `-m` merges it into them.
+    /// Without a package name, `package` statements in the input say where each part goes.
+    fn import(&self, package: Option<&str>) -> Result<(), Failure> {
+        let text = match &self.input {
+            Some(path) => fs::read_to_string(path)?,
+            None => {
+                let mut text = String::new();
+                io::stdin().read_to_string(&mut text)?;
+                text
+            }
+        };
+        let lines = parse_lines(&text)?;
+        let mut parts: Vec<(String, Vec<&Line>)> = Vec::new();
+        if let Some(package) = package {
+            parts.push((package.to_owned(), Vec::new()));
+        }
+        for line in &lines {
+            match line {
+                Line::Package { name } if package.is_none() => {
+                    parts.push((name.as_str().into_owned(), Vec::new()));
+                }
+                Line::Package { .. } => (),
+                line => match parts.last_mut() {
+                    Some((_, part)) => part.push(line),
+                    None if matches!(line, Line::Empty | Line::Comment { .. }) => (),
+                    None => return Err(Failure::Uci("Parse error (no package defined)".into())),
+                },
+            }
+        }
+        for (package, part) in parts {
+            Arg::parse(&package)?;
+            let path = self.staging.config_path(&package);
+            if self.merge && path.exists() {
+                let committed = uciedit::parse_config(&path, |mut ctx| {
+                    let mut count = 0;
+                    while ctx.step() {
+                        count += 1;
+                    }
+                    Ok(count)
+                })?;
+                let deltas = merge_deltas(&part, committed);
+                uciedit::rewrite_config(&path, |mut ctx| ctx.apply_deltas(&deltas))?;
+            } else {
+                let text = format_lines(part.iter().copied());
+                uciedit::write_config(&path, text.trim_start_matches('\n'))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn changes(&self, package: &str, out: &mut dyn Write) -> Result<(), Failure> {
+        if !self.staging.config_path(package).exists() {
+            return Err(not_found());
+        }
+        let mut text = String::new();
+        write_changes(&mut text, package, &self.staging.changes(package)?);
+        out.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Prints the changes from the stock config like `uci changes`, with `-<config>` for a
+    /// package that was deleted.
+    fn diff(&self, package: Option<&str>, out: &mut dyn Write) -> Result<(), Failure> {
+        let mut text = String::new();
+        for diff in self.overlay(package)? {
+            match diff.status {
+                PackageStatus::Removed => writeln!(text, "-{}", diff.package).unwrap(),
+                _ => write_changes(&mut text, &diff.package, &diff.diff.changes),
+            }
+        }
+        out.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    fn defaults(&self, package: Option<&str>, out: &mut dyn Write) -> Result<(), Failure> {
+        let script = defaults_script(&self.overlay(package)?);
+        out.write_all(script.as_bytes())?;
+        Ok(())
+    }
+
+    fn revert(&self, arg: &Arg) -> Result<(), Failure> {
+        let section = match &arg.path {
+            None => None,
+            Some(path) => match &path.section {
+                SectionSelector::Named(name) => Some(name.clone()),
+                SectionSelector::Indexed { .. } => {
+                    let (sections, index) = self.section(arg)?;
+                    Some(sections[index].name.clone())
+                }
+            },
+        };
+        Ok(self
+            .staging
+            .revert(&arg.package, section.as_deref(), arg.option())?)
+    }
+}
+
+/// Lists changes the way `uci changes` does.
+fn write_changes(text: &mut String, package: &str, deltas: &[Delta]) {
+    for delta in deltas {
+        let (prefix, op) = match delta.cmd {
+            DeltaCmd::Remove => ("-", "="),
+            DeltaCmd::ListAdd => ("", "+="),
+            DeltaCmd::ListDel => ("", "-="),
+            _ => ("", "="),
+        };
+        write!(text, "{prefix}{package}.{}", delta.section).unwrap();
+        if let Some(option) = &delta.option {
+            write!(text, ".{option}").unwrap();
+        }
+        if let Some(value) = &delta.value {
+            write!(text, "{op}{}", quote(value)).unwrap();
+        }
+        text.push('\n');
+    }
+}
+
+/// The changes that merge imported lines into a package, as libuci's parser does with `-m`:
+/// named sections are updated in place, `option` replaces and `list` appends, and anonymous
+/// sections are added after the `committed` ones.
+fn merge_deltas(lines: &[&Line], committed: usize) -> Vec<Delta> {
+    let mut deltas = Vec::new();
+    let mut section = None;
+    let mut added = committed;
+    for line in lines {
+        let (cmd, option, value) = match line {
+            Line::Section { ty, name } => {
+                let ty = ty.as_str().into_owned();
+                let (cmd, name) = match name {
+                    Some(name) => (DeltaCmd::Change, name.as_str().into_owned()),
+                    None => {
+                        added += 1;
+                        (DeltaCmd::Add, anonymous_name(added, &ty))
+                    }
+                };
+                section = Some(name);
+                (cmd, None, ty)
+            }
+            Line::Option { option, value } => (
+                DeltaCmd::Change,
+                Some(option.as_str().into_owned()),
+                value.as_str().into_owned(),
+            ),
+            Line::List { list, item } => (
+                DeltaCmd::ListAdd,
+                Some(list.as_str().into_owned()),
+                item.as_str().into_owned(),
+            ),
+            _ => continue,
+        };
+        let Some(section) = &section else { continue };
+        deltas.push(Delta {
+            cmd,
+            section: section.clone(),
+            option,
+            value: Some(value),
+        });
+    }
+    deltas
+}
+
+/// Parses `uci`'s options, which come before the command.
+fn parse_options(args: &[String]) -> Result<(Cli, usize), Failure> {
+    let mut cli = Cli {
+        staging: Staging::default(),
+        rom: ROM_CONFDIR.into(),
+        delimiter: " ".into(),
+        input: None,
+        merge: false,
+        export_names: true,
+        quiet: false,
+        extended: true,
+    };
+    let mut i = 0;
+    while let Some(arg) = args
+        .get(i)
+        .filter(|arg| arg.starts_with('-') && arg.len() > 1)
+    {
+        i += 1;
+        for (at, flag) in arg.char_indices().skip(1) {
+            let mut value = || -> Result<String, Failure> {
+                let rest = &arg[at + 1..];
+                if !rest.is_empty() {
+                    return Ok(rest.to_owned());
+                }
+                i += 1;
+                args.get(i - 1).cloned().ok_or(Failure::Usage)
+            };
+            match flag {
+                'c' => cli.staging.confdir = value()?.into(),
+                'd' => cli.delimiter = value()?,
+                'f' => cli.input = Some(value()?),
+                'P' | 't' => cli.staging.savedir = value()?.into(),
+                'r' => cli.rom = value()?.into(),
+                'm' => cli.merge = true,
+                'n' => cli.export_names = true,
+                'N' => cli.export_names = false,
+                'q' => cli.quiet = true,
+                's' | 'S' => (),
+                'X' => cli.extended = false,
+                _ => return Err(Failure::Usage),
+            }
+            if matches!(flag, 'c' | 'd' | 'f' | 'P' | 'r' | 't') {
+                break;
+            }
+        }
+    }
+    Ok((cli, i))
+}
+
+fn run(args: &[String], out: &mut dyn Write, err: &mut dyn Write) -> u8 {
+    let mut quiet = false;
+    let result = parse_options(args).and_then(|(cli, i)| {
+        quiet = cli.quiet;
+        match args.get(i) {
+            Some(command) => cli.run(command, &args[i + 1..], out),
+            None => Err(Failure::Usage),
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(Failure::Usage) => {
+            let _ = err.write_all(USAGE.as_bytes());
+            255
+        }
+        Err(Failure::Uci(message)) => {
+            if !quiet {
+                let _ = writeln!(err, "uci: {message}");
+            }
+            1
+        }
+        Err(Failure::Silent) => 1,
+    }
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let code = run(&args, &mut io::stdout().lock(), &mut io::stderr().lock());
+    ExitCode::from(code)
+}
+
+#[cfg(test)]
+fn uci(confdir: &std::path::Path, args: &[&str]) -> (u8, String, String) {
+    let mut full = vec![
+        "-c".to_owned(),
+        confdir.join("config").display().to_string(),
+        "-P".to_owned(),
+        confdir.join("save").display().to_string(),
+    ];
+    full.extend(args.iter().map(|arg| arg.to_string()));
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    let code = run(&full, &mut out, &mut err);
+    (
+        code,
+        String::from_utf8(out).unwrap(),
+        String::from_utf8(err).unwrap(),
+    )
+}
+
+#[test]
+fn test_uci_commands() {
+    let dir = std::env::temp_dir().join(format!("uciedit-{}-cli", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("config")).unwrap();
+    fs::write(
+        dir.join("config/firewall"),
+        "config defaults\n\toption input 'REJECT'\n\nconfig zone 'lan'\n\toption name 'lan'\n\tlist network 'lan'\n\n# ping\nconfig rule\n\toption name 'Allow-Ping'\n",
+    )
+    .unwrap();
+    let ok = |out: &str| (0, out.to_owned(), String::new());
+
+    assert_eq!(
+        uci(&dir, &["show", "firewall"]),
+        ok("firewall.@defaults[0]=defaults\n\
+            firewall.@defaults[0].input='REJECT'\n\
+            firewall.lan=zone\n\
+            firewall.lan.name='lan'\n\
+            firewall.lan.network='lan'\n\
+            firewall.@rule[0]=rule\n\
+            firewall.@rule[0].name='Allow-Ping'\n")
+    );
+    assert_eq!(uci(&dir, &["add", "firewall", "rule"]), ok("cfg0492bd\n"));
+    assert_eq!(uci(&dir, &["set", "firewall.@rule[-1].name=It's"]), ok(""));
+    assert_eq!(uci(&dir, &["set", "firewall.lan.name=lan"]), ok(""));
+    assert_eq!(
+        uci(&dir, &["add_list", "firewall.lan.network=guest"]),
+        ok("")
+    );
+    assert_eq!(
+        uci(&dir, &["delete", "firewall.@defaults[0].input"]),
+        ok("")
+    );
+    assert_eq!(
+        uci(&dir, &["changes"]),
+        ok("firewall.cfg0492bd='rule'\n\
+            firewall.cfg0492bd.name='It'\\''s'\n\
+            firewall.lan.network+='guest'\n\
+            -firewall.cfg01e63d.input\n")
+    );
+    assert_eq!(
+        uci(&dir, &["-d", ",", "get", "firewall.lan.network"]),
+        ok("lan,guest\n")
+    );
+    assert_eq!(
+        uci(&dir, &["-X", "show", "firewall.@rule[0]"]),
+        ok("firewall.cfg0392bd=rule\nfirewall.cfg0392bd.name='Allow-Ping'\n")
+    );
+    assert_eq!(
+        uci(&dir, &["get", "firewall.lan.nosuch"]),
+        (1, String::new(), "uci: Entry not found\n".into())
+    );
+    assert_eq!(
+        uci(&dir, &["-q", "get", "firewall.nosuch"]),
+        (1, String::new(), String::new())
+    );
+    assert_eq!(uci(&dir, &["bogus"]).0, 255);
+
+    assert_eq!(uci(&dir, &["commit", "firewall"]), ok(""));
+    assert_eq!(uci(&dir, &["changes", "firewall"]), ok(""));
+    assert_eq!(
+        uci(&dir, &["export", "firewall"]),
+        ok("package firewall\n\
+            \n\
+            config defaults 'cfg01e63d'\n\
+            \n\
+            config zone 'lan'\n\
+            \toption name 'lan'\n\
+            \tlist network 'lan'\n\
+            \tlist network 'guest'\n\
+            \n\
+            config rule 'cfg0392bd'\n\
+            \toption name 'Allow-Ping'\n\
+            \n\
+            config rule 'cfg0492bd'\n\
+            \toption name 'It'\\''s'\n\
+            \n")
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("config/firewall")).unwrap(),
+        "config defaults\n\nconfig zone 'lan'\n\toption name 'lan'\n\tlist network 'lan'\n\tlist network guest\n\n# ping\nconfig rule\n\toption name 'Allow-Ping'\n\nconfig rule\n\toption name 'It'\\''s'\n"
+    );
+
+    let rom = dir.join("rom");
+    fs::create_dir_all(&rom).unwrap();
+    fs::write(
+        rom.join("firewall"),
+        "config defaults\n\tlist network wan\n\nconfig zone lan\n\toption name lan\n\tlist network lan\n\nconfig rule\n\toption name 'Allow-Ping'\n",
+    )
+    .unwrap();
+    let rom = rom.display().to_string();
+    assert_eq!(
+        uci(&dir, &["-r", &rom, "diff"]),
+        ok("-firewall.cfg01e63d.network\n\
+            firewall.lan.network+='guest'\n\
+            firewall.cfg0492bd='rule'\n\
+            firewall.cfg0492bd.name='It'\\''s'\n")
+    );
+    assert_eq!(
+        uci(&dir, &["-r", &rom, "defaults", "firewall"]).1,
+        "#!/bin/sh\n# Generated by uciedit from the changes to the stock config.\n\n\
+         uci -q batch <<'EOF'\n\
+         delete firewall.cfg01e63d.network\n\
+         add_list firewall.lan.network='guest'\n\
+         add firewall rule\n\
+         set firewall.@rule[1].name='It'\\''s'\n\
+         commit firewall\n\
+         EOF\n\
+         \n\
+         exit 0\n"
+    );
+    assert_eq!(uci(&dir, &["-r", &rom, "diff", "nosuch"]).0, 1);
+
+    fs::write(
+        dir.join("import"),
+        "package system\n\nconfig system\n\toption hostname ap1\n",
+    )
+    .unwrap();
+    assert_eq!(
+        uci(
+            &dir,
+            &["-f", &dir.join("import").display().to_string(), "import"]
+        ),
+        ok("")
+    );
+    assert_eq!(
+        fs::read_to_string(dir.join("config/system")).unwrap(),
+        "config system\n\toption hostname ap1\n"
+    );
+    // written through a temporary file that is renamed into place
+    let leftovers = fs::read_dir(dir.join("config")).unwrap().filter(|entry| {
+        entry
+            .as_ref()
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with('.')
+    });
+    assert_eq!(leftovers.count(), 0);
+
+    // list items with spaces come out quoted so that a shell reads them back
+    assert_eq!(
+        uci(&dir, &["add_list", "firewall.lan.network=it's here"]),
+        ok("")
+    );
+    assert_eq!(
+        uci(&dir, &["get", "firewall.lan.network"]),
+        ok("lan guest 'it'\\''s here'\n")
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,657 @@
+//! The libuci staging area. `uci set` and LuCI don't edit `/etc/config/<package>` directly:
+//! they append their changes to a delta file in `/tmp/.uci/<package>`, and `uci commit` applies
+//! those on top of the config file. Reading the deltas lets us see the config the way LuCI
+//! does, and staging or committing through them keeps us from racing its apply workflow.
+
+use crate::document::OwnedLine;
+use crate::query::{
+    anonymous_id, anonymous_name, find_section, is_named, is_valid_type, section_body,
+    SectionSelector, UciPath,
+};
+use crate::{
+    bail, error, lex_words, quote, rewrite_config, Arena, Error, Line, Lines, Sections,
+    SectionsMut, Token, UciDocument,
+};
+use eyre::Context;
+use fd_lock_rs::{FdLock, LockType};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Seek, Write};
+use std::path::PathBuf;
+
+/// Where libuci keeps committed config files.
+pub const CONFDIR: &str = "/etc/config";
+/// Where libuci stages uncommitted changes.
+pub const SAVEDIR: &str = "/tmp/.uci";
+
+/// The kinds of change libuci records, named after its `UCI_CMD_*` constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeltaCmd {
+    /// `+`: add an anonymous section, the value is its type
+    Add,
+    /// `-`: remove a section, an option, or with an index as the value a list item
+    Remove,
+    /// no prefix: set an option, or a section's type
+    Change,
+    /// `@`: rename a section or option, the value is the new name
+    Rename,
+    /// `^`: move a section, the value is its new index
+    Reorder,
+    /// `|`: append a list item
+    ListAdd,
+    /// `~`: remove matching list items
+    ListDel,
+}
+
+impl DeltaCmd {
+    fn prefix(self) -> &'static str {
+        match self {
+            DeltaCmd::Add => "+",
+            DeltaCmd::Remove => "-",
+            DeltaCmd::Change => "",
+            DeltaCmd::Rename => "@",
+            DeltaCmd::Reorder => "^",
+            DeltaCmd::ListAdd => "|",
+            DeltaCmd::ListDel => "~",
+        }
+    }
+
+    /// What LuCI's `uci.changes()` calls the command, such as `list-add`.
+    pub fn luci_name(self) -> &'static str {
+        match self {
+            DeltaCmd::Add => "add",
+            DeltaCmd::Remove => "remove",
+            DeltaCmd::Change => "set",
+            DeltaCmd::Rename => "rename",
+            DeltaCmd::Reorder => "order",
+            DeltaCmd::ListAdd => "list-add",
+            DeltaCmd::ListDel => "list-del",
+        }
+    }
+}
+
+/// One staged change, as in a line of a delta file or an entry of `uci changes`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Delta {
+    pub cmd: DeltaCmd,
+    /// A section name, or the `cfgXXXXXX` name of an anonymous section.
+    pub section: String,
+    pub option: Option<String>,
+    pub value: Option<String>,
+}
+
+impl Delta {
+    /// Parses a delta file line such as `|firewall.cfg0592bd.proto='udp'`. The package named
+    /// in the line must be `package`.
+    pub fn parse_line(line: &str, package: &str) -> Result<Self, Error> {
+        let (cmd, rest) = match line.as_bytes().first() {
+            Some(b'+') => (DeltaCmd::Add, &line[1..]),
+            Some(b'-') => (DeltaCmd::Remove, &line[1..]),
+            Some(b'@') => (DeltaCmd::Rename, &line[1..]),
+            Some(b'^') => (DeltaCmd::Reorder, &line[1..]),
+            Some(b'|') => (DeltaCmd::ListAdd, &line[1..]),
+            Some(b'~') => (DeltaCmd::ListDel, &line[1..]),
+            _ => (DeltaCmd::Change, line),
+        };
+        // the whole `package.section.option='value'` is one token as far as the lexer goes
+        let arg = Token::parse(rest.trim_end())?.as_str();
+        let (path, value) = match arg.split_once('=') {
+            Some((path, value)) => (path, Some(value.to_owned())),
+            None => (&*arg, None),
+        };
+        let mut parts = path.splitn(3, '.');
+        if parts.next() != Some(package) {
+            bail!("delta {line:?} is not for package {package:?}");
+        }
+        let section = match parts.next() {
+            Some(section) if !section.is_empty() => section.to_owned(),
+            _ => bail!("delta {line:?} has no section"),
+        };
+        let option = parts.next().map(str::to_owned);
+        if value.is_none() && cmd != DeltaCmd::Remove {
+            bail!("delta {line:?} has no value");
+        }
+        Ok(Delta {
+            cmd,
+            section,
+            option,
+            value,
+        })
+    }
+
+    /// Formats the change as a delta file line, the same way libuci saves it.
+    pub fn to_line(&self, package: &str) -> String {
+        let mut line = format!("{}{package}.{}", self.cmd.prefix(), self.section);
+        if let Some(option) = &self.option {
+            line.push('.');
+            line.push_str(option);
+        }
+        if let Some(value) = &self.value {
+            line.push('=');
+            line.push_str(&quote(value));
+        }
+        line
+    }
+
+    fn path(&self) -> String {
+        match &self.option {
+            Some(option) => format!("{}.{option}", self.section),
+            None => self.section.clone(),
+        }
+    }
+}
+
+/// Parses the contents of a delta file for `package`. Each change is a single word, which
+/// like in a config may be a quoted value that spans lines.
+pub fn parse_deltas(text: &str, package: &str) -> Result<Vec<Delta>, Error> {
+    lex_words(text)?
+        .into_iter()
+        .map(|word| {
+            let start = word.raw.as_ptr() as usize - text.as_ptr() as usize;
+            let line = text[..start].matches('\n').count() + 1;
+            Delta::parse_line(word.raw, package).with_context(|| format!("delta line {line}"))
+        })
+        .collect()
+}
+
+impl SectionsMut<'_, '_> {
+    /// Applies staged changes the way `uci commit` does. Like libuci, a change that no longer
+    /// applies, say to a section that has since been deleted, is skipped.
+    ///
+    /// Anonymous sections keep the names they had before any change was applied, even when
+    /// sections are added, removed or moved around them, and sections added with
+    /// [`DeltaCmd::Add`] end up anonymous. Returns the number of sections created.
+    pub fn apply_deltas(&mut self, deltas: &[Delta]) -> Result<usize, Error> {
+        let (anonymous, created) = self.apply_named(deltas);
+        unname(self.lines, |name| anonymous.contains(name));
+        Ok(created)
+    }
+
+    /// Applies deltas leaving every anonymous section named with its libuci name. Returns those
+    /// names and the number of sections created.
+    fn apply_named(&mut self, deltas: &[Delta]) -> (HashSet<String>, usize) {
+        self.rewind();
+        let mut anonymous = name_anonymous(self.lines, self.arena);
+        let mut created = 0;
+        for delta in deltas {
+            let exists =
+                find_section(self.lines, &SectionSelector::Named(delta.section.clone())).is_some();
+            let value = delta.value.as_deref().unwrap_or_default();
+            let applied = match (delta.cmd, &delta.option) {
+                (DeltaCmd::Add, None) => self.set_path(&delta.path(), value).map(|_| {
+                    anonymous.insert(delta.section.clone());
+                }),
+                (DeltaCmd::Add, Some(_)) => Err(error!("only sections can be added")),
+                (DeltaCmd::Change, _) => self.set_path(&delta.path(), value),
+                (DeltaCmd::Remove, Some(_)) if delta.value.is_some() => {
+                    self.delete_list_index(&delta.path(), value)
+                }
+                (DeltaCmd::Remove, _) => self.delete_path(&delta.path()).map(drop),
+                (DeltaCmd::Rename, _) => self.rename_path(&delta.path(), value),
+                (DeltaCmd::Reorder, _) => value
+                    .parse()
+                    .map_err(Error::from)
+                    .and_then(|index| self.reorder_path(&delta.path(), index)),
+                (DeltaCmd::ListAdd, _) => self.add_list_path(&delta.path(), value),
+                (DeltaCmd::ListDel, _) => self.del_list_path(&delta.path(), value).map(drop),
+            };
+            if applied.is_ok() && delta.option.is_none() && !exists {
+                created += 1;
+            }
+        }
+        (anonymous, created)
+    }
+
+    /// `uci delete` with a value: removes the `index`th item of a list, or the whole option if
+    /// it is a plain option.
+    fn delete_list_index(&mut self, path: &str, index: &str) -> Result<(), Error> {
+        self.rewind();
+        let parsed: UciPath = path.parse()?;
+        let index: isize = index.trim().parse()?;
+        let (Some(section), Some(option)) =
+            (find_section(self.lines, &parsed.section), &parsed.option)
+        else {
+            bail!("entry not found: {path}")
+        };
+        let named: Vec<usize> = section_body(self.lines, section)
+            .filter(|&i| is_named(&self.lines[i], option))
+            .collect();
+        // an option replaces everything before it
+        let first = named
+            .iter()
+            .rposition(|&i| matches!(self.lines[i], Line::Option { .. }))
+            .unwrap_or(0);
+        let items = &named[first..];
+        if let [i] = items {
+            if matches!(self.lines[*i], Line::Option { .. }) {
+                self.delete_path(path)?;
+                return Ok(());
+            }
+        }
+        let Some(&removed) = usize::try_from(index).ok().and_then(|n| items.get(n)) else {
+            return Ok(());
+        };
+        self.lines[removed] = Line::Skip;
+        // a single option line left over still holds a one item list
+        if let [i, _] = items {
+            if let Line::Option { option, value } = self.lines[*i] {
+                if *i != removed {
+                    self.lines[*i] = Line::List {
+                        list: option,
+                        item: value,
+                    };
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Gives every anonymous section its libuci name, so that deltas keep finding them after
+/// other sections move. Returns the names handed out.
+fn name_anonymous<'a>(lines: &mut Lines<'a>, arena: &'a Arena) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for i in 0..lines.len() {
+        if let Line::Section { name: None, .. } = &lines[i] {
+            let id = anonymous_id(lines, i);
+            if let Line::Section { name, .. } = &mut lines[i] {
+                *name = Some(Token::from_string(id.clone(), arena));
+            }
+            names.insert(id);
+        }
+    }
+    names
+}
+
+fn unname(lines: &mut Lines, anonymous: impl Fn(&str) -> bool) {
+    for line in lines {
+        if let Line::Section { name, .. } = line {
+            if name.is_some_and(|n| anonymous(&n.as_str())) {
+                *name = None;
+            }
+        }
+    }
+}
+
+/// A config directory together with the directory its changes are staged in.
+#[derive(Clone, Debug)]
+pub struct Staging {
+    pub confdir: PathBuf,
+    pub savedir: PathBuf,
+}
+
+impl Default for Staging {
+    fn default() -> Self {
+        Staging {
+            confdir: CONFDIR.into(),
+            savedir: SAVEDIR.into(),
+        }
+    }
+}
+
+impl Staging {
+    pub fn new(confdir: impl Into<PathBuf>, savedir: impl Into<PathBuf>) -> Self {
+        Staging {
+            confdir: confdir.into(),
+            savedir: savedir.into(),
+        }
+    }
+
+    pub fn config_path(&self, package: &str) -> PathBuf {
+        self.confdir.join(package)
+    }
+
+    pub fn delta_path(&self, package: &str) -> PathBuf {
+        self.savedir.join(package)
+    }
+
+    /// The staged changes of a package, in the order they were made, like `uci changes`.
+    pub fn changes(&self, package: &str) -> Result<Vec<Delta>, Error> {
+        let path = self.delta_path(package);
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                parse_deltas(&text, package).with_context(|| format!("reading {}", path.display()))
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// The packages that have staged changes.
+    pub fn changed_packages(&self) -> Result<Vec<String>, Error> {
+        let entries = match fs::read_dir(&self.savedir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut packages = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let Ok(package) = entry.file_name().into_string() else {
+                continue;
+            };
+            if !package.starts_with('.') && !self.changes(&package)?.is_empty() {
+                packages.push(package);
+            }
+        }
+        packages.sort();
+        Ok(packages)
+    }
+
+    /// Reads a package with its staged changes applied, the way `uci show` and LuCI see it.
+    pub fn parse_config<V>(
+        &self,
+        package: &str,
+        with: impl FnOnce(Sections) -> Result<V, Error>,
+    ) -> Result<V, Error> {
+        let (text, deltas) = self.read_staged(package)?;
+        let mut doc = UciDocument::parse(&text)?;
+        doc.edit(|mut ctx| ctx.apply_deltas(&deltas))?;
+        doc.read(with)
+    }
+
+    /// Like [`parse_config`](Self::parse_config), but anonymous sections keep the `cfgXXXXXX`
+    /// names libuci gave them when it loaded the package, which can differ from their current
+    /// [`id`](Sections::id) once changes added, removed or moved sections. These are the names
+    /// `uci show -X` prints and deltas refer to. The set holds the names of anonymous sections.
+    pub fn parse_config_named<V>(
+        &self,
+        package: &str,
+        with: impl FnOnce(Sections, &HashSet<String>) -> Result<V, Error>,
+    ) -> Result<V, Error> {
+        let path = self.config_path(package);
+        let text =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        let deltas = self.changes(package)?;
+        let mut doc = UciDocument::parse(&text)?;
+        let (anonymous, _) = doc.edit(|mut ctx| Ok(ctx.apply_named(&deltas)))?;
+        doc.read(|ctx| with(ctx, &anonymous))
+    }
+
+    /// Appends changes to the package's delta file, like `uci set` without `uci commit`.
+    pub fn stage(&self, package: &str, deltas: &[Delta]) -> Result<(), Error> {
+        let mut locked = self.lock_deltas(package)?;
+        locked.seek(std::io::SeekFrom::End(0))?;
+        let mut text = String::new();
+        for delta in deltas {
+            text.push_str(&delta.to_line(package));
+            text.push('\n');
+        }
+        locked.write_all(text.as_bytes())?;
+        Ok(())
+    }
+
+    /// Stages a new anonymous section of type `ty`, like `uci add`, and returns its name.
+    pub fn add(&self, package: &str, ty: &str) -> Result<String, Error> {
+        if !is_valid_type(ty) {
+            bail!("invalid section type {ty:?}");
+        }
+        let mut locked = self.lock_deltas(package)?;
+        let mut text = String::new();
+        locked.read_to_string(&mut text)?;
+        let deltas = parse_deltas(&text, package)?;
+
+        let path = self.config_path(package);
+        let config =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        let mut doc = UciDocument::parse(&config)?;
+        let committed = doc
+            .lines()
+            .iter()
+            .filter(|line| matches!(line, OwnedLine::Section { .. }))
+            .count();
+        let created = doc.edit(|mut ctx| ctx.apply_deltas(&deltas))?;
+        // libuci numbers sections by how many it has allocated, counting the committed
+        // ones and every one created by a change since
+        let name = anonymous_name(committed + created + 1, ty);
+
+        let delta = Delta {
+            cmd: DeltaCmd::Add,
+            section: name.clone(),
+            option: None,
+            value: Some(ty.to_owned()),
+        };
+        locked.seek(std::io::SeekFrom::End(0))?;
+        writeln!(locked, "{}", delta.to_line(package))?;
+        Ok(name)
+    }
+
+    /// Applies the staged changes to the config file and clears them, like `uci commit`.
+    pub fn commit(&self, package: &str) -> Result<(), Error> {
+        let mut locked = self.lock_deltas(package)?;
+        let mut text = String::new();
+        locked.read_to_string(&mut text)?;
+        let deltas = parse_deltas(&text, package)?;
+        if deltas.is_empty() {
+            return Ok(());
+        }
+        rewrite_config(self.config_path(package), |mut ctx| {
+            ctx.apply_deltas(&deltas)
+        })?;
+        locked.set_len(0)?;
+        Ok(())
+    }
+
+    /// Drops staged changes, like `uci revert`: all of a package's changes, those to one
+    /// section (including its options), or those to a single option.
+    pub fn revert(
+        &self,
+        package: &str,
+        section: Option<&str>,
+        option: Option<&str>,
+    ) -> Result<(), Error> {
+        let mut locked = self.lock_deltas(package)?;
+        let mut text = String::new();
+        locked.read_to_string(&mut text)?;
+        let mut kept = String::new();
+        if let Some(section) = section {
+            for delta in parse_deltas(&text, package)? {
+                let matches = delta.section == section
+                    && (option.is_none() || delta.option.as_deref() == option);
+                if !matches {
+                    kept.push_str(&delta.to_line(package));
+                    kept.push('\n');
+                }
+            }
+        }
+        locked.set_len(0)?;
+        locked.seek(std::io::SeekFrom::Start(0))?;
+        locked.write_all(kept.as_bytes())?;
+        Ok(())
+    }
+
+    fn read_staged(&self, package: &str) -> Result<(String, Vec<Delta>), Error> {
+        let path = self.config_path(package);
+        let text =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        Ok((text, self.changes(package)?))
+    }
+
+    fn lock_deltas(&self, package: &str) -> Result<FdLock<File>, Error> {
+        use std::os::unix::fs::DirBuilderExt;
+
+        if !self.savedir.exists() {
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&self.savedir)?;
+        }
+        let path = self.delta_path(package);
+        let file = File::options()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("opening {}", path.display()))?;
+        Ok(FdLock::lock(file, LockType::Exclusive, true)?)
+    }
+}
+
+#[cfg(test)]
+fn temp_staging(name: &str, firewall: &str) -> Staging {
+    let dir = std::env::temp_dir().join(format!("uciedit-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("config")).unwrap();
+    fs::write(dir.join("config/firewall"), firewall).unwrap();
+    Staging::new(dir.join("config"), dir.join("save"))
+}
+
+#[test]
+fn test_delta_lines() {
+    let text = "firewall.cfg0592bd.name='Allow '\\''this'\\'''\n\
+                +firewall.cfg0a92bd='rule'\n\
+                -firewall.cfg0692bd\n\
+                -firewall.cfg0792bd.src\n\
+                |firewall.lan.network='guest'\n\
+                ~firewall.lan.network='lan'\n\
+                @firewall.cfg0892bd='myrule'\n\
+                ^firewall.myrule='0'\n";
+    let deltas = parse_deltas(text, "firewall").unwrap();
+    assert_eq!(
+        deltas[0],
+        Delta {
+            cmd: DeltaCmd::Change,
+            section: "cfg0592bd".into(),
+            option: Some("name".into()),
+            value: Some("Allow 'this'".into()),
+        }
+    );
+    assert_eq!(deltas[2].cmd, DeltaCmd::Remove);
+    assert_eq!(deltas[2].value, None);
+    let written: String = deltas
+        .iter()
+        .map(|d| d.to_line("firewall") + "\n")
+        .collect();
+    assert_eq!(written, text);
+
+    // libuci continues a quoted value on the next line
+    let text = "firewall.cfg0592bd.description='first\nsecond'\n-firewall.cfg0692bd\n";
+    let deltas = parse_deltas(text, "firewall").unwrap();
+    assert_eq!(deltas[0].value.as_deref(), Some("first\nsecond"));
+    assert_eq!(deltas[1].cmd, DeltaCmd::Remove);
+    let written: String = deltas
+        .iter()
+        .map(|d| d.to_line("firewall") + "\n")
+        .collect();
+    assert_eq!(written, text);
+
+    assert!(parse_deltas("network.lan.proto='static'\n", "firewall").is_err());
+    assert!(parse_deltas("|firewall.lan.network\n", "firewall").is_err());
+}
+
+#[test]
+fn test_stage_commit_revert() {
+    let staging = temp_staging(
+        "staging",
+        "config defaults\n\toption input REJECT\n\nconfig rule\n\toption name one\n\nconfig rule\n\toption name two\n",
+    );
+
+    let rule = staging.add("firewall", "rule").unwrap();
+    assert_eq!(rule, "cfg0492bd");
+    staging
+        .stage(
+            "firewall",
+            &[
+                Delta {
+                    cmd: DeltaCmd::Change,
+                    section: rule.clone(),
+                    option: Some("name".into()),
+                    value: Some("three".into()),
+                },
+                Delta {
+                    cmd: DeltaCmd::Reorder,
+                    section: "cfg0392bd".into(),
+                    option: None,
+                    value: Some("0".into()),
+                },
+                // still refers to the second rule even though it moved
+                Delta {
+                    cmd: DeltaCmd::ListAdd,
+                    section: "cfg0392bd".into(),
+                    option: Some("proto".into()),
+                    value: Some("tcp".into()),
+                },
+                Delta {
+                    cmd: DeltaCmd::Remove,
+                    section: "cfg0292bd".into(),
+                    option: None,
+                    value: None,
+                },
+            ],
+        )
+        .unwrap();
+    assert_eq!(staging.changed_packages().unwrap(), ["firewall"]);
+    assert_eq!(staging.changes("firewall").unwrap().len(), 5);
+    assert_eq!(staging.add("firewall", "rule").unwrap(), "cfg0592bd");
+    staging.revert("firewall", Some("cfg0592bd"), None).unwrap();
+
+    let names = staging
+        .parse_config("firewall", |mut ctx| {
+            let mut names = Vec::new();
+            while ctx.step() {
+                assert_eq!(ctx.name()?, None);
+                names.push(ctx.get_path(&format!("{}.name", ctx.id()?))?);
+            }
+            Ok(names)
+        })
+        .unwrap();
+    let opt = |s: &str| Some(crate::UciValue::Option(s.into()));
+    assert_eq!(names, [opt("two"), None, opt("three")]);
+    // the committed file is untouched until commit
+    assert!(!fs::read_to_string(staging.config_path("firewall"))
+        .unwrap()
+        .contains("three"));
+
+    staging.commit("firewall").unwrap();
+    assert!(staging.changes("firewall").unwrap().is_empty());
+    assert_eq!(
+        fs::read_to_string(staging.config_path("firewall")).unwrap(),
+        "config rule\n\toption name two\n\tlist proto tcp\n\nconfig defaults\n\toption input REJECT\n\nconfig rule\n\toption name three\n"
+    );
+
+    staging
+        .stage(
+            "firewall",
+            &[Delta {
+                cmd: DeltaCmd::Change,
+                section: "cfg0192bd".into(),
+                option: Some("name".into()),
+                value: Some("changed".into()),
+            }],
+        )
+        .unwrap();
+    staging.revert("firewall", None, None).unwrap();
+    assert!(staging.changes("firewall").unwrap().is_empty());
+
+    fs::remove_dir_all(staging.confdir.parent().unwrap()).unwrap();
+}
+
+#[test]
+fn test_delete_list_index() {
+    let remove = |path: &str, index: &str| {
+        let (section, option) = path.split_once('.').unwrap();
+        Delta {
+            cmd: DeltaCmd::Remove,
+            section: section.into(),
+            option: Some(option.into()),
+            value: Some(index.into()),
+        }
+    };
+    let config = "config zone lan\n\toption network 'lan'\n\tlist network 'guest'\n\tlist network 'iot'\n\toption name 'lan'\n";
+    let edited = crate::rewrite_config_string(config.into(), |mut ctx| {
+        ctx.apply_deltas(&[
+            remove("lan.network", "2"),
+            remove("lan.network", "1"),
+            remove("lan.network", "5"),
+            remove("lan.name", "0"),
+        ])?;
+        assert_eq!(
+            ctx.get_path("lan.network")?,
+            Some(crate::UciValue::List(vec!["lan".into()]))
+        );
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(edited, "config zone lan\n\tlist network 'lan'\n");
+}
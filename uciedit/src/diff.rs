@@ -0,0 +1,449 @@
+//! What changed between two versions of a config, as the changes libuci would stage to get from
+//! one to the other. Only what `uci show` would print counts: comments, blank lines and quoting
+//! are ignored. Anonymous sections have no name to pair them up by, so they are matched by
+//! their type and options instead.
+//!
+//! The [`Delta`]s of a [`Diff`] apply with [`apply_deltas`](crate::SectionsMut::apply_deltas),
+//! can be staged like a LuCI edit, or replayed on another device with [`Diff::batch`].
+
+use crate::__private::fold_value;
+use crate::delta::{Delta, DeltaCmd};
+use crate::query::anonymous_name;
+use crate::{quote, Error, Sections, UciDocument, UciValue};
+use std::cmp::Reverse;
+use std::collections::HashSet;
+use std::fmt::Write;
+
+/// The changes that turn one config into another, in the order they apply: removed sections,
+/// renames, edits of the sections both have, added sections, and last the moves that put every
+/// section in its new place.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diff {
+    pub changes: Vec<Delta>,
+    /// The ids and types of the old sections, for [`batch`](Self::batch) to follow along.
+    old: Vec<(String, String)>,
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The changes as a script for `uci batch`, for a device whose `package` is still the old
+    /// config. The sections it adds are anonymous there and get whatever `cfgXXXXXX` name libuci
+    /// picks, so they are referred to as `@type[n]`.
+    pub fn batch(&self, package: &str) -> String {
+        // the sections as the script goes, and whether it added them
+        let mut sections: Vec<(String, String, bool)> = self
+            .old
+            .iter()
+            .map(|(id, ty)| (id.clone(), ty.clone(), false))
+            .collect();
+        let mut out = String::new();
+        for change in &self.changes {
+            let found = sections.iter().position(|(id, ..)| *id == change.section);
+            let mut path = format!("{package}.");
+            match found {
+                Some(i) if sections[i].2 => {
+                    let ty = &sections[i].1;
+                    let n = sections[..i].iter().filter(|s| s.1 == *ty).count();
+                    let _ = write!(path, "@{ty}[{n}]");
+                }
+                _ => path.push_str(&change.section),
+            }
+            if let Some(option) = &change.option {
+                path.push('.');
+                path.push_str(option);
+            }
+            let section = found.filter(|_| change.option.is_none());
+            let value = change.value.as_deref().unwrap_or_default();
+            let _ = match change.cmd {
+                DeltaCmd::Add => {
+                    sections.push((change.section.clone(), value.to_owned(), true));
+                    writeln!(out, "add {package} {value}")
+                }
+                DeltaCmd::Change if change.option.is_none() => {
+                    match section {
+                        Some(i) => sections[i].1 = value.to_owned(),
+                        None => sections.push((change.section.clone(), value.to_owned(), false)),
+                    }
+                    writeln!(out, "set {path}={value}")
+                }
+                DeltaCmd::Change => writeln!(out, "set {path}={}", quote(value)),
+                DeltaCmd::Remove => {
+                    if let Some(i) = section {
+                        sections.remove(i);
+                    }
+                    match &change.value {
+                        Some(index) => writeln!(out, "delete {path}={index}"),
+                        None => writeln!(out, "delete {path}"),
+                    }
+                }
+                DeltaCmd::Rename => {
+                    if let Some(i) = section {
+                        sections[i] = (value.to_owned(), sections[i].1.clone(), false);
+                    }
+                    writeln!(out, "rename {path}={value}")
+                }
+                DeltaCmd::Reorder => {
+                    if let (Some(i), Ok(index)) = (section, value.parse::<usize>()) {
+                        let moved = sections.remove(i);
+                        sections.insert(index.min(sections.len()), moved);
+                    }
+                    writeln!(out, "reorder {path}={value}")
+                }
+                DeltaCmd::ListAdd => writeln!(out, "add_list {path}={}", quote(value)),
+                DeltaCmd::ListDel => writeln!(out, "del_list {path}={}", quote(value)),
+            };
+        }
+        out
+    }
+}
+
+/// A section with what `uci show` would print of it.
+struct Section {
+    id: String,
+    named: bool,
+    ty: String,
+    options: Vec<(String, UciValue)>,
+}
+
+impl Section {
+    /// Reads the sections the way libuci loads them: a section with the name of an earlier one
+    /// is merged into it, giving it its type and options, with lists appended.
+    fn read(sections: &Sections) -> Vec<Section> {
+        let mut read: Vec<Section> = Vec::new();
+        for section in sections.iter() {
+            let (id, ty) = (section.id(), section.ty().into_owned());
+            let named = section.name().is_some();
+            let Some(first) = read.iter_mut().find(|s| named && s.named && s.id == id) else {
+                let options = section.options();
+                read.push(Section {
+                    id,
+                    named,
+                    ty,
+                    options,
+                });
+                continue;
+            };
+            first.ty = ty;
+            for (name, value) in section.options() {
+                fold_value(&mut first.options, &name, value);
+            }
+        }
+        read
+    }
+
+    /// How many options two sections have in common.
+    fn likeness(&self, other: &Section) -> usize {
+        let common = self.options.iter().filter(|o| other.options.contains(o));
+        common.count()
+    }
+
+    fn option(&self, name: &str) -> Option<&UciValue> {
+        let found = self.options.iter().find(|(n, _)| n == name);
+        found.map(|(_, value)| value)
+    }
+}
+
+impl UciDocument {
+    /// The changes that turn this document into `new`.
+    pub fn diff(&self, new: &UciDocument) -> Result<Diff, Error> {
+        self.read(|old| new.read(|new| Ok(diff(&old, &new))))
+    }
+}
+
+/// The changes that turn the config `old` reads into the one `new` reads.
+pub fn diff(old: &Sections, new: &Sections) -> Diff {
+    let old = Section::read(old);
+    let new = Section::read(new);
+    let pairs = pair_up(&old, &new);
+    let mut changes = Vec::new();
+
+    for &(o, n) in &pairs {
+        if let (Some(o), None) = (o, n) {
+            changes.push(delta(DeltaCmd::Remove, &old[o].id, None, None));
+        }
+    }
+
+    // what each new section is called once the changes before it are applied
+    let mut ids = vec![String::new(); new.len()];
+    let mut taken: HashSet<String> = old.iter().map(|s| s.id.clone()).collect();
+    taken.extend(new.iter().filter(|s| s.named).map(|s| s.id.clone()));
+    let mut added = Vec::new();
+    for &(o, n) in &pairs {
+        let Some(n) = n else { continue };
+        let section = &new[n];
+        ids[n] = match o {
+            Some(o) if old[o].named && old[o].id != section.id => {
+                let new_name = Some(section.id.as_str());
+                changes.push(delta(DeltaCmd::Rename, &old[o].id, None, new_name));
+                section.id.clone()
+            }
+            Some(o) => old[o].id.clone(),
+            None if section.named => section.id.clone(),
+            None => {
+                // a fresh cfgXXXXXX that can't be mistaken for another section
+                let mut id = section.id.clone();
+                let mut position = n + 1;
+                while taken.contains(&id) {
+                    position += 1;
+                    id = anonymous_name(position, &section.ty);
+                }
+                taken.insert(id.clone());
+                id
+            }
+        };
+        if o.is_none() {
+            added.push(n);
+        }
+    }
+
+    for &(o, n) in &pairs {
+        let (Some(o), Some(n)) = (o, n) else { continue };
+        let (old, new, id) = (&old[o], &new[n], &ids[n]);
+        if old.ty != new.ty {
+            changes.push(delta(DeltaCmd::Change, id, None, Some(&new.ty)));
+        }
+        for (name, _) in &old.options {
+            if new.option(name).is_none() {
+                changes.push(delta(DeltaCmd::Remove, id, Some(name), None));
+            }
+        }
+        for (name, value) in &new.options {
+            option_changes(&mut changes, id, name, old.option(name), value);
+        }
+    }
+
+    for &n in &added {
+        let (section, id) = (&new[n], &ids[n]);
+        let cmd = match section.named {
+            true => DeltaCmd::Change,
+            false => DeltaCmd::Add,
+        };
+        changes.push(delta(cmd, id, None, Some(&section.ty)));
+        for (name, value) in &section.options {
+            option_changes(&mut changes, id, name, None, value);
+        }
+    }
+
+    // added sections start out at the end
+    let mut order: Vec<&str> = pairs
+        .iter()
+        .filter_map(|&(o, n)| o.and(n))
+        .chain(added.iter().copied())
+        .map(|n| ids[n].as_str())
+        .collect();
+    for (index, id) in ids.iter().enumerate() {
+        if order[index] == id {
+            continue;
+        }
+        let Some(from) = order.iter().position(|o| o == id) else {
+            continue;
+        };
+        let moved = order.remove(from);
+        order.insert(index, moved);
+        let index = index.to_string();
+        changes.push(delta(DeltaCmd::Reorder, id, None, Some(&index)));
+    }
+
+    Diff {
+        changes,
+        old: old.into_iter().map(|s| (s.id, s.ty)).collect(),
+    }
+}
+
+fn delta(cmd: DeltaCmd, section: &str, option: Option<&str>, value: Option<&str>) -> Delta {
+    Delta {
+        cmd,
+        section: section.to_owned(),
+        option: option.map(str::to_owned),
+        value: value.map(str::to_owned),
+    }
+}
+
+/// The changes that turn the option `name` of section `id` from `old` into `new`. A list that
+/// only lost some items and gained others at its end keeps the rest, anything else about a list
+/// is written anew, which is also how a change of order shows.
+fn option_changes(
+    changes: &mut Vec<Delta>,
+    id: &str,
+    name: &str,
+    old: Option<&UciValue>,
+    new: &UciValue,
+) {
+    if old == Some(new) {
+        return;
+    }
+    let items = match new {
+        UciValue::Option(value) => {
+            changes.push(delta(DeltaCmd::Change, id, Some(name), Some(value)));
+            return;
+        }
+        UciValue::List(items) => items,
+    };
+    let (old_items, was_list) = match old {
+        Some(UciValue::List(items)) => (items.as_slice(), true),
+        Some(UciValue::Option(value)) => (std::slice::from_ref(value), false),
+        None => (&[][..], false),
+    };
+    let mut gone: Vec<&String> = Vec::new();
+    for item in old_items {
+        if !items.contains(item) && !gone.contains(&item) {
+            gone.push(item);
+        }
+    }
+    let kept: Vec<&String> = old_items.iter().filter(|i| !gone.contains(i)).collect();
+    let appended = kept.len() <= items.len() && kept.iter().zip(items).all(|(a, b)| *a == b);
+    // `del_list` leaves a plain option alone
+    let start = if appended && (was_list || gone.is_empty()) {
+        for value in gone {
+            changes.push(delta(DeltaCmd::ListDel, id, Some(name), Some(value)));
+        }
+        kept.len()
+    } else {
+        if old.is_some() {
+            changes.push(delta(DeltaCmd::Remove, id, Some(name), None));
+        }
+        0
+    };
+    for item in &items[start..] {
+        changes.push(delta(DeltaCmd::ListAdd, id, Some(name), Some(item)));
+    }
+}
+
+/// Pairs up the sections of two versions of a config, as `(old, new)` indexes: every old
+/// section in order with its match if it has one, then the new sections that have none.
+///
+/// Named sections match by name, or failing that a section with the same type and options
+/// whose name is gone, which makes a rename. Anonymous ones match an identical section first.
+/// The rest are paired most alike first, by the options they have in common, so that a
+/// section is only new once the old ones of its type have run out.
+fn pair_up(old: &[Section], new: &[Section]) -> Vec<(Option<usize>, Option<usize>)> {
+    let mut matched: Vec<Option<usize>> = vec![None; new.len()];
+    let mut used = vec![false; old.len()];
+    let gone = |s: &Section| !new.iter().any(|n| n.named && n.id == s.id);
+
+    for (n, section) in new.iter().enumerate() {
+        let found = match section.named {
+            true => old
+                .iter()
+                .enumerate()
+                .position(|(o, s)| !used[o] && s.named && s.id == section.id),
+            false => None,
+        };
+        if let Some(o) = found {
+            matched[n] = Some(o);
+            used[o] = true;
+        }
+    }
+    // renamed sections, then identical anonymous ones
+    for named in [true, false] {
+        for (n, section) in new.iter().enumerate() {
+            if matched[n].is_some() || section.named != named {
+                continue;
+            }
+            let found = old.iter().enumerate().position(|(o, s)| {
+                !used[o]
+                    && s.named == named
+                    && (!named || gone(s))
+                    && s.ty == section.ty
+                    && s.options == section.options
+            });
+            if let Some(o) = found {
+                matched[n] = Some(o);
+                used[o] = true;
+            }
+        }
+    }
+    loop {
+        let mut best = None;
+        for (n, section) in new.iter().enumerate() {
+            if matched[n].is_some() || section.named {
+                continue;
+            }
+            for (o, s) in old.iter().enumerate() {
+                if used[o] || s.named || s.ty != section.ty {
+                    continue;
+                }
+                let score = (s.likeness(section), Reverse(n), Reverse(o));
+                if best.as_ref().is_none_or(|best| score > *best) {
+                    best = Some(score);
+                }
+            }
+        }
+        let Some((_, Reverse(n), Reverse(o))) = best else {
+            break;
+        };
+        matched[n] = Some(o);
+        used[o] = true;
+    }
+
+    let mut pairs: Vec<_> = (0..old.len())
+        .map(|o| (Some(o), matched.iter().position(|&m| m == Some(o))))
+        .collect();
+    let unmatched = (0..new.len()).filter(|&n| matched[n].is_none());
+    pairs.extend(unmatched.map(|n| (None, Some(n))));
+    pairs
+}
+
+#[test]
+fn test_diff() {
+    use crate::rewrite_config_string;
+
+    let old = "# firewall\nconfig defaults\n\toption input ACCEPT\n\nconfig zone lan\n\toption name lan\n\tlist network lan\n\nconfig zone wan\n\toption name wan\n\tlist network wan\n\tlist network wan6\n\nconfig rule\n\toption name ssh\n\toption target ACCEPT\n\nconfig rule\n\toption name ping\n\toption target ACCEPT\n\tlist proto icmp\n\nconfig redirect\n\toption name old\n\toption src wan\n";
+    let new = "config defaults\n\toption input 'ACCEPT' # quoted\n\nconfig zone guest\n\toption name lan\n\tlist network lan\n\nconfig rule\n\toption name web\n\nconfig zone wan\n\toption name wan\n\tlist network wan6\n\tlist network wan\n\nconfig rule\n\toption name ping\n\toption target DROP\n\tlist proto icmp\n\tlist proto icmpv6\n\nconfig rule\n\toption name ssh\n\toption target ACCEPT\n";
+    let (old_doc, new_doc) = (
+        UciDocument::parse(old).unwrap(),
+        UciDocument::parse(new).unwrap(),
+    );
+    let diff = old_doc.diff(&new_doc).unwrap();
+    let lines: Vec<_> = diff.changes.iter().map(|d| d.to_line("firewall")).collect();
+    assert_eq!(
+        lines,
+        [
+            "-firewall.cfg063837",
+            "@firewall.lan='guest'",
+            "-firewall.wan.network",
+            "|firewall.wan.network='wan6'",
+            "|firewall.wan.network='wan'",
+            "firewall.cfg0592bd.target='DROP'",
+            "|firewall.cfg0592bd.proto='icmpv6'",
+            "+firewall.cfg0392bd='rule'",
+            "firewall.cfg0392bd.name='web'",
+            "^firewall.cfg0392bd='2'",
+            "^firewall.cfg0592bd='4'",
+        ]
+    );
+    assert_eq!(diff.changes[3].cmd.luci_name(), "list-add");
+
+    // applying the changes gives the new config, give or take comments
+    let applied = rewrite_config_string(old.to_owned(), |mut ctx| {
+        ctx.apply_deltas(&diff.changes).map(drop)
+    })
+    .unwrap();
+    let applied = UciDocument::parse(&applied).unwrap();
+    assert!(applied.diff(&new_doc).unwrap().is_empty());
+
+    let batch = diff.batch("firewall");
+    assert!(batch.starts_with("delete firewall.cfg063837\nrename firewall.lan=guest\n"));
+    assert!(batch.contains(
+        "add firewall rule\nset firewall.@rule[2].name='web'\nreorder firewall.@rule[2]=2\n"
+    ));
+    assert!(batch.ends_with("reorder firewall.cfg0592bd=4\n"));
+
+    // libuci merges sections of the same name, on either side
+    let twice: UciDocument = "config rule a\n\toption x 1\n\nconfig rule a\n\tlist y 2\n"
+        .parse()
+        .unwrap();
+    let once: UciDocument = "config rule a\n\toption x 1\n\tlist y 2\n".parse().unwrap();
+    assert!(twice.diff(&once).unwrap().is_empty());
+    let changed: UciDocument =
+        "config rule a\n\toption x 3\n\nconfig rule a\n\tlist y 2\n\tlist y 4\n"
+            .parse()
+            .unwrap();
+    let diff = once.diff(&changed).unwrap();
+    let lines: Vec<_> = diff.changes.iter().map(|d| d.to_line("fw")).collect();
+    assert_eq!(lines, ["fw.a.x='3'", "|fw.a.y='4'"]);
+    assert_eq!(twice.diff(&changed).unwrap().changes.len(), 2);
+}
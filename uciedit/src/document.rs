@@ -0,0 +1,388 @@
+//! A parsed config that owns its text. [`Line`] and [`Token`] borrow from the string they were
+//! parsed from and from an [`Arena`], so [`Sections`] and [`SectionsMut`] only live for the
+//! duration of a closure. A [`UciDocument`] can instead be kept in a struct, cloned, sent to
+//! another task or returned from a function, and still be edited through the same cursors.
+
+use crate::{format_lines, parse_lines, Arena, Error, Line, Lines, ParseError, Sections};
+use crate::{SectionsMut, Token};
+use crate::{UciSection, UciValue};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A whole config: its sections, options, lists, comments and blank lines, in file order.
+/// Tokens keep the quoting they were written with, so lines that aren't edited come out of
+/// [`Display`](fmt::Display) the way [`rewrite_config`](crate::rewrite_config) would write them.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct UciDocument {
+    lines: Vec<OwnedLine>,
+}
+
+/// An owned [`Line`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OwnedLine {
+    Empty,
+    Comment {
+        indent: bool,
+        text: String,
+    },
+    Section {
+        ty: OwnedToken,
+        name: Option<OwnedToken>,
+    },
+    Option {
+        option: OwnedToken,
+        value: OwnedToken,
+    },
+    List {
+        list: OwnedToken,
+        item: OwnedToken,
+    },
+    Package {
+        name: OwnedToken,
+    },
+    Trailing {
+        text: String,
+    },
+}
+
+/// An owned [`Token`], kept exactly as it was written.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OwnedToken {
+    raw: String,
+}
+
+impl UciDocument {
+    pub fn parse(config: &str) -> Result<Self, ParseError> {
+        Ok(Self::from_lines(&parse_lines(config)?))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Ok(Self::parse(&text).map_err(|err| err.with_path(path))?)
+    }
+
+    /// Like [`load`](Self::load), but reads the file without blocking the tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub async fn load_async(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let text = tokio::fs::read_to_string(path).await?;
+        Ok(Self::parse(&text).map_err(|err| err.with_path(path))?)
+    }
+
+    /// Takes `lines` without the removed ones. A trailing comment whose statement was removed
+    /// or replaced becomes a comment line of its own, like [`format_lines`] writes it.
+    pub fn from_lines(lines: &Lines) -> Self {
+        UciDocument {
+            lines: kept_lines(lines, OwnedLine::from_line).collect(),
+        }
+    }
+
+    pub fn lines(&self) -> &[OwnedLine] {
+        &self.lines
+    }
+
+    /// Runs `with` on a read-only cursor over the document.
+    pub fn read<V>(&self, with: impl FnOnce(Sections) -> Result<V, Error>) -> Result<V, Error> {
+        let lines = self.borrow_lines();
+        with(Sections::new(&lines))
+    }
+
+    /// Runs `with` on a cursor that edits the document. If it fails, the document is left as it
+    /// was.
+    pub fn edit<V>(
+        &mut self,
+        with: impl FnOnce(SectionsMut) -> Result<V, Error>,
+    ) -> Result<V, Error> {
+        let arena = Arena::new();
+        let mut lines = self.borrow_lines();
+        let v = with(SectionsMut::new(&mut lines, &arena))?;
+        // Lines the closure didn't touch still borrow from `self.lines`, and are moved over
+        // instead of copied. Only edited lines become new owned lines.
+        let mut origins = HashMap::new();
+        for (index, line) in self.lines.iter().enumerate() {
+            origins.entry(line_key(&line.as_line())).or_insert(index);
+        }
+        let mut moved = vec![false; self.lines.len()];
+        let edited: Vec<_> = kept_lines(&lines, |line| match origins.get(&line_key(line)) {
+            Some(&index) if !moved[index] && self.lines[index].is_borrowed_by(line) => {
+                moved[index] = true;
+                Some(Ok(index))
+            }
+            _ => OwnedLine::from_line(line).map(Err),
+        })
+        .collect();
+        drop(lines);
+        let mut old: Vec<_> = std::mem::take(&mut self.lines)
+            .into_iter()
+            .map(Some)
+            .collect();
+        self.lines = edited
+            .into_iter()
+            .map(|line| match line {
+                Ok(index) => old[index].take().expect("each line is moved once"),
+                Err(owned) => owned,
+            })
+            .collect();
+        Ok(v)
+    }
+
+    /// Looks up a path like `uci get`. A path without an option resolves to the section type.
+    pub fn get_path(&self, path: &str) -> Result<Option<UciValue>, Error> {
+        self.read(|ctx| ctx.get_path(path))
+    }
+
+    /// Reads the section with the given name, or `cfgXXXXXX` id if it is anonymous.
+    pub fn get<S>(&self, id: &str) -> Result<Option<S>, Error>
+    where
+        S: for<'a> UciSection<'a>,
+    {
+        self.read(|mut ctx| {
+            while ctx.step() {
+                if ctx.id()? == id {
+                    return ctx.get().map(Some);
+                }
+            }
+            Ok(None)
+        })
+    }
+
+    fn borrow_lines(&self) -> Lines<'_> {
+        self.lines.iter().map(OwnedLine::as_line).collect()
+    }
+}
+
+/// Runs `keep` on each line of `lines` that isn't removed, with trailing comments whose
+/// statement is gone already detached.
+fn kept_lines<'l, T>(
+    lines: &'l Lines,
+    mut keep: impl FnMut(&Line) -> Option<T> + 'l,
+) -> impl Iterator<Item = T> + 'l {
+    let mut prev: Option<&Line> = None;
+    lines.iter().filter_map(move |line| {
+        let kept = match line {
+            Line::Trailing { text } if !prev.is_some_and(Line::is_statement) => {
+                keep(&Line::detached(text))
+            }
+            _ => keep(line),
+        };
+        prev = Some(line);
+        kept
+    })
+}
+
+/// Where the text of a line starts, to find the owned line it may still borrow from.
+fn line_key(line: &Line) -> Option<*const u8> {
+    Some(match line {
+        Line::Comment { text, .. } | Line::Trailing { text } => text.as_ptr(),
+        Line::Section { ty: token, .. }
+        | Line::Option { option: token, .. }
+        | Line::List { list: token, .. }
+        | Line::Package { name: token } => token.raw.as_ptr(),
+        Line::Empty | Line::Skip => return None,
+    })
+}
+
+impl FromStr for UciDocument {
+    type Err = ParseError;
+
+    fn from_str(config: &str) -> Result<Self, Self::Err> {
+        Self::parse(config)
+    }
+}
+
+impl fmt::Display for UciDocument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_lines(&self.borrow_lines()))
+    }
+}
+
+impl OwnedLine {
+    /// `None` for [`Line::Skip`], which stands for a line that was removed.
+    pub fn from_line(line: &Line) -> Option<Self> {
+        Some(match line {
+            Line::Empty => OwnedLine::Empty,
+            Line::Comment { indent, text } => OwnedLine::Comment {
+                indent: *indent,
+                text: (*text).to_owned(),
+            },
+            Line::Section { ty, name } => OwnedLine::Section {
+                ty: ty.into(),
+                name: name.as_ref().map(OwnedToken::from),
+            },
+            Line::Option { option, value } => OwnedLine::Option {
+                option: option.into(),
+                value: value.into(),
+            },
+            Line::List { list, item } => OwnedLine::List {
+                list: list.into(),
+                item: item.into(),
+            },
+            Line::Package { name } => OwnedLine::Package { name: name.into() },
+            Line::Trailing { text } => OwnedLine::Trailing {
+                text: (*text).to_owned(),
+            },
+            Line::Skip => return None,
+        })
+    }
+
+    /// Whether `line` is this line as [`as_line`](Self::as_line) borrowed it, with every part
+    /// still pointing at our own text.
+    fn is_borrowed_by(&self, line: &Line) -> bool {
+        let same = |a: &Token, b: &Token| std::ptr::eq(a.raw, b.raw);
+        match (self.as_line(), line) {
+            (Line::Empty, Line::Empty) => true,
+            (Line::Comment { indent, text }, Line::Comment { indent: i, text: t }) => {
+                indent == *i && std::ptr::eq(text, *t)
+            }
+            (Line::Section { ty, name }, Line::Section { ty: t, name: n }) => {
+                same(&ty, t)
+                    && match (name, n) {
+                        (Some(name), Some(n)) => same(&name, n),
+                        (name, n) => name.is_none() && n.is_none(),
+                    }
+            }
+            (
+                Line::Option { option, value },
+                Line::Option {
+                    option: o,
+                    value: v,
+                },
+            ) => same(&option, o) && same(&value, v),
+            (Line::List { list, item }, Line::List { list: l, item: i }) => {
+                same(&list, l) && same(&item, i)
+            }
+            (Line::Package { name }, Line::Package { name: n }) => same(&name, n),
+            (Line::Trailing { text }, Line::Trailing { text: t }) => std::ptr::eq(text, *t),
+            _ => false,
+        }
+    }
+
+    pub fn as_line(&self) -> Line<'_> {
+        match self {
+            OwnedLine::Empty => Line::Empty,
+            OwnedLine::Comment { indent, text } => Line::Comment {
+                indent: *indent,
+                text,
+            },
+            OwnedLine::Section { ty, name } => Line::Section {
+                ty: ty.as_token(),
+                name: name.as_ref().map(OwnedToken::as_token),
+            },
+            OwnedLine::Option { option, value } => Line::Option {
+                option: option.as_token(),
+                value: value.as_token(),
+            },
+            OwnedLine::List { list, item } => Line::List {
+                list: list.as_token(),
+                item: item.as_token(),
+            },
+            OwnedLine::Package { name } => Line::Package {
+                name: name.as_token(),
+            },
+            OwnedLine::Trailing { text } => Line::Trailing { text },
+        }
+    }
+}
+
+impl OwnedToken {
+    /// A token for `value`, quoted only if it needs to be.
+    pub fn new(value: &str) -> Self {
+        let arena = Arena::new();
+        Token::from_str(value, &arena).into()
+    }
+
+    pub fn parse(raw: &str) -> Result<Self, ParseError> {
+        Ok(Token::parse(raw)?.into())
+    }
+
+    /// The token as written in the file, including any quotes and escapes.
+    pub fn raw(&self) -> &str {
+        &self.raw
+    }
+
+    /// The value of the token with quotes and escapes removed.
+    pub fn as_str(&self) -> Cow<'_, str> {
+        self.as_token().as_str()
+    }
+
+    pub fn as_token(&self) -> Token<'_> {
+        Token { raw: &self.raw }
+    }
+}
+
+impl From<Token<'_>> for OwnedToken {
+    fn from(token: Token<'_>) -> Self {
+        OwnedToken {
+            raw: token.raw.to_owned(),
+        }
+    }
+}
+
+impl From<&Token<'_>> for OwnedToken {
+    fn from(token: &Token<'_>) -> Self {
+        (*token).into()
+    }
+}
+
+impl fmt::Display for OwnedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.raw)
+    }
+}
+
+#[test]
+fn test_document() {
+    #[derive(UciSection, Debug, PartialEq)]
+    struct Zone {
+        name: String,
+        #[uci(split)]
+        network: Vec<String>,
+    }
+
+    fn send<T: Send + Sync + 'static>(doc: T) -> T {
+        doc
+    }
+
+    let config = "# zones\nconfig zone 'lan'\n\toption name \"lan\"\n\tlist network lan\n\n\t# keep\nconfig zone\n\toption name 'it'\\''s'\n";
+    let doc: UciDocument = config.parse().unwrap();
+    assert_eq!(doc.to_string(), config);
+
+    let mut copy = send(doc.clone());
+    assert_eq!(
+        copy.get::<Zone>("lan").unwrap(),
+        Some(Zone {
+            name: "lan".into(),
+            network: vec!["lan".into()],
+        })
+    );
+    assert_eq!(
+        copy.get_path("@zone[1].name").unwrap(),
+        Some(UciValue::Option("it's".into()))
+    );
+
+    let comment = |doc: &UciDocument| match &doc.lines()[0] {
+        OwnedLine::Comment { text, .. } => text.as_ptr(),
+        _ => unreachable!(),
+    };
+    let before = comment(&copy);
+    copy.edit(|mut ctx| ctx.set_path("lan.network", "lan guest"))
+        .unwrap();
+    // lines the edit didn't touch are kept, not copied
+    assert_eq!(comment(&copy), before);
+    assert!(copy
+        .edit::<()>(|mut ctx| {
+            ctx.set_path("lan.name", "gone")?;
+            crate::bail!("give up")
+        })
+        .is_err());
+    assert_eq!(
+        copy.to_string(),
+        "# zones\nconfig zone 'lan'\n\toption name \"lan\"\n\toption network 'lan guest'\n\n\t# keep\nconfig zone\n\toption name 'it'\\''s'\n"
+    );
+    assert_ne!(copy, doc);
+    assert!(matches!(&doc.lines()[0], OwnedLine::Comment { text, .. } if text == " zones"));
+}
@@ -0,0 +1,122 @@
+use std::fmt::{self, Display};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// What is wrong with a config that failed to parse.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A `'` or `"` that is never closed.
+    UnterminatedQuote(char),
+    /// A statement that doesn't start with `config`, `option`, `list` or `package`.
+    UnknownKeyword(String),
+    /// `config` without a section type.
+    MissingSectionType,
+    /// `option`, `list` or `package` without a name.
+    MissingName(&'static str),
+    /// A statement with more words than its keyword takes.
+    TooManyArguments(&'static str),
+    /// `option` or `list` before the first `config`, or right after a `package`.
+    OptionOutsideSection(&'static str),
+    /// More than one statement where [`Line::parse`](crate::Line::parse) expects one.
+    MultipleStatements,
+    /// More than one word where [`Token::parse`](crate::Token::parse) expects one.
+    MultipleTokens,
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnterminatedQuote(quote) => write!(f, "unterminated {quote} quote"),
+            ErrorKind::UnknownKeyword(keyword) => write!(
+                f,
+                "unknown keyword {keyword:?}, expected config, option, list or package"
+            ),
+            ErrorKind::MissingSectionType => write!(f, "expected a section type after config"),
+            ErrorKind::MissingName(keyword) => write!(f, "expected a name after {keyword}"),
+            ErrorKind::TooManyArguments(keyword) => write!(f, "too many arguments to {keyword}"),
+            ErrorKind::OptionOutsideSection(keyword) => {
+                write!(f, "{keyword} outside of a config section")
+            }
+            ErrorKind::MultipleStatements => write!(f, "expected a single statement"),
+            ErrorKind::MultipleTokens => write!(f, "expected a single word"),
+        }
+    }
+}
+
+/// A syntax error in a config, pointing at where it is. Its [`Display`] renders like a rustc
+/// diagnostic:
+///
+/// ```text
+/// error: unterminated ' quote
+///  --> /etc/config/secprof:3:14
+///   |
+/// 3 |     option name 'oops
+///   |                 ^
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    /// the file, if the config was read from one
+    pub path: Option<PathBuf>,
+    /// 1-based
+    pub line: usize,
+    /// 1-based, in characters
+    pub column: usize,
+    /// the whole line the error is on
+    pub snippet: String,
+    /// how many characters of the snippet to underline
+    pub len: usize,
+}
+
+impl ParseError {
+    /// An error for the bytes `span` of `src`.
+    pub(crate) fn new(kind: ErrorKind, src: &str, span: Range<usize>) -> Self {
+        let line_start = src[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = src[span.start..]
+            .find('\n')
+            .map_or(src.len(), |i| span.start + i);
+        let snippet = src[line_start..line_end].trim_end_matches('\r');
+        let len = src[span.start..span.end.min(line_end)].chars().count();
+        ParseError {
+            kind,
+            path: None,
+            line: src[..line_start].matches('\n').count() + 1,
+            column: src[line_start..span.start].chars().count() + 1,
+            snippet: snippet.to_owned(),
+            len: len.max(1),
+        }
+    }
+
+    pub fn with_path(mut self, path: impl AsRef<Path>) -> Self {
+        self.path = Some(path.as_ref().to_owned());
+        self
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ParseError {
+            line,
+            column,
+            snippet,
+            ..
+        } = self;
+        writeln!(f, "error: {}", self.kind)?;
+        let gutter = " ".repeat(line.to_string().len());
+        match &self.path {
+            Some(path) => writeln!(f, "{gutter}--> {}:{line}:{column}", path.display())?,
+            None => writeln!(f, "{gutter}--> {line}:{column}")?,
+        }
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{line} | {snippet}")?;
+        // keep tabs so that the carets line up however wide the terminal draws them
+        let indent: String = snippet
+            .chars()
+            .take(column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        write!(f, "{gutter} | {indent}{}", "^".repeat(self.len))
+    }
+}
+
+impl std::error::Error for ParseError {}
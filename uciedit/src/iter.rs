@@ -0,0 +1,295 @@
+//! Handles on single sections, as an alternative to driving the [`step`](Sections::step) cursor
+//! by hand. A handle always points at a section, so unlike the cursor its methods can't be
+//! called too early, and each section is visited exactly once.
+//!
+//! Reading goes through a plain [`Iterator`] of [`SectionRef`]s. A [`SectionMut`] borrows the
+//! cursor it came from, so there can only be one at a time and they are taken with
+//! [`next_section`](SectionsMut::next_section) in a `while let` loop instead.
+
+use crate::query::{delete_option, is_valid_name, option_value, options, section_id, set_option};
+use crate::UciValue;
+use crate::{bail, Error, Line, Lines, Sections, SectionsMut, Token, UciPatch, UciSection};
+use std::borrow::Cow;
+
+/// A read-only handle on a section.
+#[derive(Clone, Copy)]
+pub struct SectionRef<'a> {
+    lines: &'a Lines<'a>,
+    line: usize,
+    index: usize,
+    ty: Token<'a>,
+    name: Option<Token<'a>>,
+}
+
+/// The sections of a config in order, see [`Sections::iter`].
+#[derive(Clone)]
+pub struct Iter<'a> {
+    lines: &'a Lines<'a>,
+    index: usize,
+    position: usize,
+}
+
+/// A handle on the section a [`SectionsMut`] cursor is on.
+pub struct SectionMut<'c, 'l, 'a> {
+    cursor: &'c mut SectionsMut<'l, 'a>,
+}
+
+impl<'a> Iter<'a> {
+    fn new(lines: &'a Lines<'a>) -> Self {
+        Iter {
+            lines,
+            index: 0,
+            position: 0,
+        }
+    }
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = SectionRef<'a>;
+
+    fn next(&mut self) -> Option<SectionRef<'a>> {
+        let (found, ty, name) =
+            self.lines[self.index..]
+                .iter()
+                .enumerate()
+                .find_map(|(found, line)| match line {
+                    Line::Section { ty, name } => Some((found, *ty, *name)),
+                    _ => None,
+                })?;
+        let section = SectionRef {
+            lines: self.lines,
+            line: self.index + found,
+            index: self.position,
+            ty,
+            name,
+        };
+        self.index = section.line + 1;
+        self.position += 1;
+        Some(section)
+    }
+}
+
+impl<'a> Sections<'a> {
+    /// Every section from the first, wherever the cursor is.
+    pub fn iter(&self) -> Iter<'a> {
+        Iter::new(self.lines)
+    }
+
+    /// The sections `S` can read, judging by their type. For types without a
+    /// `#[derive(UciSection)]`, that may be all of them.
+    pub fn sections_of<S: UciSection<'a>>(&self) -> impl Iterator<Item = SectionRef<'a>> {
+        self.iter().filter(SectionRef::is::<S>)
+    }
+}
+
+impl<'a> SectionRef<'a> {
+    pub fn ty(&self) -> Cow<'a, str> {
+        self.ty.as_str()
+    }
+
+    pub fn name(&self) -> Option<Cow<'a, str>> {
+        self.name.map(|name| name.as_str())
+    }
+
+    /// The position of the section in the config, counting sections of every type.
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The section name, or the `cfgXXXXXX` name libuci uses for an anonymous section.
+    pub fn id(&self) -> String {
+        section_id(self.lines, self.line)
+    }
+
+    /// Whether the section has one of the types `S` reads.
+    pub fn is<S: UciSection<'a>>(&self) -> bool {
+        is_type::<S>(&self.ty())
+    }
+
+    pub fn get<S: UciSection<'a>>(&self) -> Result<S, Error> {
+        S::read(self.lines, self.line)
+    }
+
+    pub fn option(&self, name: &str) -> Option<UciValue> {
+        option_value(self.lines, self.line, name)
+    }
+
+    /// The options of the section, in the order `uci show` lists them.
+    pub fn options(&self) -> Vec<(String, UciValue)> {
+        options(self.lines, self.line)
+    }
+}
+
+impl<'l, 'a> SectionsMut<'l, 'a> {
+    /// Every section from the first, wherever the cursor is. A section that is about to be
+    /// [`remove`](Self::remove)d is still there.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter::new(self.lines)
+    }
+
+    /// Steps to the next section like [`step`](Self::step), and returns a handle on it.
+    pub fn next_section(&mut self) -> Option<SectionMut<'_, 'l, 'a>> {
+        self.step().then_some(SectionMut { cursor: self })
+    }
+
+    /// Steps to the next section `S` can read, judging by its type, and returns a handle on it.
+    pub fn next_of<S: UciSection<'a>>(&mut self) -> Option<SectionMut<'_, 'l, 'a>> {
+        while self.step() {
+            if self.ty().is_ok_and(|ty| is_type::<S>(&ty)) {
+                return Some(SectionMut { cursor: self });
+            }
+        }
+        None
+    }
+}
+
+impl<'a> SectionMut<'_, '_, 'a> {
+    pub fn ty(&self) -> Result<Cow<'a, str>, Error> {
+        self.cursor.ty()
+    }
+
+    pub fn name(&self) -> Result<Option<Cow<'a, str>>, Error> {
+        self.cursor.name()
+    }
+
+    /// The position of the section in the config, counting sections of every type.
+    pub fn index(&self) -> usize {
+        self.cursor.lines[..self.cursor.index]
+            .iter()
+            .filter(|line| matches!(line, Line::Section { .. }))
+            .count()
+    }
+
+    /// The section name, or the `cfgXXXXXX` name libuci uses for an anonymous section.
+    pub fn id(&self) -> String {
+        section_id(self.cursor.lines, self.cursor.index)
+    }
+
+    /// Whether the section has one of the types `S` reads.
+    pub fn is<S: UciSection<'a>>(&self) -> bool {
+        self.ty().is_ok_and(|ty| is_type::<S>(&ty))
+    }
+
+    pub fn get<S: UciSection<'a>>(&self) -> Result<S, Error> {
+        self.cursor.get()
+    }
+
+    pub fn set<S: UciSection<'a>>(&mut self, section: S) -> Result<(), Error> {
+        self.cursor.set(section)
+    }
+
+    /// Writes only the options `patch` sets or deletes, leaving the rest of the section alone.
+    pub fn patch<P: UciPatch<'a>>(&mut self, patch: P) -> Result<(), Error> {
+        self.cursor.patch(patch)
+    }
+
+    /// Removes the section, including the comments right above it, once the cursor moves on.
+    pub fn remove(self) {
+        self.cursor.retain = false;
+    }
+
+    pub fn option(&self, name: &str) -> Option<UciValue> {
+        option_value(self.cursor.lines, self.cursor.index, name)
+    }
+
+    /// The options of the section, in the order `uci show` lists them.
+    pub fn options(&self) -> Vec<(String, UciValue)> {
+        options(self.cursor.lines, self.cursor.index)
+    }
+
+    /// Like `uci set` on an option of this section: replaces any option or list of that name.
+    pub fn set_option(&mut self, name: &str, value: &str) -> Result<(), Error> {
+        if !is_valid_name(name) {
+            bail!("invalid option name {name:?}");
+        }
+        let SectionsMut {
+            lines,
+            arena,
+            index,
+            ..
+        } = self.cursor;
+        set_option(lines, arena, *index, name, value);
+        Ok(())
+    }
+
+    /// Removes every `option` and `list` line of an option. Returns false if there was none.
+    pub fn delete_option(&mut self, name: &str) -> bool {
+        delete_option(self.cursor.lines, self.cursor.index, name)
+    }
+}
+
+/// The type and name of the section starting at `index`, where a stepped cursor should be.
+pub(crate) fn header<'a>(
+    lines: &Lines<'a>,
+    index: usize,
+) -> Result<(Token<'a>, Option<Token<'a>>), Error> {
+    match lines.get(index) {
+        Some(Line::Section { ty, name }) => Ok((*ty, *name)),
+        _ => bail!("line {index} does not start a section"),
+    }
+}
+
+fn is_type<'a, S: UciSection<'a>>(ty: &str) -> bool {
+    S::types().is_none_or(|types| types.contains(&ty))
+}
+
+#[test]
+fn test_section_handles() {
+    use crate::{parse_config_string, rewrite_config_string};
+
+    #[derive(UciSection, Debug, PartialEq)]
+    #[uci(ty = "rule")]
+    struct Rule {
+        name: String,
+        target: String,
+    }
+
+    let config = "config zone lan\n\toption name lan\n\nconfig rule\n\toption name a\n\toption target DROP\n\nconfig rule old\n\toption name b\n\toption target DROP\n";
+    let lines = crate::parse_lines(config).unwrap();
+    assert!(header(&lines, 0).is_ok());
+    assert!(header(&lines, 1).is_err() && header(&lines, 100).is_err());
+    parse_config_string(config, |ctx| {
+        assert!(ctx.get::<Rule>().is_err());
+        let ids: Vec<_> = ctx.iter().map(|s| (s.index(), s.id())).collect();
+        assert_eq!(
+            ids,
+            [
+                (0, "lan".into()),
+                (1, "cfg0292bd".into()),
+                (2, "old".into())
+            ]
+        );
+        let rules: Vec<Rule> = ctx
+            .sections_of::<Rule>()
+            .map(|s| s.get())
+            .collect::<Result<_, _>>()?;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].name, "a");
+        assert_eq!(
+            ctx.iter().next().unwrap().option("name"),
+            Some(UciValue::Option("lan".into()))
+        );
+        Ok(())
+    })
+    .unwrap();
+
+    let edited = rewrite_config_string(config.to_owned(), |mut ctx| {
+        while let Some(mut rule) = ctx.next_of::<Rule>() {
+            if rule.name()?.is_some() {
+                rule.remove();
+                continue;
+            }
+            assert_eq!(rule.index(), 1);
+            rule.set_option("target", "ACCEPT")?;
+            assert!(rule.set_option("bad name", "x").is_err());
+            assert!(rule.delete_option("name"));
+        }
+        assert_eq!(ctx.iter().count(), 2);
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        edited,
+        "config zone lan\n\toption name lan\n\nconfig rule\n\toption target ACCEPT\n"
+    );
+}
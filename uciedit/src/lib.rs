@@ -1,72 +1,110 @@
+pub use document::UciDocument;
+pub use error::{ErrorKind, ParseError};
 use eyre::Context;
 pub use eyre::{bail, eyre as error, Error};
+use fd_lock_rs::{FdLock, LockType};
+pub use indexmap::IndexMap;
 pub use inpt::inpt;
-use inpt::split::{Quoted, SingleQuoted, Spaced};
-use inpt::{inpt_step, Inpt, InptStep};
+use iter::header;
+pub use iter::{SectionMut, SectionRef};
+use query::{
+    after_section, find_section, insert_section, is_valid_name, is_valid_type, nth_section_start,
+    section_range, take_lines, take_section, SectionSelector,
+};
 use std::fmt::Display;
-use std::io::{BufRead, BufWriter, Seek};
+use std::io::{Read, Write};
 use std::{borrow::Cow, fs::File, path::Path};
 use std::{fmt, fs};
 pub use uciedit_macros::UciSection;
 
+pub mod delta;
+pub mod diff;
+pub mod document;
+mod error;
+pub mod iter;
 pub mod openwrt;
+pub mod overlay;
+pub mod query;
+pub mod reconcile;
+pub mod schema;
+#[cfg(feature = "serde")]
+pub mod serde;
 
 pub fn parse_config<V>(
     path: impl AsRef<Path>,
     with: impl FnOnce(Sections) -> Result<V, Error>,
 ) -> Result<V, Error> {
-    let text = fs::read_to_string(path)?;
-    parse_config_string(&text, with)
+    UciDocument::load(path)?.read(with)
+}
+
+/// Like [`parse_config`], but reads the file without blocking the tokio runtime.
+#[cfg(feature = "tokio")]
+pub async fn parse_config_async<V>(
+    path: impl AsRef<Path>,
+    with: impl FnOnce(Sections) -> Result<V, Error>,
+) -> Result<V, Error> {
+    UciDocument::load_async(path).await?.read(with)
 }
 
 pub fn parse_config_string<V>(
     config: &str,
     with: impl FnOnce(Sections) -> Result<V, Error>,
 ) -> Result<V, Error> {
-    let lines = config.lines().map(Line::parse).collect::<Result<_, _>>()?;
-    with(Sections {
-        lines: &lines,
-        index: 0,
-        started: false,
-    })
+    UciDocument::parse(config)?.read(with)
 }
 
-/// TODO: async version?
+/// Edits the config file at `path` in place, holding an exclusive lock on it for the duration.
+///
+/// Like libuci, the new contents are written to a temporary file next to the original, synced
+/// and renamed over it, so a crash midway leaves either the old or the new file. The file is left
+/// untouched when the edit does not change anything.
 pub fn rewrite_config<V>(
     path: impl AsRef<Path>,
     with: impl for<'a> FnOnce(SectionsMut) -> Result<V, Error>,
 ) -> Result<V, Error> {
-    use std::io::Write;
+    let path = path.as_ref();
+    let locked = lock_config(path)?;
+    let mut text = String::new();
+    (&*locked).read_to_string(&mut text)?;
+    let (v, edited) = rewrite_text(&text, Some(path), with)?;
+    if edited != text {
+        replace_config(path, &locked, &edited)?;
+    }
+    Ok(v)
+}
 
-    use fd_lock_rs::{FdLock, LockType};
-    use std::io::BufReader;
-    let file = File::options()
-        .create(true)
-        .read(true)
-        .write(true)
-        .truncate(false)
-        .open(path)?;
-    let mut locked = FdLock::lock(file, LockType::Exclusive, true)?;
-    let mut lines = Vec::new();
-    let arena = Arena::new();
-    for line in BufReader::new(&mut *locked).lines() {
-        let line = arena.alloc(line?);
-        let parse =
-            Line::parse(line).with_context(|| format!("syntax error on line {}", lines.len()))?;
-        lines.push(parse);
-    }
-    let v = with(SectionsMut {
-        lines: &mut lines,
-        index: 0,
-        arena: &arena,
-        section_start: None,
-        retain: true,
-    })?;
-    locked.set_len(0)?;
-    locked.seek(std::io::SeekFrom::Start(0))?;
-    let mut writer = BufWriter::new(&mut *locked);
-    for line in lines {
-        write!(writer, "{}", line)?;
+/// Replaces a whole config with `text` under the same lock and atomic rename as
+/// [`rewrite_config`], creating the file if it doesn't exist.
+pub fn write_config(path: impl AsRef<Path>, text: &str) -> Result<(), Error> {
+    let path = path.as_ref();
+    let locked = lock_config(path)?;
+    replace_config(path, &locked, text)
+}
+
+/// Like [`rewrite_config`], but waits for the exclusive lock and does the file IO without
+/// blocking the tokio runtime. Fails if the lock can not be taken within `lock_timeout`.
+#[cfg(feature = "tokio")]
+pub async fn rewrite_config_async<V>(
+    path: impl AsRef<Path>,
+    lock_timeout: std::time::Duration,
+    with: impl for<'a> FnOnce(SectionsMut) -> Result<V, Error>,
+) -> Result<V, Error> {
+    use tokio::io::AsyncReadExt;
+
+    let path = path.as_ref();
+    let locked = tokio::time::timeout(lock_timeout, lock_config_async(path))
+        .await
+        .map_err(|_| error!("timed out waiting for the lock on {}", path.display()))??;
+
+    // The lock lives on the open file description, so a duplicate of the descriptor can do
+    // the IO while `locked` keeps holding it.
+    let mut file = tokio::fs::File::from_std(locked.try_clone()?);
+    let mut text = String::new();
+    file.read_to_string(&mut text).await?;
+    let (v, edited) = rewrite_text(&text, Some(path), with)?;
+    if edited != text {
+        let path = path.to_owned();
+        tokio::task::spawn_blocking(move || replace_config(&path, &locked, &edited)).await??;
     }
     Ok(v)
 }
@@ -75,30 +113,334 @@ pub fn rewrite_config_string(
     config: String,
     with: impl for<'a> FnOnce(SectionsMut) -> Result<(), Error>,
 ) -> Result<String, Error> {
-    use std::fmt::Write;
+    Ok(rewrite_text(&config, None, with)?.1)
+}
 
-    let mut lines = Vec::new();
-    let arena = Arena::new();
-    for line in arena.alloc(config).lines() {
-        lines.push(Line::parse(line)?);
-    }
-    with(SectionsMut {
-        lines: &mut lines,
-        index: 0,
-        arena: &arena,
-        section_start: None,
-        retain: true,
-    })?;
-    let mut writer = String::new();
-    for line in lines {
-        write!(writer, "{}", line)?;
+fn open_config(path: &Path) -> Result<File, Error> {
+    File::options()
+        .create(true)
+        .read(true)
+        .write(true)
+        .truncate(false)
+        .open(path)
+        .with_context(|| format!("opening {}", path.display()))
+}
+
+/// A writer that locked the old file may still be waiting after we rename the new one into
+/// place, so after taking the lock it has to check that it got the file that is actually there.
+fn is_current(path: &Path, file: &File) -> Result<bool, Error> {
+    use std::os::unix::fs::MetadataExt;
+
+    let locked = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(current.dev() == locked.dev() && current.ino() == locked.ino()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn lock_config(path: &Path) -> Result<FdLock<File>, Error> {
+    loop {
+        let locked = FdLock::lock(open_config(path)?, LockType::Exclusive, true)?;
+        if is_current(path, &locked)? {
+            return Ok(locked);
+        }
     }
-    Ok(writer)
+}
+
+/// Polls for the lock without blocking, since a blocking flock would tie up a runtime worker.
+#[cfg(feature = "tokio")]
+async fn lock_config_async(path: &Path) -> Result<FdLock<File>, Error> {
+    use std::time::Duration;
+
+    let mut backoff = Duration::from_millis(5);
+    loop {
+        let file = tokio::task::spawn_blocking({
+            let path = path.to_owned();
+            move || open_config(&path)
+        })
+        .await??;
+        if let Ok(locked) = FdLock::lock(file, LockType::Exclusive, false) {
+            if is_current(path, &locked)? {
+                return Ok(locked);
+            }
+            continue;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(Duration::from_millis(200));
+    }
+}
+
+/// Atomically replaces the locked file at `path` with `text`, keeping its mode and ownership.
+fn replace_config(path: &Path, locked: &File, text: &str) -> Result<(), Error> {
+    use std::os::unix::fs::{fchown, MetadataExt, OpenOptionsExt};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| error!("{} is not a file", path.display()))?
+        .to_string_lossy();
+    let meta = locked.metadata()?;
+
+    let (temp_path, mut temp) = loop {
+        let temp_path = dir.join(format!(
+            ".{file_name}.uciedit-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match File::options()
+            .write(true)
+            .create_new(true)
+            .mode(meta.mode() & 0o7777)
+            .open(&temp_path)
+        {
+            Ok(temp) => break (temp_path, temp),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => {
+                return Err(Error::from(err).wrap_err(format!("creating {}", temp_path.display())))
+            }
+        }
+    };
+
+    let written = (|| {
+        temp.write_all(text.as_bytes())?;
+        // the umask may have masked the mode we asked for
+        temp.set_permissions(meta.permissions())?;
+        let temp_meta = temp.metadata()?;
+        if (temp_meta.uid(), temp_meta.gid()) != (meta.uid(), meta.gid()) {
+            fchown(&temp, Some(meta.uid()), Some(meta.gid()))?;
+        }
+        temp.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if let Err(err) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(Error::from(err).wrap_err(format!("replacing {}", path.display())));
+    }
+
+    // make the rename itself durable
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+fn rewrite_text<V>(
+    config: &str,
+    path: Option<&Path>,
+    with: impl FnOnce(SectionsMut) -> Result<V, Error>,
+) -> Result<(V, String), Error> {
+    let mut doc = UciDocument::parse(config).map_err(|err| match path {
+        Some(path) => err.with_path(path),
+        None => err,
+    })?;
+    let v = doc.edit(with)?;
+    Ok((v, doc.to_string()))
 }
 
 pub type Lines<'a> = Vec<Line<'a>>;
 pub type Arena = typed_arena::Arena<String>;
 
+/// The value of an option: a single `option` or the items of a `list`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum UciValue {
+    Option(String),
+    List(Vec<String>),
+}
+
+/// Parses a boolean the way OpenWrt does: `1`, `yes`, `on`, `true` and `enabled` are true, and
+/// `0`, `no`, `off`, `false` and `disabled` are false.
+pub fn parse_bool(value: &str) -> Result<bool, Error> {
+    match value {
+        "1" | "yes" | "on" | "true" | "enabled" => Ok(true),
+        "0" | "no" | "off" | "false" | "disabled" => Ok(false),
+        _ => bail!("invalid boolean {value:?}"),
+    }
+}
+
+/// Support code for `#[derive(UciSection)]`.
+#[doc(hidden)]
+pub mod __private {
+    use crate::{parse_bool, query, section_end, Arena, IndexMap, Line, Lines, Token, UciValue};
+    use std::collections::VecDeque;
+
+    /// Whether an existing boolean option already says the same as its replacement.
+    pub fn same_bool(old: &Line, new: &Line) -> bool {
+        match (old, new) {
+            (Line::Option { value: old, .. }, Line::Option { value: new, .. }) => {
+                matches!(
+                    (parse_bool(&old.as_str()), parse_bool(&new.as_str())),
+                    (Ok(old), Ok(new)) if old == new
+                )
+            }
+            _ => false,
+        }
+    }
+
+    /// Puts `new` in place of the `option` and `list` lines of `name` in the section at `index`,
+    /// where the first of them was or else at the end of the section. Lines that already hold
+    /// the same values are kept as they are.
+    pub fn patch_option<'a>(
+        lines: &mut Lines<'a>,
+        index: usize,
+        name: &str,
+        new: Vec<Line<'a>>,
+        is_bool: bool,
+    ) {
+        let old: Vec<usize> = query::section_body(lines, index)
+            .filter(|&i| query::is_named(&lines[i], name))
+            .collect();
+        let same = |old: &Line, new: &Line| match (old, new) {
+            (Line::Option { value: a, .. }, Line::Option { value: b, .. }) => {
+                a.as_str() == b.as_str() || is_bool && same_bool(old, new)
+            }
+            (Line::List { item: a, .. }, Line::List { item: b, .. }) => a.as_str() == b.as_str(),
+            _ => false,
+        };
+        if old.len() == new.len() && old.iter().zip(&new).all(|(&i, new)| same(&lines[i], new)) {
+            return;
+        }
+        let at = match old.first() {
+            Some(&first) => first,
+            None => section_end(lines, index) + 1,
+        };
+        for &i in &old {
+            lines[i] = Line::Skip;
+        }
+        lines.splice(at..at, new);
+    }
+
+    /// Whether the section at `index` has `name` as a plain option rather than a list.
+    pub fn is_option(lines: &Lines, index: usize, name: &str) -> bool {
+        matches!(
+            query::option_value(lines, index, name),
+            Some(UciValue::Option(_))
+        )
+    }
+
+    /// The collections a `#[uci(extra)]` field can be.
+    pub trait ExtraOptions {
+        fn entries(&self) -> Vec<(&str, &UciValue)>;
+    }
+
+    impl ExtraOptions for IndexMap<String, UciValue> {
+        fn entries(&self) -> Vec<(&str, &UciValue)> {
+            self.iter()
+                .map(|(name, value)| (name.as_str(), value))
+                .collect()
+        }
+    }
+
+    impl ExtraOptions for Vec<(String, UciValue)> {
+        fn entries(&self) -> Vec<(&str, &UciValue)> {
+            self.iter()
+                .map(|(name, value)| (name.as_str(), value))
+                .collect()
+        }
+    }
+
+    /// Adds an option or list line to the values collected so far, folding repeated names like
+    /// libuci does.
+    pub fn fold_extra(extra: &mut Vec<(String, UciValue)>, line: &Line) {
+        let (name, new) = match line {
+            Line::Option { option, value } => (option, UciValue::Option(value.as_str().into())),
+            Line::List { list, item } => (list, UciValue::List(vec![item.as_str().into()])),
+            _ => return,
+        };
+        fold_value(extra, &name.as_str(), new);
+    }
+
+    /// Adds a value to the options collected so far: an option replaces one of the same name,
+    /// and list items are appended to it.
+    pub fn fold_value(extra: &mut Vec<(String, UciValue)>, name: &str, new: UciValue) {
+        let Some((_, value)) = extra.iter_mut().find(|(n, _)| n == name) else {
+            extra.push((name.to_owned(), new));
+            return;
+        };
+        *value = match (std::mem::replace(value, UciValue::List(Vec::new())), new) {
+            (_, UciValue::Option(new)) => UciValue::Option(new),
+            (UciValue::Option(first), UciValue::List(items)) => {
+                UciValue::List([first].into_iter().chain(items).collect())
+            }
+            (UciValue::List(mut old), UciValue::List(items)) => {
+                old.extend(items);
+                UciValue::List(old)
+            }
+        };
+    }
+
+    /// The lines for the values of a `#[uci(extra)]` field. Existing lines take the next value
+    /// of the same name, the rest are appended to the section.
+    pub struct ExtraLines<'a> {
+        pending: Vec<(&'a str, VecDeque<Line<'a>>)>,
+    }
+
+    impl<'a> ExtraLines<'a> {
+        pub fn new(extra: &impl ExtraOptions, arena: &'a Arena) -> Self {
+            let pending = extra
+                .entries()
+                .into_iter()
+                .map(|(name, value)| {
+                    let name: &'a str = arena.alloc(name.to_owned());
+                    let lines = match value {
+                        UciValue::Option(value) => VecDeque::from([Line::Option {
+                            option: Token::from_str(name, arena),
+                            value: Token::from_display(value, arena),
+                        }]),
+                        UciValue::List(items) => items
+                            .iter()
+                            .map(|item| Line::List {
+                                list: Token::from_str(name, arena),
+                                item: Token::from_display(item, arena),
+                            })
+                            .collect(),
+                    };
+                    (name, lines)
+                })
+                .collect();
+            ExtraLines { pending }
+        }
+
+        /// The line replacing `old`, which keeps its quoting if the value did not change.
+        pub fn next(&mut self, old: &Line<'a>) -> Option<Line<'a>> {
+            let (_, lines) = self
+                .pending
+                .iter_mut()
+                .find(|(name, _)| query::is_named(old, name))?;
+            let new = lines.pop_front()?;
+            Some(match (old, new) {
+                (
+                    Line::Option { option, value },
+                    Line::Option {
+                        value: new_value, ..
+                    },
+                ) if value.as_str() == new_value.as_str() => Line::Option {
+                    option: *option,
+                    value: *value,
+                },
+                (Line::List { list, item }, Line::List { item: new_item, .. })
+                    if item.as_str() == new_item.as_str() =>
+                {
+                    Line::List {
+                        list: *list,
+                        item: *item,
+                    }
+                }
+                (_, new) => new,
+            })
+        }
+
+        /// The lines no existing line took.
+        pub fn rest(self) -> impl Iterator<Item = Line<'a>> {
+            self.pending.into_iter().flat_map(|(_, lines)| lines)
+        }
+    }
+}
+
 pub struct Sections<'a> {
     lines: &'a Lines<'a>,
     index: usize,
@@ -106,29 +448,31 @@ pub struct Sections<'a> {
 }
 
 impl<'a> Sections<'a> {
-    pub fn ty(&self) -> Cow<str> {
-        if !self.started {
-            panic!("call step at least once");
-        }
-        if let Line::Section { ty, .. } = &self.lines[self.index] {
-            return ty.as_str();
+    pub(crate) fn new(lines: &'a Lines<'a>) -> Self {
+        Sections {
+            lines,
+            index: 0,
+            started: false,
         }
-        panic!("section ctx not at a section")
     }
 
-    pub fn name(&self) -> Option<Cow<str>> {
+    pub fn ty(&self) -> Result<Cow<'a, str>, Error> {
         if !self.started {
-            panic!("call step at least once");
+            bail!("call step at least once");
         }
-        if let Line::Section { name, .. } = &self.lines[self.index] {
-            return name.as_ref().map(|n| n.as_str());
+        Ok(header(self.lines, self.index)?.0.as_str())
+    }
+
+    pub fn name(&self) -> Result<Option<Cow<'a, str>>, Error> {
+        if !self.started {
+            bail!("call step at least once");
         }
-        panic!("section ctx not at a section")
+        Ok(header(self.lines, self.index)?.1.map(|name| name.as_str()))
     }
 
     pub fn get<S: UciSection<'a>>(&self) -> Result<S, Error> {
         if !self.started {
-            panic!("call step at least once");
+            bail!("call step at least once");
         }
         S::read(self.lines, self.index)
     }
@@ -165,44 +509,53 @@ pub struct SectionsMut<'l, 'a> {
     retain: bool,
 }
 
-impl<'a> SectionsMut<'_, 'a> {
-    pub fn ty(&self) -> Cow<str> {
-        if self.section_start.is_none() {
-            panic!("call step at least once");
+impl<'l, 'a> SectionsMut<'l, 'a> {
+    pub(crate) fn new(lines: &'l mut Lines<'a>, arena: &'a Arena) -> Self {
+        SectionsMut {
+            lines,
+            index: 0,
+            arena,
+            section_start: None,
+            retain: true,
         }
-        if let Line::Section { ty, .. } = &self.lines[self.index] {
-            return ty.as_str();
-        }
-        panic!("section ctx not at a section")
     }
 
-    pub fn name(&self) -> Option<Cow<str>> {
+    pub fn ty(&self) -> Result<Cow<'a, str>, Error> {
         if self.section_start.is_none() {
-            panic!("call step at least once");
-        }
-        if let Line::Section { name, .. } = &self.lines[self.index] {
-            return name.as_ref().map(|n| n.as_str());
+            bail!("call step at least once");
         }
-        panic!("section ctx not at a section")
+        Ok(header(self.lines, self.index)?.0.as_str())
     }
 
-    pub fn get<S: UciSection<'a>>(&self) -> Result<S, Error> {
+    pub fn name(&self) -> Result<Option<Cow<'a, str>>, Error> {
         if self.section_start.is_none() {
-            panic!("call step at least once");
+            bail!("call step at least once");
         }
+        Ok(header(self.lines, self.index)?.1.map(|name| name.as_str()))
+    }
+
+    pub fn get<S: UciSection<'a>>(&self) -> Result<S, Error> {
         if self.section_start.is_none() {
-            panic!("call step at least once");
+            bail!("call step at least once");
         }
         S::read(self.lines, self.index)
     }
 
     pub fn set<S: UciSection<'a>>(&mut self, section: S) -> Result<(), Error> {
         if self.section_start.is_none() {
-            panic!("call step at least once");
+            bail!("call step at least once");
         }
         section.write(self.lines, self.arena, self.index)
     }
 
+    /// Writes only the options `patch` sets or deletes, leaving the rest of the section alone.
+    pub fn patch<P: UciPatch<'a>>(&mut self, patch: P) -> Result<(), Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        patch.patch(self.lines, self.arena, self.index)
+    }
+
     pub fn push<S: UciSection<'a>>(
         &mut self,
         section: S,
@@ -215,15 +568,165 @@ impl<'a> SectionsMut<'_, 'a> {
         )
     }
 
-    pub fn remove(&mut self) {
-        self.set_retain(false);
+    pub fn remove(&mut self) -> Result<(), Error> {
+        self.set_retain(false)
     }
 
-    pub fn set_retain(&mut self, retain: bool) {
+    pub fn set_retain(&mut self, retain: bool) -> Result<(), Error> {
         if self.section_start.is_none() {
-            panic!("call step at least once");
+            bail!("call step at least once");
         }
         self.retain = retain;
+        Ok(())
+    }
+
+    /// Inserts `section` right before the current one and the comments above it. The cursor
+    /// stays on the current section, so [`step`](Self::step) doesn't visit the new one.
+    pub fn insert_before<S: UciSection<'a>>(
+        &mut self,
+        section: S,
+        name: Option<impl Display>,
+    ) -> Result<(), Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        let at = *section_range(self.lines, self.index).start();
+        let new = self.detached(section, name)?;
+        let len = self.lines.len();
+        insert_section(self.lines, Some(at), new);
+        let shift = self.lines.len() - len;
+        self.section_start = self.section_start.map(|start| start + shift);
+        self.index += shift;
+        Ok(())
+    }
+
+    /// Inserts `section` right after the current one, where [`step`](Self::step) visits it next.
+    pub fn insert_after<S: UciSection<'a>>(
+        &mut self,
+        section: S,
+        name: Option<impl Display>,
+    ) -> Result<(), Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        let at = after_section(self.lines, self.index);
+        let new = self.detached(section, name)?;
+        insert_section(self.lines, at, new);
+        Ok(())
+    }
+
+    /// Like `uci reorder`: moves the current section, with the comments right above it, so that
+    /// it becomes the `index`th section, or the last one if there are fewer. The cursor moves
+    /// along, so [`step`](Self::step) continues after the section's new place.
+    pub fn move_to(&mut self, index: usize) -> Result<(), Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        let (moved, offset) = self.take_current();
+        let at = nth_section_start(self.lines, index);
+        self.place_current(at, moved, offset);
+        Ok(())
+    }
+
+    /// Moves the current section right before the section `name`, like [`move_to`](Self::move_to).
+    pub fn move_before(&mut self, name: &str) -> Result<(), Error> {
+        let other = self.other_section(name)?;
+        let (moved, offset) = self.take_current();
+        let at = *section_range(self.lines, other).start();
+        self.place_current(Some(at), moved, offset);
+        Ok(())
+    }
+
+    /// Moves the current section right after the section `name`, like [`move_to`](Self::move_to).
+    pub fn move_after(&mut self, name: &str) -> Result<(), Error> {
+        let other = self.other_section(name)?;
+        let (moved, offset) = self.take_current();
+        let at = after_section(self.lines, other);
+        self.place_current(at, moved, offset);
+        Ok(())
+    }
+
+    /// Names the current section, or makes it anonymous with `None`. Fails if another section
+    /// already has the name.
+    pub fn rename(&mut self, name: Option<&str>) -> Result<(), Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        if let Some(name) = name {
+            if !is_valid_name(name) {
+                bail!("invalid section name {name:?}");
+            }
+            match find_section(self.lines, &SectionSelector::Named(name.into())) {
+                Some(other) if other != self.index => bail!("section {name:?} already exists"),
+                _ => (),
+            }
+        }
+        if let Line::Section { name: old, .. } = &mut self.lines[self.index] {
+            if old.map(|old| old.as_str()).as_deref() != name {
+                *old = name.map(|name| Token::from_string(name.to_owned(), self.arena));
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the type of the current section, keeping its options.
+    pub fn set_type(&mut self, ty: &str) -> Result<(), Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        if !is_valid_type(ty) {
+            bail!("invalid section type {ty:?}");
+        }
+        if let Line::Section { ty: old, .. } = &mut self.lines[self.index] {
+            if *old != *ty {
+                *old = Token::from_string(ty.to_owned(), self.arena);
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends `section` and takes its lines back out, to be placed elsewhere.
+    fn detached<S: UciSection<'a>>(
+        &mut self,
+        section: S,
+        name: Option<impl Display>,
+    ) -> Result<Vec<Line<'a>>, Error> {
+        let len = self.lines.len();
+        self.push(section, name)?;
+        let mut lines: Vec<_> = self.lines.drain(len..).collect();
+        if matches!(lines.first(), Some(Line::Empty)) {
+            lines.remove(0);
+        }
+        Ok(lines)
+    }
+
+    /// The header of the section `name`, after the current section is taken out.
+    fn other_section(&self, name: &str) -> Result<usize, Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        let Some(other) = find_section(self.lines, &SectionSelector::Named(name.into())) else {
+            bail!("section {name:?} not found");
+        };
+        if other == self.index {
+            bail!("can't move section {name:?} next to itself");
+        }
+        let current = section_range(self.lines, self.index);
+        if other > *current.end() {
+            Ok(other - current.count())
+        } else {
+            Ok(other)
+        }
+    }
+
+    fn take_current(&mut self) -> (Vec<Line<'a>>, usize) {
+        take_section(self.lines, self.index)
+    }
+
+    fn place_current(&mut self, at: Option<usize>, moved: Vec<Line<'a>>, offset: usize) {
+        let start = insert_section(self.lines, at, moved);
+        self.section_start = Some(start);
+        self.index = start + offset;
     }
 
     pub fn step(&mut self) -> bool {
@@ -233,16 +736,8 @@ impl<'a> SectionsMut<'_, 'a> {
                 self.index += 1;
             } else {
                 // Remove the section
-                let mut last_index = self.index;
-                for i in self.index + 1..self.lines.len() {
-                    if matches!(self.lines[i], Line::Section { .. }) {
-                        break;
-                    }
-                    if self.lines[i].is_in_section() {
-                        last_index = i;
-                    }
-                }
-                self.lines.splice(first_index..=last_index, []);
+                let last_index = section_end(self.lines, self.index);
+                take_lines(self.lines, first_index..=last_index);
                 self.index = first_index;
             }
         }
@@ -276,9 +771,40 @@ impl<'a> SectionsMut<'_, 'a> {
         self.section_start = None;
         false
     }
+
+    /// Applies a pending [`remove`](Self::remove) and moves the cursor back before the first
+    /// section, for edits that may shift the lines under the cursor.
+    fn rewind(&mut self) {
+        if let (Some(first_index), false) = (self.section_start, self.retain) {
+            let last_index = section_end(self.lines, self.index);
+            take_lines(self.lines, first_index..=last_index);
+        }
+        self.index = 0;
+        self.section_start = None;
+        self.retain = true;
+    }
+}
+
+/// The index of the last line belonging to the section that starts at `index`.
+fn section_end(lines: &Lines, index: usize) -> usize {
+    let mut last_index = index;
+    for (i, line) in lines.iter().enumerate().skip(index + 1) {
+        if matches!(line, Line::Section { .. } | Line::Package { .. }) {
+            break;
+        }
+        if line.is_in_section() {
+            last_index = i;
+        }
+    }
+    last_index
 }
 
 pub trait UciSection<'a>: Sized {
+    /// The section types [`read`](Self::read) accepts, or `None` if it reads any section.
+    fn types() -> Option<&'static [&'static str]> {
+        None
+    }
+
     fn read(lines: &Lines<'a>, index: usize) -> Result<Self, Error>;
     fn write(&self, lines: &mut Lines<'a>, arena: &'a Arena, index: usize) -> Result<(), Error>;
     fn append(
@@ -289,6 +815,14 @@ pub trait UciSection<'a>: Sized {
     ) -> Result<(), Error>;
 }
 
+/// A partial update of a section, like the `FooPatch` that `#[derive(UciSection)]` generates
+/// for a struct `Foo`.
+pub trait UciPatch<'a> {
+    /// Rewrites the options the patch sets or deletes in the section at `index`, and leaves the
+    /// other lines alone.
+    fn patch(&self, lines: &mut Lines<'a>, arena: &'a Arena, index: usize) -> Result<(), Error>;
+}
+
 pub enum Line<'a> {
     Empty,
     Comment {
@@ -307,102 +841,429 @@ pub enum Line<'a> {
         list: Token<'a>,
         item: Token<'a>,
     },
+    /// `package <name>`, which libuci ignores in config files but uses to separate packages in
+    /// `uci import` input.
+    Package {
+        name: Token<'a>,
+    },
+    /// A comment on the same line as the statement before it, with the blanks in front of its
+    /// `#`, as in `option mtu 1400 # for the VPN`. [`format_lines`] writes it back on that line.
+    Trailing {
+        text: &'a str,
+    },
     Skip,
 }
 
 impl fmt::Display for Line<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Line::Skip = self {
+            return Ok(());
+        }
+        self.write_text(f)?;
+        writeln!(f)
+    }
+}
+
+/// Writes `lines` out as a config file. Unlike joining the lines, this keeps trailing comments
+/// on the line of their statement. One whose statement was removed or replaced becomes a comment
+/// line of its own.
+pub fn format_lines<'l, 'a: 'l>(lines: impl IntoIterator<Item = &'l Line<'a>>) -> String {
+    use std::fmt::Write as _;
+
+    let mut out = String::new();
+    let mut lines = lines.into_iter().peekable();
+    let mut prev: Option<&Line> = None;
+    while let Some(line) = lines.next() {
+        let attached = |next: Option<&&Line>| matches!(next, Some(Line::Trailing { .. }));
+        // a String can't fail to write
+        let _ = match line {
+            Line::Skip => Ok(()),
+            Line::Trailing { text } if !prev.is_some_and(Line::is_statement) => {
+                write!(out, "{}", Line::detached(text))
+            }
+            _ if line.is_statement() && attached(lines.peek()) => line.write_text(&mut out),
+            _ => write!(out, "{line}"),
+        };
+        prev = Some(line);
+    }
+    out
+}
+
+impl<'a> Line<'a> {
+    /// Parses a single statement. Use [`parse_lines`] for whole files, since quoted values and
+    /// backslash continuations may span several lines.
+    pub fn parse(line: &'a str) -> Result<Self, ParseError> {
+        // a lone option is fine here, it isn't known which section it goes in
+        let mut lines = lex(line, true)?;
+        match lines.len() {
+            0 => Ok(Line::Empty),
+            1 => Ok(lines.remove(0)),
+            _ => Err(ParseError::new(
+                ErrorKind::MultipleStatements,
+                line,
+                0..line.len(),
+            )),
+        }
+    }
+
+    /// Builds the statement, or says what is wrong and which token to blame.
+    fn from_tokens(keyword: Token<'a>, args: &[Token<'a>]) -> Result<Self, (ErrorKind, Token<'a>)> {
+        // libuci allows the option value to be omitted, which reads as an empty string
+        let empty = Token { raw: "" };
+        Ok(match (&*keyword.as_str(), args) {
+            ("config", [ty]) => Line::Section {
+                ty: *ty,
+                name: None,
+            },
+            ("config", [ty, name]) => Line::Section {
+                ty: *ty,
+                name: Some(*name),
+            },
+            ("config", []) => return Err((ErrorKind::MissingSectionType, keyword)),
+            ("config", [_, _, extra, ..]) => {
+                return Err((ErrorKind::TooManyArguments("config"), *extra))
+            }
+            ("option", [option]) => Line::Option {
+                option: *option,
+                value: empty,
+            },
+            ("option", [option, value]) => Line::Option {
+                option: *option,
+                value: *value,
+            },
+            ("option", []) => return Err((ErrorKind::MissingName("option"), keyword)),
+            ("option", [_, _, extra, ..]) => {
+                return Err((ErrorKind::TooManyArguments("option"), *extra))
+            }
+            ("list", [list]) => Line::List {
+                list: *list,
+                item: empty,
+            },
+            ("list", [list, item]) => Line::List {
+                list: *list,
+                item: *item,
+            },
+            ("list", []) => return Err((ErrorKind::MissingName("list"), keyword)),
+            ("list", [_, _, extra, ..]) => {
+                return Err((ErrorKind::TooManyArguments("list"), *extra))
+            }
+            ("package", [name]) => Line::Package { name: *name },
+            ("package", []) => return Err((ErrorKind::MissingName("package"), keyword)),
+            ("package", [_, extra, ..]) => {
+                return Err((ErrorKind::TooManyArguments("package"), *extra))
+            }
+            (kw, _) => return Err((ErrorKind::UnknownKeyword(kw.to_owned()), keyword)),
+        })
+    }
+
+    pub fn is_in_section(&self) -> bool {
+        matches!(
+            self,
+            Line::Comment { indent: true, .. }
+                | Line::Option { .. }
+                | Line::List { .. }
+                | Line::Trailing { .. }
+        )
+    }
+
+    /// Whether a [`Line::Trailing`] comment can go on the line.
+    pub(crate) fn is_statement(&self) -> bool {
+        matches!(
+            self,
+            Line::Section { .. } | Line::Option { .. } | Line::List { .. } | Line::Package { .. }
+        )
+    }
+
+    /// The comment line a trailing comment with `text` becomes without its statement.
+    pub(crate) fn detached(text: &'a str) -> Self {
+        Line::Comment {
+            indent: true,
+            text: text.split_once('#').map_or(text, |(_, text)| text),
+        }
+    }
+
+    /// Writes the line without its newline.
+    fn write_text(&self, out: &mut impl fmt::Write) -> fmt::Result {
         match self {
-            Line::Empty => writeln!(f),
+            Line::Empty | Line::Skip => Ok(()),
             Line::Comment {
                 indent: false,
                 text,
-            } => writeln!(f, "#{}", text),
-            Line::Comment { indent: true, text } => writeln!(f, "\t#{}", text),
-            Line::Section { ty, name: None } => writeln!(f, "config {}", ty),
+            } => write!(out, "#{}", text),
+            Line::Comment { indent: true, text } => write!(out, "\t#{}", text),
+            Line::Section { ty, name: None } => write!(out, "config {}", ty),
             Line::Section {
                 ty,
                 name: Some(name),
-            } => writeln!(f, "config {} {}", ty, name),
-            Line::Option { option, value } => writeln!(f, "\toption {} {}", option, value),
-            Line::List { list, item } => writeln!(f, "\tlist {} {}", list, item),
-            Line::Skip => Ok(()),
+            } => write!(out, "config {} {}", ty, name),
+            Line::Option { option, value } => write!(out, "\toption {} {}", option, value),
+            Line::List { list, item } => write!(out, "\tlist {} {}", list, item),
+            Line::Package { name } => write!(out, "package {}", name),
+            Line::Trailing { text } => out.write_str(text),
+        }
+    }
+}
+
+/// Splits a config file into lines following libuci's lexer: quoted strings and backslash
+/// continuations may span several physical lines, `;` separates statements, and an unquoted `#`
+/// starts a comment. A comment after a statement becomes a [`Line::Trailing`] right after it.
+pub fn parse_lines(config: &str) -> Result<Lines<'_>, ParseError> {
+    lex(config, false)
+}
+
+fn lex(config: &str, in_section: bool) -> Result<Lines<'_>, ParseError> {
+    let mut lexer = Lexer {
+        src: config,
+        pos: 0,
+        in_section,
+    };
+    let mut lines = Vec::new();
+    while lexer.pos < config.len() {
+        lexer.physical_line(&mut lines)?;
+    }
+    Ok(lines)
+}
+
+/// Splits `text` into its words the way the lexer reads the arguments of a statement, so a
+/// quoted word may span lines. Comments are skipped.
+pub(crate) fn lex_words(text: &str) -> Result<Vec<Token<'_>>, ParseError> {
+    let mut lexer = Lexer {
+        src: text,
+        pos: 0,
+        in_section: true,
+    };
+    let mut words = Vec::new();
+    loop {
+        while let Some(b' ' | b'\t' | b'\r' | b'\n' | b'\x0b' | b'\x0c' | b';') = lexer.peek() {
+            lexer.pos += 1;
+        }
+        match lexer.peek() {
+            None => return Ok(words),
+            Some(b'#') => {
+                let rest = &text[lexer.pos..];
+                lexer.pos += rest.find('\n').unwrap_or(rest.len());
+            }
+            Some(_) => words.push(lexer.token()?),
+        }
+    }
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    /// whether a `config` came before, which `option` and `list` need like in libuci
+    in_section: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.pos).copied()
+    }
+
+    fn skip_blanks(&mut self) {
+        while let Some(b' ' | b'\t' | b'\r' | b'\x0b' | b'\x0c') = self.peek() {
+            self.pos += 1;
+        }
+    }
+
+    fn physical_line(&mut self, lines: &mut Lines<'a>) -> Result<(), ParseError> {
+        let start = self.pos;
+        self.skip_blanks();
+        let indent = self.pos > start;
+        let mut statements = false;
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                None => break,
+                Some(b'\n') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(b';') => self.pos += 1,
+                Some(b'#') => {
+                    let rest = &self.src[self.pos + 1..];
+                    let len = rest.find('\n').unwrap_or(rest.len());
+                    if statements {
+                        let before = &self.src.as_bytes()[..self.pos];
+                        let blanks = before
+                            .iter()
+                            .rev()
+                            .take_while(|c| matches!(c, b' ' | b'\t' | b'\r' | b'\x0b' | b'\x0c'))
+                            .count();
+                        lines.push(Line::Trailing {
+                            text: &self.src[self.pos - blanks..self.pos + 1 + len],
+                        });
+                    } else {
+                        lines.push(Line::Comment {
+                            indent,
+                            text: &rest[..len],
+                        });
+                    }
+                    self.pos += 1 + len;
+                    if self.peek() == Some(b'\n') {
+                        self.pos += 1;
+                    }
+                    return Ok(());
+                }
+                Some(_) => {
+                    let statement = self.statement()?;
+                    lines.push(statement);
+                    statements = true;
+                }
+            }
+        }
+        if !statements {
+            lines.push(Line::Empty);
         }
+        Ok(())
     }
-}
 
-impl<'a> Line<'a> {
-    pub fn parse(line: &'a str) -> Result<Self, Error> {
-        let rest = line.trim();
-        if rest.is_empty() {
-            return Ok(Line::Empty);
-        }
-        if let Some(rest) = rest.strip_prefix('#') {
-            return Ok(Line::Comment {
-                indent: line.starts_with(char::is_whitespace),
-                text: rest,
-            });
+    fn statement(&mut self) -> Result<Line<'a>, ParseError> {
+        let keyword = self.token()?;
+        let mut args = Vec::new();
+        loop {
+            self.skip_blanks();
+            match self.peek() {
+                None | Some(b'\n' | b';' | b'#') => break,
+                Some(_) => args.push(self.token()?),
+            }
         }
-        let InptStep {
-            data: Ok(keyword),
-            rest,
-        } = inpt_step::<Token>(rest)
-        else {
-            unreachable!()
-        };
-        Ok(match &*keyword.as_str() {
-            "config" => {
-                let (ty, rest) = match inpt_step::<Token>(rest) {
-                    InptStep { data: Ok(ty), rest } => (ty, rest),
-                    _ => bail!("could not parse section type"),
-                };
-                let name: Option<_> = if rest.is_empty() {
-                    None
-                } else {
-                    match inpt::<Token>(rest) {
-                        Ok(name) => Some(name),
-                        _ => bail!("could not parse section name"),
-                    }
-                };
-                Line::Section { ty, name }
+        let line =
+            Line::from_tokens(keyword, &args).map_err(|(kind, token)| self.error(kind, token))?;
+        match line {
+            Line::Section { .. } => self.in_section = true,
+            Line::Package { .. } => self.in_section = false,
+            Line::Option { .. } if !self.in_section => {
+                return Err(self.error(ErrorKind::OptionOutsideSection("option"), keyword))
             }
-            "option" => {
-                let (option, value): (Token, Token) =
-                    inpt(rest).map_err(|err| error!("could not parse option: {err}"))?;
-                Line::Option { option, value }
+            Line::List { .. } if !self.in_section => {
+                return Err(self.error(ErrorKind::OptionOutsideSection("list"), keyword))
             }
-            "list" => {
-                let (list, item): (Token, Token) =
-                    inpt(rest).map_err(|err| error!("could not parse list: {err}"))?;
-                Line::List { list, item }
+            _ => (),
+        }
+        Ok(line)
+    }
+
+    /// An error pointing at `token`, which is a slice of the source.
+    fn error(&self, kind: ErrorKind, token: Token) -> ParseError {
+        let start = token.raw.as_ptr() as usize - self.src.as_ptr() as usize;
+        ParseError::new(kind, self.src, start..start + token.raw.len())
+    }
+
+    fn token(&mut self) -> Result<Token<'a>, ParseError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            match c {
+                b' ' | b'\t' | b'\r' | b'\n' | b'\x0b' | b'\x0c' | b';' | b'#' => break,
+                b'\'' => self.quoted(b'\'')?,
+                b'"' => self.quoted(b'"')?,
+                b'\\' => self.backslash(),
+                _ => self.pos += 1,
             }
-            kw => bail!("unknown UCI keyword {kw:?}"),
+        }
+        Ok(Token {
+            raw: &self.src[start..self.pos],
         })
     }
 
-    pub fn is_in_section(&self) -> bool {
-        matches!(
-            self,
-            Line::Comment { indent: true, .. } | Line::Option { .. } | Line::List { .. }
-        )
+    fn quoted(&mut self, quote: u8) -> Result<(), ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.peek() {
+                None => {
+                    let kind = ErrorKind::UnterminatedQuote(quote as char);
+                    return Err(ParseError::new(kind, self.src, start..start + 1));
+                }
+                Some(c) if c == quote => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(b'\\') if quote == b'"' => self.backslash(),
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+
+    fn backslash(&mut self) {
+        self.pos += 1;
+        if self.peek().is_some() {
+            self.pos += 1;
+        }
     }
 }
 
-#[derive(Inpt, Clone, Copy)]
-pub enum Token<'a> {
-    Q(Quoted<&'a str>),
-    Sq(SingleQuoted<&'a str>),
-    W(Spaced<&'a str>),
+/// A single word of a UCI statement, kept exactly as it was written so that untouched lines
+/// are reproduced verbatim. Adjacent quoted and unquoted parts form one token, so `'it'\''s'`
+/// reads as `it's`.
+#[derive(Clone, Copy)]
+pub struct Token<'a> {
+    raw: &'a str,
 }
 
 impl<'a> Token<'a> {
-    pub fn as_str(&self) -> Cow<str> {
-        // TODO: inpt doesn't currently do unescaping
-        match self {
-            Token::Q(x) => x.unescape(),
-            Token::Sq(x) => x.unescape(),
-            Token::W(x) => Cow::Borrowed(x.inner),
+    /// Parses exactly one token, quoted the way libuci would accept it.
+    pub fn parse(raw: &'a str) -> Result<Self, ParseError> {
+        let mut lexer = Lexer {
+            src: raw,
+            pos: 0,
+            in_section: true,
+        };
+        let token = lexer.token()?;
+        if lexer.pos != raw.len() {
+            let kind = ErrorKind::MultipleTokens;
+            return Err(ParseError::new(kind, raw, lexer.pos..raw.len()));
+        }
+        Ok(token)
+    }
+
+    /// The token as written in the file, including any quotes and escapes.
+    pub fn raw(&self) -> &'a str {
+        self.raw
+    }
+
+    /// The value of the token with quotes and escapes removed.
+    pub fn as_str(&self) -> Cow<'a, str> {
+        let raw = self.raw;
+        if !raw.contains(['\'', '"', '\\']) {
+            return Cow::Borrowed(raw);
+        }
+        if let Some(inner) = raw.strip_prefix('\'').and_then(|r| r.strip_suffix('\'')) {
+            if !inner.contains('\'') {
+                return Cow::Borrowed(inner);
+            }
+        }
+
+        fn backslash(chars: &mut std::iter::Peekable<std::str::Chars>, out: &mut String) {
+            match chars.next() {
+                // line continuation
+                None | Some('\n') => (),
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(c) => out.push(c),
+            }
+        }
+
+        let mut out = String::with_capacity(raw.len());
+        let mut chars = raw.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' => out.extend(chars.by_ref().take_while(|&c| c != '\'')),
+                '"' => {
+                    while let Some(c) = chars.next() {
+                        match c {
+                            '"' => break,
+                            '\\' => backslash(&mut chars, &mut out),
+                            c => out.push(c),
+                        }
+                    }
+                }
+                '\\' => backslash(&mut chars, &mut out),
+                c => out.push(c),
+            }
         }
+        Cow::Owned(out)
     }
 
     pub fn from_display(s: &impl fmt::Display, arena: &'a Arena) -> Self {
@@ -410,29 +1271,50 @@ impl<'a> Token<'a> {
     }
 
     pub fn from_string(s: String, arena: &'a Arena) -> Self {
-        if s.contains(|c: char| c.is_whitespace()) {
-            let q = arena.alloc(format!("{:?}", s));
-            Token::Q(Quoted {
-                inner: &q[1..q.len() - 1],
-            })
+        if is_bare_word(&s) {
+            Token {
+                raw: arena.alloc(s),
+            }
         } else {
-            let s = arena.alloc(s);
-            Token::W(Spaced { inner: s })
+            Token {
+                raw: arena.alloc(quote(&s)),
+            }
         }
     }
 
     pub fn from_str(s: &'a str, arena: &'a Arena) -> Self {
-        if s.contains(|c: char| c.is_whitespace()) {
-            let q = arena.alloc(format!("{:?}", s));
-            Token::Q(Quoted {
-                inner: &q[1..q.len() - 1],
-            })
+        if is_bare_word(s) {
+            Token { raw: s }
         } else {
-            Token::W(Spaced { inner: s })
+            Token {
+                raw: arena.alloc(quote(s)),
+            }
         }
     }
 }
 
+/// Whether `s` can be written without quotes.
+fn is_bare_word(s: &str) -> bool {
+    !s.is_empty()
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"_-.:/@+,=%!~^*[]".contains(&b))
+}
+
+/// Quotes `s` the way `uci export` does: inside single quotes, with each `'` written as `'\''`.
+/// Anything but a NUL byte survives the round trip through both libuci and uciedit.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('\'');
+    for (i, part) in s.split('\'').enumerate() {
+        if i > 0 {
+            out.push_str("'\\''");
+        }
+        out.push_str(part);
+    }
+    out.push('\'');
+    out
+}
+
 impl PartialEq<str> for Token<'_> {
     fn eq(&self, other: &str) -> bool {
         self.as_str() == other
@@ -441,11 +1323,7 @@ impl PartialEq<str> for Token<'_> {
 
 impl fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Token::Q(quoted) => write!(f, "\"{}\"", quoted.inner),
-            Token::Sq(single_quoted) => write!(f, "'{}'", single_quoted.inner),
-            Token::W(word) => write!(f, "{}", word.inner),
-        }
+        f.write_str(self.raw)
     }
 }
 
@@ -648,8 +1526,6 @@ config retain
     option foo bar
     # comment 1
 
-
-
 # section 4
 config retain
     option foo bar
@@ -660,7 +1536,7 @@ config retain
 
     let edited = rewrite_config_string(original.to_string(), |mut ctx| {
         while ctx.step() {
-            ctx.set_retain(ctx.ty() == "retain");
+            ctx.set_retain(ctx.ty()? == "retain")?;
         }
         Ok(())
     })
@@ -669,3 +1545,719 @@ config retain
     println!("===Original==={original}===Edited==={edited}===Expected==={expected}=====");
     assert_eq!(edited.replace("\t", "    "), expected);
 }
+
+#[test]
+fn test_enum_sections() {
+    let original = "config profile guest\n\toption lan_access 0\n\nconfig wpapassword key1\n\toption password hunter2\n\toption profile guest\n\nconfig bogus\n";
+
+    #[derive(UciSection, Debug, PartialEq)]
+    enum Secprof {
+        Profile {
+            lan_access: u8,
+            lan_whitelist: Option<String>,
+        },
+        #[uci(ty = "wpapassword")]
+        WpaPassword { password: String, profile: String },
+    }
+
+    let edited = rewrite_config_string(original.to_string(), |mut ctx| {
+        assert!(ctx.step());
+        assert_eq!(
+            ctx.get::<Secprof>()?,
+            Secprof::Profile {
+                lan_access: 0,
+                lan_whitelist: None
+            }
+        );
+        let key = Secprof::WpaPassword {
+            password: "hunter3".into(),
+            profile: "guest".into(),
+        };
+        assert_eq!(
+            ctx.set(key).unwrap_err().to_string(),
+            "line 0 is not a wpapassword section"
+        );
+        ctx.set(Secprof::Profile {
+            lan_access: 1,
+            lan_whitelist: Some("iot".into()),
+        })?;
+
+        assert!(ctx.step());
+        assert!(
+            matches!(ctx.get()?, Secprof::WpaPassword { password, .. } if password == "hunter2")
+        );
+        assert!(ctx.step());
+        assert_eq!(
+            ctx.get::<Secprof>().unwrap_err().to_string(),
+            "unknown section type \"bogus\", expected one of: profile, wpapassword"
+        );
+        ctx.push(
+            Secprof::WpaPassword {
+                password: "hunter4".into(),
+                profile: "guest".into(),
+            },
+            Some("key2"),
+        )
+    })
+    .unwrap();
+    assert_eq!(
+        edited,
+        "config profile guest\n\toption lan_access 1\n\toption lan_whitelist iot\n\nconfig wpapassword key1\n\toption password hunter2\n\toption profile guest\n\nconfig bogus\n\nconfig wpapassword key2\n\toption password hunter4\n\toption profile guest\n"
+    );
+}
+
+#[test]
+fn test_bool_and_split_fields() {
+    let original = "config rule\n\toption enabled 'yes'\n\toption proto 'tcp udp'\n\nconfig rule\n\toption enabled off\n\tlist proto tcp\n\tlist proto udp\n\toption log 1\n";
+
+    #[derive(UciSection, Debug, PartialEq)]
+    struct Rule {
+        enabled: bool,
+        log: Option<bool>,
+        #[uci(split)]
+        proto: Vec<String>,
+    }
+
+    let edited = rewrite_config_string(original.to_string(), |mut ctx| {
+        let mut rules = Vec::new();
+        while ctx.step() {
+            let mut rule: Rule = ctx.get()?;
+            rules.push(Rule {
+                proto: rule.proto.clone(),
+                ..rule
+            });
+            rule.proto.push("icmp".into());
+            ctx.set(rule)?;
+        }
+        assert_eq!(
+            rules,
+            [
+                Rule {
+                    enabled: true,
+                    log: None,
+                    proto: vec!["tcp".into(), "udp".into()],
+                },
+                Rule {
+                    enabled: false,
+                    log: Some(true),
+                    proto: vec!["tcp".into(), "udp".into()],
+                },
+            ]
+        );
+        ctx.push(
+            Rule {
+                enabled: true,
+                log: Some(false),
+                proto: vec!["tcp".into()],
+            },
+            None::<&str>,
+        )
+    })
+    .unwrap();
+    assert_eq!(
+        edited,
+        "config rule\n\toption enabled 'yes'\n\toption proto 'tcp udp icmp'\n\nconfig rule\n\toption enabled off\n\tlist proto tcp\n\tlist proto udp\n\toption log 1\n\tlist proto icmp\n\nconfig rule\n\toption enabled 1\n\toption log 0\n\tlist proto tcp\n"
+    );
+
+    // split booleans are written as 1 and 0 in existing sections too, in either style
+    #[derive(UciSection, Debug, PartialEq)]
+    #[uci(ty = "schedule")]
+    struct Schedule {
+        #[uci(split)]
+        days: Vec<bool>,
+    }
+    let original = "config schedule\n\toption days 'yes no'\n\nconfig schedule\n\tlist days on\n";
+    let edited = rewrite_config_string(original.to_string(), |mut ctx| {
+        while ctx.step() {
+            let mut schedule: Schedule = ctx.get()?;
+            schedule.days.push(true);
+            ctx.set(schedule)?;
+        }
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        edited,
+        "config schedule\n\toption days '1 0 1'\n\nconfig schedule\n\tlist days 1\n\tlist days 1\n"
+    );
+}
+
+#[test]
+fn test_extra_options() {
+    let original = "config zone
+	option name 'lan'
+	option input 'ACCEPT'
+	list network 'lan'
+	list network \"guest\"
+	option mtu_fix 1
+";
+
+    #[derive(UciSection, Debug, PartialEq)]
+    struct Zone {
+        name: String,
+        #[uci(extra)]
+        extra: IndexMap<String, UciValue>,
+    }
+
+    #[derive(UciSection, Debug, PartialEq)]
+    #[uci(ty = "zone")]
+    struct OrderedZone {
+        name: String,
+        #[uci(flatten)]
+        extra: Vec<(String, UciValue)>,
+    }
+
+    let edited = rewrite_config_string(original.to_string(), |mut ctx| {
+        ctx.step();
+        let mut zone: Zone = ctx.get()?;
+        assert_eq!(
+            zone.extra,
+            IndexMap::from([
+                ("input".into(), UciValue::Option("ACCEPT".into())),
+                (
+                    "network".into(),
+                    UciValue::List(vec!["lan".into(), "guest".into()])
+                ),
+                ("mtu_fix".into(), UciValue::Option("1".into())),
+            ])
+        );
+        zone.extra.shift_remove("mtu_fix");
+        zone.extra.insert(
+            "network".into(),
+            UciValue::List(vec!["lan".into(), "iot".into(), "vpn".into()]),
+        );
+        zone.extra
+            .insert("forward".into(), UciValue::Option("REJECT".into()));
+        ctx.set(zone)?;
+
+        ctx.push(
+            OrderedZone {
+                name: "wan".into(),
+                extra: vec![
+                    ("output".into(), UciValue::Option("ACCEPT".into())),
+                    ("masq".into(), UciValue::Option("1".into())),
+                ],
+            },
+            None::<&str>,
+        )
+    })
+    .unwrap();
+    assert_eq!(
+        edited,
+        "config zone
+	option name lan
+	option input 'ACCEPT'
+	list network 'lan'
+	list network iot
+	list network vpn
+	option forward REJECT
+
+config zone
+	option name wan
+	option output ACCEPT
+	option masq 1
+"
+    );
+
+    // appending a copy keeps the options in the order they were read
+    let copied = rewrite_config_string(
+        "config zone\n\toption name lan\n\toption z 1\n\toption a 2\n".to_string(),
+        |mut ctx| {
+            ctx.step();
+            let zone: Zone = ctx.get()?;
+            ctx.push(zone, None::<&str>)
+        },
+    )
+    .unwrap();
+    assert_eq!(
+        copied,
+        "config zone\n\toption name lan\n\toption z 1\n\toption a 2\n\nconfig zone\n\toption name lan\n\toption z 1\n\toption a 2\n"
+    );
+}
+
+#[test]
+fn test_section_order() {
+    #[derive(UciSection)]
+    #[uci(ty = "rule")]
+    struct Rule {
+        name: String,
+    }
+
+    let original = "config defaults
+	option input 'REJECT'
+
+# written by hand
+config rule 'user'
+	option name 'user'
+
+config rule
+	option name 'ours'
+	# trailing
+
+config zone 'lan'
+	option name 'lan'
+";
+    let rule = |name: &str| Rule { name: name.into() };
+    let edited = rewrite_config_string(original.to_string(), |mut ctx| {
+        assert!(ctx.ty().is_err() && ctx.move_to(0).is_err());
+        assert!(ctx.step() && ctx.ty()? == "defaults");
+        assert!(ctx.step() && ctx.name()?.as_deref() == Some("user"));
+        assert!(ctx.step() && ctx.get_path("@rule[1].name")?.is_some());
+        ctx.move_before("user")?;
+        ctx.insert_before(rule("first"), None::<String>)?;
+        let id = ctx.id()?;
+        assert!(ctx.move_after(&id).is_err());
+        assert!(ctx.move_after("missing").is_err());
+
+        // the cursor moved along with the section
+        assert!(ctx.step() && ctx.name()?.as_deref() == Some("user"));
+        ctx.insert_after(rule("after user"), Some("later"))?;
+        assert!(ctx.rename(Some("lan")).is_err());
+        ctx.rename(Some("by_hand"))?;
+        assert!(ctx.step() && ctx.name()?.as_deref() == Some("later"));
+        assert!(ctx.step() && ctx.name()?.as_deref() == Some("lan"));
+        ctx.set_type("zone6")?;
+        ctx.rename(None)?;
+        ctx.move_to(0)?;
+        assert!(ctx.step() && ctx.ty()? == "defaults");
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(
+        edited,
+        "config zone6
+	option name 'lan'
+
+config defaults
+	option input 'REJECT'
+
+config rule
+	option name first
+
+config rule
+	option name 'ours'
+	# trailing
+
+# written by hand
+config rule by_hand
+	option name 'user'
+
+config rule later
+	option name 'after user'
+"
+    );
+}
+
+#[test]
+fn test_token_unescape() {
+    let cases = [
+        ("plain", "plain"),
+        ("'single quoted'", "single quoted"),
+        ("\"double quoted\"", "double quoted"),
+        (r"'it'\''s'", "it's"),
+        (r#""say \"hi\"""#, r#"say "hi""#),
+        (r#"'no \escapes "here"'"#, r#"no \escapes "here""#),
+        (r"back\ slash", "back slash"),
+        (r"\t\n", "tn"),
+        ("'multi\nline'", "multi\nline"),
+        ("\"multi\nline\"", "multi\nline"),
+        ("\"contin\\\nued\"", "continued"),
+        ("contin\\\nued", "continued"),
+        ("mixed'  '\"\\\"\"end", "mixed  \"end"),
+        ("''", ""),
+        ("'#;'", "#;"),
+    ];
+    for (raw, expected) in cases {
+        let token = Token::parse(raw).unwrap();
+        assert_eq!(token.as_str(), expected, "raw token {raw:?}");
+    }
+
+    assert!(Token::parse("'unterminated").is_err());
+    assert!(Token::parse("\"unterminated\\\"").is_err());
+    assert!(Token::parse("two words").is_err());
+}
+
+#[test]
+fn test_parse_statements() {
+    let original = "config rule 'a rule' # trailing\n\toption name 'multi\nline'; option src lan\n\toption dest wan#comment\n\n\tlist proto \"tc\"\\\n'p' # kept\n";
+    let lines = parse_lines(original).unwrap();
+    assert_eq!(
+        format_lines(&lines),
+        "config rule 'a rule' # trailing\n\toption name 'multi\nline'\n\toption src lan\n\toption dest wan#comment\n\n\tlist proto \"tc\"\\\n'p' # kept\n"
+    );
+    let Line::List { item, .. } = &lines[7] else {
+        panic!("expected a list line");
+    };
+    assert_eq!(item.as_str(), "tcp");
+
+    assert!(parse_lines("option a 'b").is_err());
+    assert!(parse_lines("option a b c").is_err());
+    assert!(parse_lines("garbage here").is_err());
+}
+
+#[test]
+fn test_trailing_comments() {
+    let original = "# zones\nconfig zone lan # the lan\n\toption name lan\n\toption mtu 1400   # for the VPN  \n\toption log 1 # logging\n\toption input ACCEPT #\n";
+    let untouched = rewrite_config_string(original.to_string(), |_| Ok(())).unwrap();
+    assert_eq!(untouched, original);
+
+    let edited = rewrite_config_string(original.to_string(), |mut ctx| {
+        ctx.set_path("lan.input", "REJECT")?;
+        ctx.delete_path("lan.log")?;
+        Ok(())
+    })
+    .unwrap();
+    // an edited option keeps its comment, a deleted one leaves it behind
+    assert_eq!(
+        edited,
+        "# zones\nconfig zone lan # the lan\n\toption name lan\n\toption mtu 1400   # for the VPN  \n\t# logging\n\toption input REJECT #\n"
+    );
+}
+
+#[test]
+fn test_parse_errors() {
+    let err = |config| parse_lines(config).map(|_| ()).unwrap_err();
+
+    let unterminated = err("config secprof\n\toption name 'oops\n\toption x y\n");
+    assert_eq!(unterminated.kind, ErrorKind::UnterminatedQuote('\''));
+    assert_eq!((unterminated.line, unterminated.column), (2, 14));
+    assert_eq!(
+        unterminated.with_path("/etc/config/secprof").to_string(),
+        "error: unterminated ' quote\n --> /etc/config/secprof:2:14\n  |\n2 | \toption name 'oops\n  | \t            ^"
+    );
+
+    let unknown = err("config a\n\n  optoin é b\n");
+    assert_eq!(unknown.kind, ErrorKind::UnknownKeyword("optoin".into()));
+    assert_eq!((unknown.line, unknown.column, unknown.len), (3, 3, 6));
+    let extra = err("config a\n\toption é b c\n");
+    assert_eq!(extra.kind, ErrorKind::TooManyArguments("option"));
+    assert_eq!((extra.line, extra.column), (2, 13));
+    assert_eq!(
+        err("option a b\n").kind,
+        ErrorKind::OptionOutsideSection("option")
+    );
+    assert_eq!(
+        err("config a\npackage b\nlist c d\n").kind,
+        ErrorKind::OptionOutsideSection("list")
+    );
+    assert_eq!(err("config\n").kind, ErrorKind::MissingSectionType);
+    assert!(matches!(
+        Line::parse("option a b; option c d"),
+        Err(ParseError {
+            kind: ErrorKind::MultipleStatements,
+            ..
+        })
+    ));
+    assert!(Line::parse("option a b").is_ok());
+
+    let wrapped = parse_config_string("config a\n\tlist", |_| Ok(())).unwrap_err();
+    assert_eq!(
+        wrapped.downcast_ref::<ParseError>().unwrap().kind,
+        ErrorKind::MissingName("list")
+    );
+}
+
+#[test]
+fn test_quote_round_trip() {
+    let alphabet = [
+        "a", "Z", "0", " ", "\t", "\n", "\r", "'", "\"", "\\", "#", ";", "$", "é", "😀", "\x0b",
+    ];
+    let mut corpus: Vec<String> = vec![
+        String::new(),
+        "Reject LAN to WAN for custom IP".into(),
+        "it's".into(),
+        "'''".into(),
+        "\\'\\".into(),
+        "ends with backslash\\".into(),
+        "\\\n".into(),
+        "\\\r\n".into(),
+        "# not a comment".into(),
+        "a;b;c".into(),
+        "tab\tand\nnewline".into(),
+        "\u{1F600}\u{200B}\u{FEFF}".into(),
+        "192.168.1.1/24".into(),
+        "00:11:22:33:44:55".into(),
+    ];
+    for a in alphabet {
+        corpus.push(a.into());
+        for b in alphabet {
+            corpus.push(format!("{a}{b}"));
+            for c in alphabet {
+                corpus.push(format!("{a}{b}{c}"));
+            }
+        }
+    }
+    let mut seed = 0x2545_f491_4f6c_dd1du64;
+    for _ in 0..2000 {
+        let mut s = String::new();
+        for _ in 0..seed % 24 {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            s.push_str(alphabet[(seed % alphabet.len() as u64) as usize]);
+        }
+        corpus.push(s);
+    }
+
+    let arena = Arena::new();
+    for value in &corpus {
+        let lines = [
+            Line::Section {
+                ty: Token::from_str("test", &arena),
+                name: Some(Token::from_string(value.clone(), &arena)),
+            },
+            Line::Option {
+                option: Token::from_str("value", &arena),
+                value: Token::from_string(value.clone(), &arena),
+            },
+            Line::List {
+                list: Token::from_str("items", &arena),
+                item: Token::from_str(value, &arena),
+            },
+        ];
+        let written: String = lines.iter().map(|l| l.to_string()).collect();
+        let parsed = parse_lines(&written).unwrap_or_else(|e| panic!("{value:?}: {e:?}"));
+        let [Line::Section {
+            name: Some(name), ..
+        }, Line::Option { value: option, .. }, Line::List { item, .. }] = &parsed[..]
+        else {
+            panic!("{value:?} was written as {written:?}");
+        };
+        assert_eq!(&name.as_str(), value, "written as {written:?}");
+        assert_eq!(&option.as_str(), value, "written as {written:?}");
+        assert_eq!(&item.as_str(), value, "written as {written:?}");
+    }
+}
+
+#[test]
+fn test_field_attributes() {
+    let original = "config redirect\n\toption src-ip '10.0.0.2'\n\toption note 'kept'\n";
+
+    #[derive(UciSection, Debug, PartialEq)]
+    struct Redirect {
+        #[uci(rename = "src-ip")]
+        src_ip: String,
+        #[uci(default)]
+        reflection: bool,
+        #[uci(default = "wan")]
+        src: String,
+        #[uci(default = 8080)]
+        dest_port: u16,
+        #[uci(inpt)]
+        weight: Option<i32>,
+        #[uci(skip)]
+        note: String,
+    }
+
+    let edited = rewrite_config_string(original.to_string(), |mut ctx| {
+        assert!(ctx.step());
+        let mut redirect: Redirect = ctx.get()?;
+        assert_eq!(
+            redirect,
+            Redirect {
+                src_ip: "10.0.0.2".into(),
+                reflection: false,
+                src: "wan".into(),
+                dest_port: 8080,
+                weight: None,
+                note: String::new(),
+            }
+        );
+        redirect.weight = Some(3);
+        redirect.note = "ignored".into();
+        ctx.set(redirect)
+    })
+    .unwrap();
+    assert_eq!(
+        edited,
+        "config redirect\n\toption src-ip 10.0.0.2\n\toption note 'kept'\n\toption reflection 0\n\toption src wan\n\toption dest_port 8080\n\toption weight 3\n"
+    );
+
+    let parsed: Redirect = parse_config_string(
+        "config redirect\n\toption src-ip x\n\toption weight ' 7 '\n",
+        |mut ctx| {
+            assert!(ctx.step());
+            ctx.get()
+        },
+    )
+    .unwrap();
+    assert_eq!(parsed.weight, Some(7));
+}
+
+#[cfg(test)]
+fn temp_config(name: &str, contents: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("uciedit-{}-{name}", std::process::id()));
+    fs::write(&path, contents).unwrap();
+    path
+}
+
+#[cfg(all(test, feature = "tokio"))]
+#[tokio::test]
+async fn test_rewrite_config_async() {
+    let original = "\n# comment\nconfig rule\n\toption name 'keep me'\n\toption target ACCEPT\n\nconfig rule\n\toption name other\n";
+
+    #[derive(UciSection)]
+    struct Rule {
+        name: String,
+        target: Option<String>,
+    }
+
+    let edit = |mut ctx: SectionsMut| {
+        let mut names = Vec::new();
+        while ctx.step() {
+            let mut rule: Rule = ctx.get()?;
+            rule.target = Some("REJECT".into());
+            names.push(rule.name.clone());
+            ctx.set(rule)?;
+        }
+        Ok(names)
+    };
+
+    let sync_path = temp_config("rewrite-sync", original);
+    let async_path = temp_config("rewrite-async", original);
+    let sync_names = rewrite_config(&sync_path, edit).unwrap();
+    let async_names = rewrite_config_async(&async_path, std::time::Duration::from_secs(1), edit)
+        .await
+        .unwrap();
+    assert_eq!(sync_names, async_names);
+    assert_eq!(
+        fs::read_to_string(&sync_path).unwrap(),
+        fs::read_to_string(&async_path).unwrap()
+    );
+
+    let sync_parsed = parse_config(&sync_path, |mut ctx| Ok(ctx.step())).unwrap();
+    let async_parsed = parse_config_async(&async_path, |mut ctx| Ok(ctx.step()))
+        .await
+        .unwrap();
+    assert_eq!(sync_parsed, async_parsed);
+
+    // Another editor holding the lock makes us time out instead of hanging
+    let held = fd_lock_rs::FdLock::lock(
+        File::open(&async_path).unwrap(),
+        fd_lock_rs::LockType::Exclusive,
+        true,
+    )
+    .unwrap();
+    let timed_out =
+        rewrite_config_async(
+            &async_path,
+            std::time::Duration::from_millis(50),
+            |_| Ok(()),
+        )
+        .await;
+    assert!(timed_out.is_err());
+    drop(held);
+    rewrite_config_async(&async_path, std::time::Duration::from_secs(1), |_| Ok(()))
+        .await
+        .unwrap();
+
+    fs::remove_file(sync_path).unwrap();
+    fs::remove_file(async_path).unwrap();
+}
+
+#[test]
+fn test_rewrite_config_atomic() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let path = temp_config("rewrite-atomic", "config counter\n\toption count 0\n");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+    // held open so that the filesystem can't hand its inode number to one of the new files
+    let original = File::open(&path).unwrap();
+    let inode = original.metadata().unwrap().ino();
+
+    #[derive(UciSection)]
+    struct Counter {
+        count: u32,
+    }
+
+    // unchanged content is never rewritten
+    rewrite_config(&path, |mut ctx| {
+        assert!(ctx.step());
+        ctx.set(ctx.get::<Counter>()?)
+    })
+    .unwrap();
+    assert_eq!(fs::metadata(&path).unwrap().ino(), inode);
+
+    // concurrent editors stay mutually exclusive across the renames
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                for _ in 0..25 {
+                    rewrite_config(&path, |mut ctx| {
+                        assert!(ctx.step());
+                        let Counter { count } = ctx.get()?;
+                        ctx.set(Counter { count: count + 1 })
+                    })
+                    .unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let meta = fs::metadata(&path).unwrap();
+    assert_ne!(meta.ino(), inode);
+    let mut old = String::new();
+    (&original).read_to_string(&mut old).unwrap();
+    assert_eq!(old, "config counter\n\toption count 0\n");
+    assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+    assert_eq!(
+        fs::read_to_string(&path).unwrap(),
+        "config counter\n\toption count 100\n"
+    );
+
+    let prefix = format!(".{}.uciedit-", path.file_name().unwrap().to_string_lossy());
+    let leftovers = fs::read_dir(path.parent().unwrap())
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with(&prefix)
+        })
+        .count();
+    assert_eq!(leftovers, 0);
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_patch() {
+    #[derive(UciSection)]
+    struct Rule {
+        name: Option<String>,
+        #[uci(split)]
+        proto: Vec<String>,
+        enabled: bool,
+        target: String,
+    }
+
+    let config = "config rule\n\toption name 'mine'\n\toption proto 'tcp udp'\n\toption enabled yes\n\toption src_ip '10.0.0.1'\n\toption target 'DROP'\n";
+    let patched = rewrite_config_string(config.to_owned(), |mut ctx| {
+        assert!(ctx.step());
+        ctx.patch(RulePatch {
+            enabled: Some(Some(true)),
+            target: Some(Some("DROP".into())),
+            ..Default::default()
+        })?;
+        let unchanged = ctx.get::<Rule>()?;
+        assert_eq!(unchanged.name.as_deref(), Some("mine"));
+        ctx.patch(RulePatch {
+            name: Some(None),
+            proto: Some(vec!["icmp".into()]),
+            target: Some(Some("ACCEPT".into())),
+            ..Default::default()
+        })
+    })
+    .unwrap();
+    assert_eq!(
+        patched,
+        "config rule\n\toption proto icmp\n\toption enabled yes\n\toption src_ip '10.0.0.1'\n\toption target ACCEPT\n"
+    );
+
+    let err = rewrite_config_string("config zone\n".to_owned(), |mut ctx| {
+        assert!(ctx.step());
+        ctx.patch(RulePatch::default())
+    });
+    assert!(err.is_err());
+}
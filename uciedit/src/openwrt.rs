@@ -1,38 +1,25 @@
-use uciedit_macros::UciSection;
+//! Typed sections of the configs OpenWrt ships. Options with a documented default are
+//! `Option`s, so that writing a section back doesn't spell out every default; their getters of
+//! the same name fall back to it.
 
-#[derive(strum::EnumString, strum::Display, Default, PartialEq, Eq)]
-pub enum FirewallTarget {
-    #[default]
-    ACCEPT,
-    REJECT,
-    DROP,
-    MARK,
-    NOTRACK,
-}
+pub use firewall::{FirewallRule, FirewallRulePatch, FirewallTarget};
 
-#[derive(UciSection, Default)]
-#[uci(ty = "rule")]
-pub struct FirewallRule {
-    /*
-    option	name		'Reject LAN to WAN for custom IP'
-    option	src		'lan'
-    option	src_ip		'192.168.1.2'
-    option	src_mac		'00:11:22:33:44:55'
-    option	src_port	'80'
-    option	dest		'wan'
-    option	dest_ip		'194.25.2.129'
-    option	dest_port	'120'
-    option	proto		'tcp'
-    option	target		'REJECT'
-    */
-    pub name: String,
-    pub src: String,
-    pub src_ip: Option<String>,
-    pub src_mac: Option<String>,
-    pub src_port: Option<String>,
-    pub dest: String,
-    pub dest_ip: Option<String>,
-    pub dest_port: Option<String>,
-    pub proto: Option<String>,
-    pub target: FirewallTarget,
+/// Defines getters that fall back to the documented default of an unset option.
+macro_rules! defaults {
+    ($section:ty { $($option:ident: $ty:ty = $default:expr),* $(,)? }) => {
+        impl $section {
+            $(
+                #[doc = concat!("`", stringify!($option), "`, `", stringify!($default), "` unless set.")]
+                pub fn $option(&self) -> $ty {
+                    self.$option.clone().unwrap_or($default)
+                }
+            )*
+        }
+    };
 }
+
+pub mod dhcp;
+pub mod firewall;
+pub mod network;
+pub mod types;
+pub mod wireless;
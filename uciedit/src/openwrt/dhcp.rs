@@ -0,0 +1,445 @@
+//! `/etc/config/dhcp`: dnsmasq's DNS and DHCP service, its address pools and static leases.
+//! The `odhcpd` settings in the same file are left alone.
+
+use super::types::{Hostname, IpMask, MacAddr};
+use crate::{bail, Error, Sections, SectionsMut};
+use eyre::Context;
+use std::fmt::{self, Display};
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+use uciedit_macros::UciSection;
+
+pub const PATH: &str = "/etc/config/dhcp";
+
+/// A dnsmasq instance. OpenWrt ships one anonymous section.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "dnsmasq")]
+pub struct Dnsmasq {
+    pub domainneeded: Option<bool>,
+    pub boguspriv: Option<bool>,
+    pub localise_queries: Option<bool>,
+    pub rebind_protection: Option<bool>,
+    pub rebind_localhost: Option<bool>,
+    /// the domain only answered locally, like `/lan/`
+    pub local: Option<String>,
+    pub domain: Option<String>,
+    pub expandhosts: Option<bool>,
+    pub authoritative: Option<bool>,
+    pub readethers: Option<bool>,
+    pub leasefile: Option<String>,
+    pub resolvfile: Option<String>,
+    pub noresolv: Option<bool>,
+    pub localservice: Option<bool>,
+    pub port: Option<u16>,
+    pub cachesize: Option<u32>,
+    pub logqueries: Option<bool>,
+    /// upstream servers, like `8.8.8.8` or `/example.com/10.0.0.1`
+    #[uci(split)]
+    pub server: Vec<String>,
+    /// fixed answers, like `/example.com/10.0.0.1`
+    #[uci(split)]
+    pub address: Vec<String>,
+    #[uci(split)]
+    pub interface: Vec<String>,
+    #[uci(split)]
+    pub notinterface: Vec<String>,
+}
+
+defaults!(Dnsmasq {
+    domainneeded: bool = false,
+    boguspriv: bool = false,
+    localise_queries: bool = false,
+    rebind_protection: bool = false,
+    rebind_localhost: bool = false,
+    expandhosts: bool = false,
+    authoritative: bool = false,
+    readethers: bool = false,
+    noresolv: bool = false,
+    localservice: bool = false,
+    port: u16 = 53,
+    cachesize: u32 = 150,
+    logqueries: bool = false,
+});
+
+/// The address pool of an interface, usually named after it.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "dhcp")]
+pub struct Dhcp {
+    /// the logical interface in /etc/config/network
+    pub interface: String,
+    /// the first address as an offset from the network address
+    pub start: Option<u32>,
+    /// the number of addresses in the pool
+    pub limit: Option<u32>,
+    /// like `12h` or `infinite`
+    pub leasetime: Option<String>,
+    /// don't serve DHCP on the interface
+    pub ignore: Option<bool>,
+    pub force: Option<bool>,
+    pub dynamicdhcp: Option<bool>,
+    pub netmask: Option<Ipv4Addr>,
+    /// `server`, `relay`, `hybrid` or `disabled`, for odhcpd
+    pub dhcpv4: Option<String>,
+    pub dhcpv6: Option<String>,
+    pub ra: Option<String>,
+    /// options like `6,192.168.1.2` or `option:dns-server,192.168.1.2`
+    #[uci(split)]
+    pub dhcp_option: Vec<String>,
+    #[uci(split)]
+    pub dhcp_option_force: Vec<String>,
+    #[uci(split)]
+    pub tag: Vec<String>,
+}
+
+defaults!(Dhcp {
+    start: u32 = 100,
+    limit: u32 = 150,
+    leasetime: String = "12h".into(),
+    ignore: bool = false,
+    force: bool = false,
+    dynamicdhcp: bool = true,
+});
+
+impl Dhcp {
+    /// The first and last address the pool hands out in `subnet`, the way dnsmasq's init script
+    /// computes them: `start` counts from the network address and the pool ends before the
+    /// broadcast address. `None` if it doesn't fit in the subnet at all.
+    pub fn range(&self, subnet: IpMask) -> Option<(Ipv4Addr, Ipv4Addr)> {
+        let IpAddr::V4(network) = subnet.network().addr else {
+            return None;
+        };
+        let network = u32::from(network);
+        let size = 1u32
+            .checked_shl(32 - u32::from(subnet.prefix()))
+            .unwrap_or(0);
+        let last_host = network.wrapping_add(size).wrapping_sub(2);
+        let first = network.checked_add(self.start())?;
+        let last = first
+            .checked_add(self.limit().checked_sub(1)?)?
+            .min(last_host);
+        (first <= last).then(|| (first.into(), last.into()))
+    }
+}
+
+/// A host's `ip`: a static address, or `ignore` to never answer it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HostIp {
+    Ignore,
+    Addr(Ipv4Addr),
+}
+
+impl FromStr for HostIp {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        if s == "ignore" {
+            return Ok(HostIp::Ignore);
+        }
+        match s.parse() {
+            Ok(addr) => Ok(HostIp::Addr(addr)),
+            Err(_) => bail!("{s:?} is not an IPv4 address or ignore"),
+        }
+    }
+}
+
+impl Display for HostIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostIp::Ignore => f.write_str("ignore"),
+            HostIp::Addr(addr) => addr.fmt(f),
+        }
+    }
+}
+
+impl From<Ipv4Addr> for HostIp {
+    fn from(addr: Ipv4Addr) -> Self {
+        HostIp::Addr(addr)
+    }
+}
+
+/// A static lease, or a name and tags for devices that get a dynamic one.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "host")]
+pub struct Host {
+    pub name: Option<Hostname>,
+    /// the MAC addresses of one device, like its wired and wireless ones
+    #[uci(split)]
+    pub mac: Vec<MacAddr>,
+    pub ip: Option<HostIp>,
+    pub leasetime: Option<String>,
+    /// the [`Tag`] sections whose options the host gets
+    #[uci(split)]
+    pub tag: Vec<String>,
+    /// the IPv6 interface identifier, in hex
+    pub hostid: Option<String>,
+    #[uci(split)]
+    pub duid: Vec<String>,
+    /// also answer DNS queries for `name`
+    pub dns: Option<bool>,
+    pub broadcast: Option<bool>,
+}
+
+defaults!(Host {
+    dns: bool = false,
+    broadcast: bool = false,
+});
+
+impl Host {
+    pub fn has_mac(&self, mac: &MacAddr) -> bool {
+        self.mac.contains(mac)
+    }
+}
+
+/// A DNS name answered with a fixed address.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "domain")]
+pub struct Domain {
+    pub name: String,
+    pub ip: Option<IpAddr>,
+}
+
+/// DHCP options for the hosts and pools tagged with the section name.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "tag")]
+pub struct Tag {
+    #[uci(split)]
+    pub dhcp_option: Vec<String>,
+    pub force: Option<bool>,
+}
+
+defaults!(Tag {
+    force: bool = false,
+});
+
+/// A network boot server.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "boot")]
+pub struct Boot {
+    pub filename: String,
+    pub serveraddress: Option<Ipv4Addr>,
+    pub servername: Option<String>,
+    /// only boot hosts with this tag
+    pub networkid: Option<String>,
+    #[uci(split)]
+    pub dhcp_option: Vec<String>,
+    pub force: Option<bool>,
+}
+
+defaults!(Boot {
+    force: bool = false,
+});
+
+/// The whole dhcp config. Sections of other types, like `odhcpd`, are ignored.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct DhcpConfig {
+    pub dnsmasq: Vec<Dnsmasq>,
+    /// the pools by section name
+    pub pools: Vec<(String, Dhcp)>,
+    /// the hosts by section name, which is `cfgXXXXXX` for anonymous ones
+    pub hosts: Vec<(String, Host)>,
+    pub domains: Vec<Domain>,
+    /// the tags by section name, which is the tag
+    pub tags: Vec<(String, Tag)>,
+    pub boots: Vec<Boot>,
+}
+
+impl DhcpConfig {
+    pub fn read(sections: Sections) -> Result<Self, Error> {
+        let mut config = DhcpConfig::default();
+        for section in sections.iter() {
+            let mut read = || -> Result<(), Error> {
+                match &*section.ty() {
+                    "dnsmasq" => config.dnsmasq.push(section.get()?),
+                    "dhcp" => config.pools.push((section.id(), section.get()?)),
+                    "host" => config.hosts.push((section.id(), section.get()?)),
+                    "domain" => config.domains.push(section.get()?),
+                    "tag" => config.tags.push((section.id(), section.get()?)),
+                    "boot" => config.boots.push(section.get()?),
+                    _ => (),
+                }
+                Ok(())
+            };
+            read().wrap_err_with(|| format!("reading dhcp section {}", section.id()))?;
+        }
+        Ok(config)
+    }
+
+    /// The pool of the logical interface `interface`.
+    pub fn pool(&self, interface: &str) -> Option<&Dhcp> {
+        self.pools
+            .iter()
+            .find_map(|(_, pool)| (pool.interface == interface).then_some(pool))
+    }
+
+    pub fn host(&self, name: &str) -> Option<&Host> {
+        self.hosts
+            .iter()
+            .find_map(|(n, host)| (n == name).then_some(host))
+    }
+
+    /// The first host listing `mac`, which is the one dnsmasq uses.
+    pub fn host_by_mac(&self, mac: &MacAddr) -> Option<&Host> {
+        self.hosts
+            .iter()
+            .find_map(|(_, host)| host.has_mac(mac).then_some(host))
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&Tag> {
+        self.tags
+            .iter()
+            .find_map(|(n, tag)| (n == name).then_some(tag))
+    }
+}
+
+/// Makes `host` the static lease of the device with its MAC addresses, without leaving a second
+/// `host` section behind for them. The first `host` section listing any of them becomes `host`,
+/// keeping the other MACs it lists and any options `Host` doesn't model. Later sections have
+/// the MACs taken out, and are removed if they list no others. Without a match, `host` is
+/// appended as an anonymous section. Steps `sections` to the end.
+pub fn upsert_host(sections: &mut SectionsMut, host: Host) -> Result<(), Error> {
+    let mut found = false;
+    while let Some(mut section) = sections.next_of::<Host>() {
+        let mut existing: Host = section
+            .get()
+            .wrap_err_with(|| format!("reading dhcp section {}", section.id()))?;
+        if !existing.mac.iter().any(|mac| host.has_mac(mac)) {
+            continue;
+        }
+        if found {
+            existing.mac.retain(|mac| !host.has_mac(mac));
+            if existing.mac.is_empty() {
+                section.remove();
+            } else {
+                section.set(existing)?;
+            }
+            continue;
+        }
+        found = true;
+        let mut mac = existing.mac;
+        for new in &host.mac {
+            if !mac.contains(new) {
+                mac.push(*new);
+            }
+        }
+        section.set(Host {
+            mac,
+            ..host.clone()
+        })?;
+    }
+    if !found {
+        sections.push(host, None::<String>)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn test_dhcp_config() {
+    use crate::{parse_config_string, rewrite_config_string};
+
+    let config = "
+config dnsmasq
+	option domainneeded '1'
+	option local '/lan/'
+	option domain 'lan'
+	option authoritative '1'
+	option rebind_protection '1'
+	list server '/example.com/10.0.0.1'
+
+config dhcp 'lan'
+	option interface 'lan'
+	option start '100'
+	option limit '150'
+	option leasetime '12h'
+	list dhcp_option '6,192.168.1.2'
+
+config dhcp 'wan'
+	option interface 'wan'
+	option ignore '1'
+
+config host
+	option name 'printer'
+	option mac '00:11:22:33:44:55 00:11:22:33:44:56'
+	option ip '192.168.1.20'
+	option tag 'iot'
+	option comment 'by the door'
+
+config host
+	option name 'printer-wifi'
+	option mac '00:11:22:33:44:56'
+	option ip '192.168.1.21'
+
+config host
+	option mac '66:77:88:99:aa:bb'
+	option ip 'ignore'
+
+config domain
+	option name 'nas.lan'
+	option ip '192.168.1.5'
+
+config tag 'iot'
+	list dhcp_option '3'
+
+config boot
+	option filename 'pxelinux.0'
+	option serveraddress '192.168.1.5'
+
+config odhcpd 'odhcpd'
+	option maindhcp '0'
+";
+    let dhcp = parse_config_string(config, DhcpConfig::read).unwrap();
+    assert!(dhcp.dnsmasq[0].authoritative());
+    assert_eq!(dhcp.dnsmasq[0].port(), 53);
+    let lan = dhcp.pool("lan").unwrap();
+    assert_eq!(
+        lan.range("192.168.1.1/24".parse().unwrap()),
+        Some((
+            Ipv4Addr::new(192, 168, 1, 100),
+            Ipv4Addr::new(192, 168, 1, 249)
+        ))
+    );
+    assert_eq!(
+        lan.range("10.0.0.1/25".parse().unwrap()),
+        Some((Ipv4Addr::new(10, 0, 0, 100), Ipv4Addr::new(10, 0, 0, 126)))
+    );
+    assert!(dhcp.pool("wan").unwrap().ignore());
+    let printer_mac = "00:11:22:33:44:56".parse().unwrap();
+    let printer = dhcp.host_by_mac(&printer_mac).unwrap();
+    assert_eq!(printer.name.as_ref().unwrap().as_str(), "printer");
+    assert_eq!(printer.tag, ["iot"]);
+    assert_eq!(dhcp.hosts[2].1.ip, Some(HostIp::Ignore));
+    assert_eq!(dhcp.tag("iot").unwrap().dhcp_option, ["3"]);
+    assert_eq!(dhcp.boots[0].filename, "pxelinux.0");
+
+    let upsert = |config: &str, host: Host| {
+        rewrite_config_string(config.to_string(), |mut ctx| upsert_host(&mut ctx, host)).unwrap()
+    };
+    let moved = upsert(
+        config,
+        Host {
+            name: Some("printer".parse().unwrap()),
+            mac: vec![printer_mac],
+            ip: Some(Ipv4Addr::new(192, 168, 1, 30).into()),
+            ..Default::default()
+        },
+    );
+    let dhcp = parse_config_string(&moved, DhcpConfig::read).unwrap();
+    assert_eq!(dhcp.hosts.len(), 2);
+    let printer = dhcp.host_by_mac(&printer_mac).unwrap();
+    assert_eq!(printer.mac.len(), 2);
+    assert_eq!(printer.ip, Some(Ipv4Addr::new(192, 168, 1, 30).into()));
+    assert!(printer.tag.is_empty());
+    assert!(moved.contains("option comment 'by the door'"));
+    assert!(!moved.contains("printer-wifi"));
+
+    let camera_mac = "aa:bb:cc:dd:ee:ff".parse().unwrap();
+    let camera = Host {
+        name: Some("camera".parse().unwrap()),
+        mac: vec![camera_mac],
+        ip: Some(Ipv4Addr::new(192, 168, 1, 40).into()),
+        ..Default::default()
+    };
+    let added = upsert(&moved, camera.clone());
+    assert_eq!(upsert(&added, camera.clone()), added);
+    let dhcp = parse_config_string(&added, DhcpConfig::read).unwrap();
+    assert_eq!(dhcp.host_by_mac(&camera_mac), Some(&camera));
+}
@@ -0,0 +1,668 @@
+//! `/etc/config/firewall`, as fw3 and fw4 read it.
+
+use super::types::{IpMask, MacAddr, Neg, PortRange};
+use crate::{bail, Error, Sections};
+use eyre::Context;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use uciedit_macros::UciSection;
+
+pub const PATH: &str = "/etc/config/firewall";
+
+/// What happens to the packets a rule matches.
+#[derive(strum::EnumString, strum::Display, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FirewallTarget {
+    ACCEPT,
+    /// fw3 rejects when a rule has no target
+    #[default]
+    REJECT,
+    DROP,
+    MARK,
+    NOTRACK,
+    HELPER,
+    DSCP,
+}
+
+/// The policy of a zone or of the defaults section.
+#[derive(strum::EnumString, strum::Display, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    ACCEPT,
+    REJECT,
+    DROP,
+}
+
+#[derive(strum::EnumString, strum::Display, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RedirectTarget {
+    #[default]
+    DNAT,
+    SNAT,
+}
+
+#[derive(strum::EnumString, strum::Display, Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NatTarget {
+    #[default]
+    MASQUERADE,
+    SNAT,
+    DNAT,
+    ACCEPT,
+}
+
+#[derive(strum::EnumString, strum::Display, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum Family {
+    #[default]
+    Any,
+    Ipv4,
+    Ipv6,
+}
+
+#[derive(strum::EnumString, strum::Display, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum IncludeType {
+    #[default]
+    Script,
+    Restore,
+    Nftables,
+}
+
+#[derive(strum::EnumString, strum::Display, Clone, Copy, Debug, PartialEq, Eq)]
+#[strum(ascii_case_insensitive)]
+pub enum Weekday {
+    #[strum(to_string = "Mon", serialize = "Monday", serialize = "1")]
+    Mon,
+    #[strum(to_string = "Tue", serialize = "Tuesday", serialize = "2")]
+    Tue,
+    #[strum(to_string = "Wed", serialize = "Wednesday", serialize = "3")]
+    Wed,
+    #[strum(to_string = "Thu", serialize = "Thursday", serialize = "4")]
+    Thu,
+    #[strum(to_string = "Fri", serialize = "Friday", serialize = "5")]
+    Fri,
+    #[strum(to_string = "Sat", serialize = "Saturday", serialize = "6")]
+    Sat,
+    #[strum(to_string = "Sun", serialize = "Sunday", serialize = "7")]
+    Sun,
+}
+
+/// The space separated days of `weekdays`, which [`Neg`] turns into "all but these".
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Weekdays(pub Vec<Weekday>);
+
+impl FromStr for Weekdays {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let days = s
+            .split_whitespace()
+            .map(|day| {
+                day.parse()
+                    .wrap_err_with(|| format!("{day:?} is not a weekday"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if days.is_empty() {
+            bail!("no weekdays in {s:?}");
+        }
+        Ok(Weekdays(days))
+    }
+}
+
+impl Display for Weekdays {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, day) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            day.fmt(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// `start_time` and `stop_time`: `hh:mm` or `hh:mm:ss`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: Option<u8>,
+}
+
+impl FromStr for TimeOfDay {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut parts = s.split(':');
+        let mut part = |max: u8| -> Result<Option<u8>, Error> {
+            let Some(part) = parts.next() else {
+                return Ok(None);
+            };
+            match part.parse() {
+                Ok(n) if n <= max && part.len() <= 2 => Ok(Some(n)),
+                _ => bail!("{s:?} is not a time like 14:30 or 14:30:00"),
+            }
+        };
+        let (Some(hour), Some(minute)) = (part(23)?, part(59)?) else {
+            bail!("{s:?} is not a time like 14:30 or 14:30:00");
+        };
+        let second = part(59)?;
+        if parts.next().is_some() {
+            bail!("{s:?} is not a time like 14:30 or 14:30:00");
+        }
+        Ok(TimeOfDay {
+            hour,
+            minute,
+            second,
+        })
+    }
+}
+
+impl Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)?;
+        if let Some(second) = self.second {
+            write!(f, ":{second:02}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The global settings. fw3 only reads the first `defaults` section.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "defaults")]
+pub struct Defaults {
+    pub input: Option<Policy>,
+    pub output: Option<Policy>,
+    pub forward: Option<Policy>,
+    pub syn_flood: Option<bool>,
+    pub synflood_rate: Option<String>,
+    pub synflood_burst: Option<u32>,
+    pub drop_invalid: Option<bool>,
+    pub tcp_syncookies: Option<bool>,
+    pub tcp_window_scaling: Option<bool>,
+    pub accept_redirects: Option<bool>,
+    pub accept_source_route: Option<bool>,
+    pub custom_chains: Option<bool>,
+    pub disable_ipv6: Option<bool>,
+    pub flow_offloading: Option<bool>,
+    pub flow_offloading_hw: Option<bool>,
+    pub auto_helper: Option<bool>,
+}
+
+defaults!(Defaults {
+    input: Policy = Policy::REJECT,
+    output: Policy = Policy::REJECT,
+    forward: Policy = Policy::REJECT,
+    syn_flood: bool = false,
+    synflood_rate: String = "25/s".into(),
+    synflood_burst: u32 = 50,
+    drop_invalid: bool = false,
+    tcp_syncookies: bool = true,
+    tcp_window_scaling: bool = true,
+    accept_redirects: bool = false,
+    accept_source_route: bool = false,
+    custom_chains: bool = true,
+    disable_ipv6: bool = false,
+    flow_offloading: bool = false,
+    flow_offloading_hw: bool = false,
+    auto_helper: bool = true,
+});
+
+/// A group of interfaces that share a policy.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "zone")]
+pub struct Zone {
+    pub name: String,
+    #[uci(split)]
+    pub network: Vec<String>,
+    #[uci(split)]
+    pub device: Vec<String>,
+    #[uci(split)]
+    pub subnet: Vec<Neg<IpMask>>,
+    pub input: Option<Policy>,
+    pub output: Option<Policy>,
+    pub forward: Option<Policy>,
+    pub family: Option<Family>,
+    pub masq: Option<bool>,
+    pub masq6: Option<bool>,
+    #[uci(split)]
+    pub masq_src: Vec<Neg<IpMask>>,
+    #[uci(split)]
+    pub masq_dest: Vec<Neg<IpMask>>,
+    pub masq_allow_invalid: Option<bool>,
+    pub mtu_fix: Option<bool>,
+    pub log: Option<bool>,
+    pub log_limit: Option<String>,
+    pub extra_src: Option<String>,
+    pub extra_dest: Option<String>,
+}
+
+defaults!(Zone {
+    input: Policy = Policy::DROP,
+    output: Policy = Policy::DROP,
+    forward: Policy = Policy::DROP,
+    family: Family = Family::Any,
+    masq: bool = false,
+    masq6: bool = false,
+    masq_allow_invalid: bool = false,
+    mtu_fix: bool = false,
+    log: bool = false,
+    log_limit: String = "10/minute".into(),
+});
+
+impl Zone {
+    /// The logical interfaces the zone covers. Without `network`, `device` or `subnet` that is
+    /// the interface named like the zone.
+    pub fn networks(&self) -> Vec<&str> {
+        if self.network.is_empty() && self.device.is_empty() && self.subnet.is_empty() {
+            return vec![self.name.as_str()];
+        }
+        self.network.iter().map(String::as_str).collect()
+    }
+}
+
+/// Lets traffic from one zone through to another.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "forwarding")]
+pub struct Forwarding {
+    pub name: Option<String>,
+    pub src: String,
+    pub dest: String,
+    pub family: Option<Family>,
+    pub enabled: Option<bool>,
+}
+
+defaults!(Forwarding {
+    family: Family = Family::Any,
+    enabled: bool = true,
+});
+
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "rule")]
+pub struct FirewallRule {
+    /*
+    option	name		'Reject LAN to WAN for custom IP'
+    option	src		'lan'
+    option	src_ip		'192.168.1.2'
+    option	src_mac		'00:11:22:33:44:55'
+    option	src_port	'80'
+    option	dest		'wan'
+    option	dest_ip		'194.25.2.129'
+    option	dest_port	'120'
+    option	proto		'tcp'
+    option	target		'REJECT'
+    */
+    pub name: Option<String>,
+    /// Without a `src` the rule matches traffic from the router itself.
+    pub src: Option<String>,
+    #[uci(split)]
+    pub src_ip: Vec<Neg<IpMask>>,
+    #[uci(split)]
+    pub src_mac: Vec<Neg<MacAddr>>,
+    #[uci(split)]
+    pub src_port: Vec<Neg<PortRange>>,
+    /// Without a `dest` the rule matches traffic to the router itself.
+    pub dest: Option<String>,
+    #[uci(split)]
+    pub dest_ip: Vec<Neg<IpMask>>,
+    #[uci(split)]
+    pub dest_port: Vec<Neg<PortRange>>,
+    /// `tcp udp` when empty
+    #[uci(split)]
+    pub proto: Vec<String>,
+    #[uci(split)]
+    pub icmp_type: Vec<String>,
+    pub family: Option<Family>,
+    pub mark: Option<String>,
+    pub set_mark: Option<String>,
+    pub limit: Option<String>,
+    pub limit_burst: Option<u32>,
+    pub extra: Option<String>,
+    pub start_date: Option<String>,
+    pub stop_date: Option<String>,
+    pub start_time: Option<TimeOfDay>,
+    pub stop_time: Option<TimeOfDay>,
+    pub weekdays: Option<Neg<Weekdays>>,
+    pub utc_time: Option<bool>,
+    pub enabled: Option<bool>,
+    #[uci(default = "REJECT")]
+    pub target: FirewallTarget,
+}
+
+defaults!(FirewallRule {
+    family: Family = Family::Any,
+    limit_burst: u32 = 5,
+    utc_time: bool = false,
+    enabled: bool = true,
+});
+
+impl FirewallRule {
+    /// The protocols the rule matches.
+    pub fn protocols(&self) -> Vec<&str> {
+        match self.proto.is_empty() {
+            true => vec!["tcp", "udp"],
+            false => self.proto.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+/// A port forward, or with `target SNAT` a source NAT rule.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "redirect")]
+pub struct Redirect {
+    pub name: Option<String>,
+    pub src: Option<String>,
+    #[uci(split)]
+    pub src_ip: Vec<Neg<IpMask>>,
+    #[uci(split)]
+    pub src_dip: Vec<Neg<IpMask>>,
+    #[uci(split)]
+    pub src_mac: Vec<Neg<MacAddr>>,
+    #[uci(split)]
+    pub src_port: Vec<Neg<PortRange>>,
+    #[uci(split)]
+    pub src_dport: Vec<Neg<PortRange>>,
+    /// `tcp udp` when empty
+    #[uci(split)]
+    pub proto: Vec<String>,
+    pub dest: Option<String>,
+    pub dest_ip: Option<IpMask>,
+    pub dest_port: Option<PortRange>,
+    pub family: Option<Family>,
+    pub mark: Option<String>,
+    pub ipset: Option<String>,
+    pub reflection: Option<bool>,
+    pub reflection_src: Option<String>,
+    #[uci(split)]
+    pub reflection_zone: Vec<String>,
+    pub limit: Option<String>,
+    pub limit_burst: Option<u32>,
+    pub extra: Option<String>,
+    pub enabled: Option<bool>,
+    pub target: Option<RedirectTarget>,
+}
+
+defaults!(Redirect {
+    family: Family = Family::Any,
+    reflection: bool = true,
+    reflection_src: String = "internal".into(),
+    limit_burst: u32 = 5,
+    enabled: bool = true,
+    target: RedirectTarget = RedirectTarget::DNAT,
+});
+
+/// Source NAT for traffic the zone masquerading doesn't cover.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "nat")]
+pub struct Nat {
+    pub name: Option<String>,
+    pub family: Option<Family>,
+    pub src: Option<String>,
+    #[uci(split)]
+    pub src_ip: Vec<Neg<IpMask>>,
+    #[uci(split)]
+    pub src_port: Vec<Neg<PortRange>>,
+    #[uci(split)]
+    pub dest_ip: Vec<Neg<IpMask>>,
+    #[uci(split)]
+    pub dest_port: Vec<Neg<PortRange>>,
+    /// every protocol when empty
+    #[uci(split)]
+    pub proto: Vec<String>,
+    pub snat_ip: Option<IpMask>,
+    pub snat_port: Option<PortRange>,
+    pub device: Option<String>,
+    pub mark: Option<String>,
+    pub extra: Option<String>,
+    pub enabled: Option<bool>,
+    pub target: Option<NatTarget>,
+}
+
+defaults!(Nat {
+    family: Family = Family::Any,
+    enabled: bool = true,
+    target: NatTarget = NatTarget::MASQUERADE,
+});
+
+/// A named set of addresses, ports or MACs that rules can match with `ipset`.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "ipset")]
+pub struct Ipset {
+    pub name: String,
+    pub enabled: Option<bool>,
+    pub family: Option<Family>,
+    pub storage: Option<String>,
+    #[uci(rename = "match", split)]
+    pub matches: Vec<String>,
+    pub iprange: Option<String>,
+    pub portrange: Option<PortRange>,
+    pub netmask: Option<u8>,
+    pub maxelem: Option<u32>,
+    pub hashsize: Option<u32>,
+    pub timeout: Option<u32>,
+    #[uci(split)]
+    pub entry: Vec<String>,
+    pub loadfile: Option<String>,
+    pub external: Option<String>,
+}
+
+defaults!(Ipset {
+    enabled: bool = true,
+    family: Family = Family::Ipv4,
+});
+
+/// A script or rule file the firewall loads after its own rules.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "include")]
+pub struct Include {
+    pub path: Option<String>,
+    #[uci(rename = "type")]
+    pub ty: Option<IncludeType>,
+    pub family: Option<Family>,
+    pub reload: Option<bool>,
+    pub position: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+defaults!(Include {
+    path: String = "/etc/firewall.user".into(),
+    ty: IncludeType = IncludeType::Script,
+    family: Family = Family::Any,
+    reload: bool = false,
+    enabled: bool = true,
+});
+
+/// The whole firewall config. Sections of other types are ignored.
+///
+/// ```no_run
+/// # use uciedit::openwrt::firewall::{FirewallConfig, PATH};
+/// let firewall = uciedit::parse_config(PATH, FirewallConfig::read)?;
+/// let lan = firewall.zone_for_network("lan");
+/// # Ok::<(), uciedit::Error>(())
+/// ```
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct FirewallConfig {
+    pub defaults: Option<Defaults>,
+    pub zones: Vec<Zone>,
+    pub forwardings: Vec<Forwarding>,
+    pub rules: Vec<FirewallRule>,
+    pub redirects: Vec<Redirect>,
+    pub nats: Vec<Nat>,
+    pub ipsets: Vec<Ipset>,
+    pub includes: Vec<Include>,
+}
+
+impl FirewallConfig {
+    pub fn read(sections: Sections) -> Result<Self, Error> {
+        let mut config = FirewallConfig::default();
+        for section in sections.iter() {
+            let mut read = || -> Result<(), Error> {
+                match &*section.ty() {
+                    "defaults" if config.defaults.is_none() => {
+                        config.defaults = Some(section.get()?)
+                    }
+                    "zone" => config.zones.push(section.get()?),
+                    "forwarding" => config.forwardings.push(section.get()?),
+                    "rule" => config.rules.push(section.get()?),
+                    "redirect" => config.redirects.push(section.get()?),
+                    "nat" => config.nats.push(section.get()?),
+                    "ipset" => config.ipsets.push(section.get()?),
+                    "include" => config.includes.push(section.get()?),
+                    _ => (),
+                }
+                Ok(())
+            };
+            read().wrap_err_with(|| format!("reading firewall section {}", section.id()))?;
+        }
+        Ok(config)
+    }
+
+    /// The settings of the `defaults` section, or the documented defaults without one.
+    pub fn defaults(&self) -> Defaults {
+        self.defaults.clone().unwrap_or_default()
+    }
+
+    pub fn zone(&self, name: &str) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.name == name)
+    }
+
+    /// The zone that covers the logical interface `network`.
+    pub fn zone_for_network(&self, network: &str) -> Option<&Zone> {
+        self.zones
+            .iter()
+            .find(|zone| zone.networks().contains(&network))
+    }
+
+    /// Whether an enabled forwarding lets traffic from zone `src` through to zone `dest`.
+    pub fn forwards(&self, src: &str, dest: &str) -> bool {
+        self.forwardings
+            .iter()
+            .any(|fwd| fwd.enabled() && fwd.src == src && fwd.dest == dest)
+    }
+
+    pub fn rule(&self, name: &str) -> Option<&FirewallRule> {
+        self.rules
+            .iter()
+            .find(|rule| rule.name.as_deref() == Some(name))
+    }
+}
+
+#[test]
+fn test_firewall_config() {
+    use crate::parse_config_string;
+
+    let config = "
+config defaults
+	option syn_flood	1
+	option input		REJECT
+	option output		ACCEPT
+	option forward		REJECT
+
+config zone
+	option name		lan
+	list   network		'lan'
+	option input		ACCEPT
+	option output		ACCEPT
+	option forward		ACCEPT
+
+config zone
+	option name		wan
+	option network		'wan wan6'
+	option input		REJECT
+	option masq		1
+	option mtu_fix		1
+
+config zone
+	option name		guest
+
+config forwarding
+	option src		lan
+	option dest		wan
+
+config rule
+	option name		Allow-DHCP-Renew
+	option src		wan
+	option proto		udp
+	option dest_port	68
+	option target		ACCEPT
+	option family		ipv4
+
+config rule
+	option name		Allow-ICMPv6-Input
+	option src		wan
+	option proto	icmp
+	list icmp_type		echo-request
+	list icmp_type		echo-reply
+	option limit		1000/sec
+	option family		ipv6
+	option target		ACCEPT
+
+config rule
+	option name		Block-Weekend
+	option src		lan
+	option dest		wan
+	option src_mac		'00:11:22:33:44:55'
+	list src_ip		192.168.1.20
+	list src_ip		'!192.168.1.0/28'
+	option start_time	'21:00'
+	option weekdays		'! Sat Sun'
+
+config redirect
+	option name		Forward-SSH
+	option src		wan
+	option src_dport	2222
+	option dest		lan
+	option dest_ip		192.168.1.10
+	option dest_port	22
+
+config include
+	option path		/etc/firewall.user
+
+config unknown
+	option what		ever
+";
+    let firewall = parse_config_string(config, FirewallConfig::read).unwrap();
+
+    let defaults = firewall.defaults();
+    assert!(defaults.syn_flood());
+    assert_eq!(defaults.output(), Policy::ACCEPT);
+    assert!(defaults.tcp_syncookies());
+
+    assert_eq!(firewall.zone_for_network("wan6").unwrap().name, "wan");
+    assert_eq!(firewall.zone_for_network("guest").unwrap().name, "guest");
+    assert!(firewall.zone_for_network("iot").is_none());
+    let wan = firewall.zone("wan").unwrap();
+    assert!(wan.masq());
+    assert_eq!(wan.forward(), Policy::DROP);
+    assert!(firewall.forwards("lan", "wan"));
+    assert!(!firewall.forwards("wan", "lan"));
+
+    let renew = firewall.rule("Allow-DHCP-Renew").unwrap();
+    assert_eq!(renew.family(), Family::Ipv4);
+    assert_eq!(renew.protocols(), ["udp"]);
+    assert_eq!(renew.dest_port, [PortRange::single(68).into()]);
+    let icmp = firewall.rule("Allow-ICMPv6-Input").unwrap();
+    assert_eq!(icmp.icmp_type, ["echo-request", "echo-reply"]);
+    let weekend = firewall.rule("Block-Weekend").unwrap();
+    assert_eq!(weekend.target, FirewallTarget::REJECT);
+    assert_eq!(weekend.protocols(), ["tcp", "udp"]);
+    assert_eq!(weekend.start_time.unwrap().to_string(), "21:00");
+    assert_eq!(weekend.weekdays.as_ref().unwrap().to_string(), "!Sat Sun");
+    assert!(weekend.enabled());
+    let src_ip: Vec<_> = weekend.src_ip.iter().map(|ip| ip.to_string()).collect();
+    assert_eq!(src_ip, ["192.168.1.20", "!192.168.1.0/28"]);
+    assert_eq!(weekend.src_mac.len(), 1);
+
+    let ssh = &firewall.redirects[0];
+    assert_eq!(ssh.target(), RedirectTarget::DNAT);
+    assert_eq!(ssh.src_dport[0].to_string(), "2222");
+    assert_eq!(firewall.includes[0].ty(), IncludeType::Script);
+
+    let err = parse_config_string(
+        "config rule\n\toption src_port 'http'\n",
+        FirewallConfig::read,
+    )
+    .unwrap_err();
+    assert_eq!(
+        format!("{err:#}"),
+        r#"reading firewall section cfg0192bd: "http" is not a port or port range"#
+    );
+}
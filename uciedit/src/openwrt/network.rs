@@ -0,0 +1,414 @@
+//! `/etc/config/network`: the logical interfaces netifd brings up, the devices under them, and
+//! static routes and policy rules.
+
+use super::types::{IpMask, MacAddr, Mask};
+use crate::{Error, Sections};
+use eyre::Context;
+use std::net::{IpAddr, Ipv4Addr};
+use uciedit_macros::UciSection;
+
+pub const PATH: &str = "/etc/config/network";
+
+/// The protocol handler of an interface.
+#[derive(strum::EnumString, strum::Display, Default, Clone, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum Proto {
+    #[default]
+    None,
+    Static,
+    Dhcp,
+    Dhcpv6,
+    Pppoe,
+    /// a handler from a package, like `wireguard` or `6in4`
+    #[strum(default)]
+    Other(String),
+}
+
+/// A logical interface, like `lan` or `wan`.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "interface")]
+pub struct Interface {
+    pub proto: Option<Proto>,
+    /// the [`Device`] or Linux interface it runs on, or `@lan` for an alias
+    pub device: Option<String>,
+    /// `device` before OpenWrt 21.02
+    pub ifname: Option<String>,
+    /// `bridge` before OpenWrt 21.02
+    #[uci(rename = "type")]
+    pub ty: Option<String>,
+    /// with `proto static`, addresses with or without a prefix length
+    #[uci(split)]
+    pub ipaddr: Vec<IpMask>,
+    /// the netmask of an `ipaddr` without a prefix length
+    pub netmask: Option<Ipv4Addr>,
+    pub gateway: Option<IpAddr>,
+    pub broadcast: Option<Ipv4Addr>,
+    #[uci(split)]
+    pub ip6addr: Vec<IpMask>,
+    pub ip6gw: Option<IpAddr>,
+    pub ip6assign: Option<u8>,
+    pub ip6hint: Option<String>,
+    #[uci(split)]
+    pub dns: Vec<IpAddr>,
+    pub peerdns: Option<bool>,
+    pub defaultroute: Option<bool>,
+    pub metric: Option<u32>,
+    pub mtu: Option<u32>,
+    pub auto: Option<bool>,
+    pub disabled: Option<bool>,
+}
+
+defaults!(Interface {
+    proto: Proto = Proto::None,
+    peerdns: bool = true,
+    defaultroute: bool = true,
+    metric: u32 = 0,
+    auto: bool = true,
+    disabled: bool = false,
+});
+
+impl Interface {
+    /// The device the interface runs on, from `device` or the older `ifname`.
+    pub fn device_name(&self) -> Option<&str> {
+        self.device.as_deref().or(self.ifname.as_deref())
+    }
+
+    /// The first static IPv4 address and its prefix length, taken from `netmask` when the
+    /// address has none. `192.168.1.1/24` is the router address on a default LAN.
+    pub fn ipv4(&self) -> Option<IpMask> {
+        let addr = self.ipaddr.iter().find(|addr| addr.addr.is_ipv4())?;
+        Some(match (addr.mask, self.netmask) {
+            (None, Some(netmask)) => IpMask {
+                addr: addr.addr,
+                mask: Some(Mask::Netmask(netmask)),
+            },
+            _ => *addr,
+        })
+    }
+
+    /// The first static IPv4 address, which is the router's address on a LAN.
+    pub fn ipv4_address(&self) -> Option<Ipv4Addr> {
+        match self.ipv4()?.addr {
+            IpAddr::V4(addr) => Some(addr),
+            IpAddr::V6(_) => None,
+        }
+    }
+
+    /// The subnet of [`ipv4`](Self::ipv4), like `192.168.1.0/24`.
+    pub fn ipv4_subnet(&self) -> Option<IpMask> {
+        Some(self.ipv4()?.network())
+    }
+}
+
+/// A bridge, VLAN or otherwise configured Linux network device.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "device")]
+pub struct Device {
+    pub name: String,
+    /// `bridge`, `8021q`, `8021ad`, `macvlan` or `veth`; a plain device without one
+    #[uci(rename = "type")]
+    pub ty: Option<String>,
+    /// the members of a bridge
+    #[uci(split)]
+    pub ports: Vec<String>,
+    /// the parent device of a VLAN or macvlan
+    pub ifname: Option<String>,
+    pub vid: Option<u16>,
+    pub macaddr: Option<MacAddr>,
+    pub mtu: Option<u32>,
+    pub ipv6: Option<bool>,
+    pub vlan_filtering: Option<bool>,
+    pub disabled: Option<bool>,
+}
+
+defaults!(Device {
+    ipv6: bool = true,
+    vlan_filtering: bool = false,
+    disabled: bool = false,
+});
+
+impl Device {
+    pub fn is_bridge(&self) -> bool {
+        self.ty.as_deref() == Some("bridge")
+    }
+}
+
+/// A VLAN on a VLAN filtering bridge.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "bridge-vlan")]
+pub struct BridgeVlan {
+    /// the bridge [`Device`]
+    pub device: String,
+    pub vlan: u16,
+    /// bridge ports, with `:u` for untagged and `*` for the primary VLAN as in `lan1:u*`
+    #[uci(split)]
+    pub ports: Vec<String>,
+    pub local: Option<bool>,
+}
+
+defaults!(BridgeVlan { local: bool = true });
+
+impl BridgeVlan {
+    /// The port names without their `:t`, `:u` or `*` flags.
+    pub fn port_names(&self) -> Vec<&str> {
+        self.ports
+            .iter()
+            .map(|port| port.split_once(':').map_or(port.as_str(), |(name, _)| name))
+            .map(|port| port.trim_end_matches('*'))
+            .collect()
+    }
+}
+
+/// A static IPv4 route.
+#[derive(UciSection, Clone, Debug, PartialEq)]
+#[uci(ty = "route")]
+pub struct Route {
+    pub interface: String,
+    pub target: IpMask,
+    /// the netmask of a `target` without a prefix length
+    pub netmask: Option<Ipv4Addr>,
+    pub gateway: Option<IpAddr>,
+    pub metric: Option<u32>,
+    pub mtu: Option<u32>,
+    pub table: Option<String>,
+    pub source: Option<IpMask>,
+    /// `unicast` unless set, or one like `blackhole` or `unreachable`
+    #[uci(rename = "type")]
+    pub ty: Option<String>,
+    pub onlink: Option<bool>,
+    pub disabled: Option<bool>,
+}
+
+defaults!(Route {
+    metric: u32 = 0,
+    onlink: bool = false,
+    disabled: bool = false,
+});
+
+/// A static IPv6 route.
+#[derive(UciSection, Clone, Debug, PartialEq)]
+#[uci(ty = "route6")]
+pub struct Route6 {
+    pub interface: String,
+    pub target: IpMask,
+    pub gateway: Option<IpAddr>,
+    pub metric: Option<u32>,
+    pub mtu: Option<u32>,
+    pub table: Option<String>,
+    pub source: Option<IpMask>,
+    #[uci(rename = "type")]
+    pub ty: Option<String>,
+    pub onlink: Option<bool>,
+    pub disabled: Option<bool>,
+}
+
+defaults!(Route6 {
+    metric: u32 = 0,
+    onlink: bool = false,
+    disabled: bool = false,
+});
+
+/// An IPv4 policy routing rule.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "rule")]
+pub struct Rule {
+    #[uci(rename = "in")]
+    pub incoming: Option<String>,
+    #[uci(rename = "out")]
+    pub outgoing: Option<String>,
+    pub src: Option<IpMask>,
+    pub dest: Option<IpMask>,
+    pub tos: Option<u8>,
+    pub mark: Option<String>,
+    pub lookup: Option<String>,
+    pub goto: Option<u32>,
+    pub action: Option<String>,
+    pub priority: Option<u32>,
+    pub invert: Option<bool>,
+    pub disabled: Option<bool>,
+}
+
+defaults!(Rule {
+    invert: bool = false,
+    disabled: bool = false,
+});
+
+/// The whole network config. Sections of other types, like `globals` and `switch`, are
+/// ignored.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct NetworkConfig {
+    /// the logical interfaces by section name
+    pub interfaces: Vec<(String, Interface)>,
+    pub devices: Vec<Device>,
+    pub bridge_vlans: Vec<BridgeVlan>,
+    pub routes: Vec<Route>,
+    pub routes6: Vec<Route6>,
+    pub rules: Vec<Rule>,
+}
+
+impl NetworkConfig {
+    pub fn read(sections: Sections) -> Result<Self, Error> {
+        let mut config = NetworkConfig::default();
+        for section in sections.iter() {
+            let mut read = || -> Result<(), Error> {
+                match &*section.ty() {
+                    "interface" => config.interfaces.push((section.id(), section.get()?)),
+                    "device" => config.devices.push(section.get()?),
+                    "bridge-vlan" => config.bridge_vlans.push(section.get()?),
+                    "route" => config.routes.push(section.get()?),
+                    "route6" => config.routes6.push(section.get()?),
+                    "rule" => config.rules.push(section.get()?),
+                    _ => (),
+                }
+                Ok(())
+            };
+            read().wrap_err_with(|| format!("reading network section {}", section.id()))?;
+        }
+        Ok(config)
+    }
+
+    pub fn interface(&self, name: &str) -> Option<&Interface> {
+        self.interfaces
+            .iter()
+            .find_map(|(n, interface)| (n == name).then_some(interface))
+    }
+
+    pub fn device(&self, name: &str) -> Option<&Device> {
+        self.devices.iter().find(|device| device.name == name)
+    }
+
+    /// The [`Device`] section of the logical interface `name`, following `@` aliases. Plain
+    /// Linux interfaces like `eth1` often have none.
+    pub fn interface_device(&self, name: &str) -> Option<&Device> {
+        self.device(&self.resolve_device(name)?)
+    }
+
+    /// The Linux device name the logical interface `name` runs on, following `@` aliases.
+    pub fn resolve_device(&self, name: &str) -> Option<String> {
+        let mut name = name;
+        // an alias of an alias of ... ends somewhere unless the config loops
+        for _ in 0..self.interfaces.len() {
+            let device = self.interface(name)?.device_name()?;
+            match device.strip_prefix('@') {
+                Some(parent) => name = parent,
+                None => return Some(device.to_owned()),
+            }
+        }
+        None
+    }
+
+    /// The router's address and the subnet of the logical interface `name`: `192.168.1.1` and
+    /// `192.168.1.0/24` on a default LAN.
+    pub fn ipv4_subnet(&self, name: &str) -> Option<(Ipv4Addr, IpMask)> {
+        let interface = self.interface(name)?;
+        Some((interface.ipv4_address()?, interface.ipv4_subnet()?))
+    }
+
+    /// The logical interfaces whose device is `device` or a bridge with `device` as a port.
+    pub fn interfaces_of_device(&self, device: &str) -> Vec<&str> {
+        self.interfaces
+            .iter()
+            .filter(|(name, _)| {
+                self.resolve_device(name).is_some_and(|own| {
+                    own == device
+                        || self
+                            .device(&own)
+                            .is_some_and(|own| own.ports.iter().any(|port| port == device))
+                })
+            })
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// The VLANs configured on the bridge `device`.
+    pub fn vlans(&self, device: &str) -> Vec<&BridgeVlan> {
+        self.bridge_vlans
+            .iter()
+            .filter(|vlan| vlan.device == device)
+            .collect()
+    }
+}
+
+#[test]
+fn test_network_config() {
+    use crate::parse_config_string;
+
+    let config = "
+config interface 'loopback'
+	option device 'lo'
+	option proto 'static'
+	option ipaddr '127.0.0.1'
+	option netmask '255.0.0.0'
+
+config globals 'globals'
+	option ula_prefix 'fd12:3456:789a::/48'
+
+config device
+	option name 'br-lan'
+	option type 'bridge'
+	list ports 'lan1'
+	list ports 'lan2'
+	option vlan_filtering '1'
+
+config bridge-vlan
+	option device 'br-lan'
+	option vlan '1'
+	list ports 'lan1:u*'
+	list ports 'lan2:t'
+
+config interface 'lan'
+	option device 'br-lan'
+	option proto 'static'
+	option ipaddr '192.168.1.1'
+	option netmask '255.255.255.0'
+	option ip6assign '60'
+
+config interface 'guest'
+	option device 'br-lan.3'
+	option proto 'static'
+	option ipaddr '10.3.0.1/24'
+
+config interface 'wan'
+	option device 'wan'
+	option proto 'dhcp'
+
+config interface 'wan6'
+	option device '@wan'
+	option proto 'dhcpv6'
+
+config interface 'vpn'
+	option proto 'wireguard'
+
+config route
+	option interface 'lan'
+	option target '10.10.0.0/16'
+	option gateway '192.168.1.2'
+
+config rule
+	option in 'guest'
+	option lookup '100'
+";
+    let network = parse_config_string(config, NetworkConfig::read).unwrap();
+
+    let (router, subnet) = network.ipv4_subnet("lan").unwrap();
+    assert_eq!(router, Ipv4Addr::new(192, 168, 1, 1));
+    assert_eq!(subnet.to_string(), "192.168.1.0/24");
+    assert_eq!(
+        network.ipv4_subnet("guest").unwrap().1.to_string(),
+        "10.3.0.0/24"
+    );
+    assert_eq!(network.ipv4_subnet("wan"), None);
+    assert_eq!(network.interface("lan").unwrap().ip6assign, Some(60));
+    assert_eq!(
+        network.interface("vpn").unwrap().proto(),
+        Proto::Other("wireguard".into())
+    );
+
+    assert!(network.interface_device("lan").unwrap().is_bridge());
+    assert_eq!(network.resolve_device("wan6").as_deref(), Some("wan"));
+    assert_eq!(network.interfaces_of_device("lan2"), ["lan"]);
+    assert_eq!(network.interfaces_of_device("wan"), ["wan", "wan6"]);
+    assert_eq!(network.vlans("br-lan")[0].port_names(), ["lan1", "lan2"]);
+    assert_eq!(network.routes[0].target.to_string(), "10.10.0.0/16");
+    assert_eq!(network.rules[0].incoming.as_deref(), Some("guest"));
+}
@@ -0,0 +1,428 @@
+//! The option datatypes LuCI validates in `ui/src/libluci/validation.js`, so that a typo is
+//! caught when the config is written instead of when fw3 or netifd reload it. Each one parses
+//! with [`FromStr`] and writes back with [`Display`], which makes it usable as a field of a
+//! derived [`UciSection`](crate::UciSection).
+
+use crate::{bail, error, Error};
+use std::fmt::{self, Display};
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+/// `port` or `portrange`: a single port like `80`, or an inclusive range like `80-443`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct PortRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl PortRange {
+    pub fn single(port: u16) -> Self {
+        PortRange {
+            first: port,
+            last: port,
+        }
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        (self.first..=self.last).contains(&port)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let port = |p: &str| {
+            if p.is_empty() || !p.bytes().all(|b| b.is_ascii_digit()) {
+                bail!("{s:?} is not a port or port range");
+            }
+            p.parse::<u16>()
+                .map_err(|_| error!("port {p} in {s:?} is larger than 65535"))
+        };
+        let range = match s.split_once('-') {
+            Some((first, last)) => PortRange {
+                first: port(first)?,
+                last: port(last)?,
+            },
+            None => PortRange::single(port(s)?),
+        };
+        if range.first > range.last {
+            bail!("port range {s:?} ends before it starts");
+        }
+        Ok(range)
+    }
+}
+
+impl Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}-{}", self.first, self.last)
+        }
+    }
+}
+
+/// How an [`IpMask`] writes its network: `/24`, or for IPv4 also `/255.255.255.0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mask {
+    Prefix(u8),
+    Netmask(Ipv4Addr),
+}
+
+/// `ipmask`: an IPv4 or IPv6 address, optionally with a prefix length or an IPv4 netmask.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpMask {
+    pub addr: IpAddr,
+    pub mask: Option<Mask>,
+}
+
+impl IpMask {
+    /// The prefix length, which is the full address length without a mask.
+    pub fn prefix(&self) -> u8 {
+        match self.mask {
+            Some(Mask::Prefix(prefix)) => prefix,
+            Some(Mask::Netmask(netmask)) => u32::from(netmask).leading_ones() as u8,
+            None if self.addr.is_ipv4() => 32,
+            None => 128,
+        }
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.network().addr, addr) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                IpMask { addr, ..*self }.network() == self.network()
+            }
+            _ => false,
+        }
+    }
+
+    /// The network the address is in, like `192.168.1.0/24` for `192.168.1.1/24`.
+    pub fn network(&self) -> IpMask {
+        let prefix = self.prefix();
+        let addr = match self.addr {
+            IpAddr::V4(addr) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                IpAddr::V4((u32::from(addr) & mask).into())
+            }
+            IpAddr::V6(addr) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                IpAddr::V6((u128::from(addr) & mask).into())
+            }
+        };
+        IpMask {
+            addr,
+            mask: Some(Mask::Prefix(prefix)),
+        }
+    }
+}
+
+impl From<IpAddr> for IpMask {
+    fn from(addr: IpAddr) -> Self {
+        IpMask { addr, mask: None }
+    }
+}
+
+impl FromStr for IpMask {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let (addr, mask) = match s.split_once('/') {
+            Some((addr, mask)) => (addr, Some(mask)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| error!("{addr:?} is not an IPv4 or IPv6 address"))?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let mask = match mask {
+            None => None,
+            Some(prefix) if !prefix.is_empty() && prefix.bytes().all(|b| b.is_ascii_digit()) => {
+                match prefix.parse() {
+                    Ok(prefix) if prefix <= max => Some(Mask::Prefix(prefix)),
+                    _ => bail!("prefix length in {s:?} is larger than {max}"),
+                }
+            }
+            Some(netmask) => {
+                let Ok(netmask) = netmask.parse::<Ipv4Addr>() else {
+                    bail!("{netmask:?} in {s:?} is neither a prefix length nor a netmask");
+                };
+                if !addr.is_ipv4() {
+                    bail!("IPv6 address {s:?} needs a prefix length, not a netmask");
+                }
+                if u32::from(netmask).leading_ones() != u32::from(netmask).count_ones() {
+                    bail!("netmask {netmask} in {s:?} is not contiguous");
+                }
+                Some(Mask::Netmask(netmask))
+            }
+        };
+        Ok(IpMask { addr, mask })
+    }
+}
+
+impl Display for IpMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.mask {
+            None => write!(f, "{}", self.addr),
+            Some(Mask::Prefix(prefix)) => write!(f, "{}/{prefix}", self.addr),
+            Some(Mask::Netmask(netmask)) => write!(f, "{}/{netmask}", self.addr),
+        }
+    }
+}
+
+/// `macaddr`: six hex pairs separated by colons, for a single device, so not a multicast
+/// address.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddr(pub [u8; 6]);
+
+impl FromStr for MacAddr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut mac = [0; 6];
+        let mut pairs = s.split(':');
+        for byte in &mut mac {
+            let pair = pairs.next().unwrap_or_default();
+            if pair.len() != 2 || !pair.bytes().all(|b| b.is_ascii_hexdigit()) {
+                bail!("{s:?} is not a MAC address, expected six hex pairs like 00:11:22:33:44:55");
+            }
+            *byte = u8::from_str_radix(pair, 16)?;
+        }
+        if pairs.next().is_some() {
+            bail!("{s:?} is not a MAC address, it has more than six hex pairs");
+        }
+        if mac[0] & 1 != 0 {
+            bail!("{s:?} is a multicast MAC address");
+        }
+        Ok(MacAddr(mac))
+    }
+}
+
+impl Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{a:02x}:{b:02x}:{c:02x}:{d:02x}:{e:02x}:{g:02x}")
+    }
+}
+
+/// `hostname`: at most 253 letters, digits, `_`, `-` and `.`. Unless it is a single word, it
+/// has to end in a letter or digit and can't be all digits and dots.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hostname(String);
+
+impl Hostname {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Hostname {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let word = |c: char| c.is_ascii_alphanumeric() || c == '_';
+        if s.is_empty() {
+            bail!("a hostname can't be empty");
+        }
+        if s.len() > 253 {
+            bail!("hostname {s:?} is longer than 253 characters");
+        }
+        if let Some(c) = s.chars().find(|&c| !word(c) && c != '-' && c != '.') {
+            bail!("hostname {s:?} contains {c:?}");
+        }
+        if !s.chars().all(word) {
+            if s.starts_with(['-', '.']) || !s.ends_with(|c: char| c.is_ascii_alphanumeric()) {
+                bail!("hostname {s:?} has to start with a letter, digit or '_' and end with a letter or digit");
+            }
+            if s.chars().all(|c| c.is_ascii_digit() || c == '.') {
+                bail!("hostname {s:?} looks like an IP address");
+            }
+        }
+        Ok(Hostname(s.to_owned()))
+    }
+}
+
+impl Display for Hostname {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// `neg(...)`: a value that may be prefixed with `!` to match everything else.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Neg<T> {
+    pub negated: bool,
+    pub value: T,
+}
+
+impl<T> From<T> for Neg<T> {
+    fn from(value: T) -> Self {
+        Neg {
+            negated: false,
+            value,
+        }
+    }
+}
+
+impl<T: FromStr<Err = Error>> FromStr for Neg<T> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let s = s.trim_start_matches([' ', '\t']);
+        let (negated, s) = match s.strip_prefix('!') {
+            Some(rest) => (true, rest.trim_start_matches([' ', '\t'])),
+            None => (false, s),
+        };
+        Ok(Neg {
+            negated,
+            value: s.parse()?,
+        })
+    }
+}
+
+impl<T: Display> Display for Neg<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            f.write_str("!")?;
+        }
+        self.value.fmt(f)
+    }
+}
+
+/// `or(...)`: the first of two datatypes that accepts the value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Or<A, B> {
+    First(A),
+    Second(B),
+}
+
+impl<A: FromStr<Err = Error>, B: FromStr<Err = Error>> FromStr for Or<A, B> {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let first = match s.parse() {
+            Ok(first) => return Ok(Or::First(first)),
+            Err(err) => err,
+        };
+        match s.parse() {
+            Ok(second) => Ok(Or::Second(second)),
+            Err(second) => bail!("{first}, and {second}"),
+        }
+    }
+}
+
+impl<A: Display, B: Display> Display for Or<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Or::First(first) => first.fmt(f),
+            Or::Second(second) => second.fmt(f),
+        }
+    }
+}
+
+#[test]
+fn test_datatypes() {
+    fn round_trip<T: FromStr<Err = Error> + Display>(s: &str) -> String {
+        s.parse::<T>().unwrap().to_string()
+    }
+    fn err<T: FromStr<Err = Error> + fmt::Debug>(s: &str) -> String {
+        s.parse::<T>().unwrap_err().to_string()
+    }
+
+    assert_eq!(
+        "80-443".parse::<PortRange>().unwrap(),
+        PortRange {
+            first: 80,
+            last: 443
+        }
+    );
+    assert_eq!(round_trip::<PortRange>("22"), "22");
+    assert_eq!(
+        err::<PortRange>("443-80"),
+        r#"port range "443-80" ends before it starts"#
+    );
+    assert_eq!(
+        err::<PortRange>("70000"),
+        r#"port 70000 in "70000" is larger than 65535"#
+    );
+    assert_eq!(
+        err::<PortRange>("http"),
+        r#""http" is not a port or port range"#
+    );
+
+    for ip in [
+        "192.168.1.1",
+        "10.0.0.0/8",
+        "10.0.0.0/255.0.0.0",
+        "fd00::/64",
+        "::1",
+    ] {
+        assert_eq!(round_trip::<IpMask>(ip), ip);
+    }
+    let lan: IpMask = "192.168.1.0/255.255.255.0".parse().unwrap();
+    assert_eq!(lan.prefix(), 24);
+    assert!(lan.contains("192.168.1.20".parse().unwrap()));
+    assert!(!lan.contains("192.168.2.20".parse().unwrap()));
+    assert_eq!(
+        "192.168.1.1/24"
+            .parse::<IpMask>()
+            .unwrap()
+            .network()
+            .to_string(),
+        "192.168.1.0/24"
+    );
+    assert_eq!(
+        err::<IpMask>("10.0.0.0/33"),
+        r#"prefix length in "10.0.0.0/33" is larger than 32"#
+    );
+    assert_eq!(
+        err::<IpMask>("10.0.0.0/255.0.255.0"),
+        r#"netmask 255.0.255.0 in "10.0.0.0/255.0.255.0" is not contiguous"#
+    );
+    assert_eq!(
+        err::<IpMask>("lan"),
+        r#""lan" is not an IPv4 or IPv6 address"#
+    );
+
+    assert_eq!(
+        round_trip::<MacAddr>("00:11:22:AA:bb:cc"),
+        "00:11:22:aa:bb:cc"
+    );
+    assert!(err::<MacAddr>("00:11:22:33:44").contains("six hex pairs"));
+    assert!(err::<MacAddr>("00:11:22:33:44:55:66").contains("more than six"));
+    assert!(err::<MacAddr>("01:00:5e:00:00:01").contains("multicast"));
+    assert!(err::<MacAddr>("ff:ff:ff:ff:ff:ff").contains("multicast"));
+
+    assert_eq!(round_trip::<Hostname>("printer.lan"), "printer.lan");
+    assert_eq!(round_trip::<Hostname>("_1234"), "_1234");
+    assert_eq!(
+        err::<Hostname>("my printer"),
+        r#"hostname "my printer" contains ' '"#
+    );
+    assert_eq!(
+        err::<Hostname>("10.0.0.1"),
+        r#"hostname "10.0.0.1" looks like an IP address"#
+    );
+    assert_eq!(
+        err::<Hostname>("lan-"),
+        r#"hostname "lan-" has to start with a letter, digit or '_' and end with a letter or digit"#
+    );
+
+    let negated: Neg<IpMask> = " ! 10.0.0.0/8".parse().unwrap();
+    assert!(negated.negated);
+    assert_eq!(negated.to_string(), "!10.0.0.0/8");
+    assert_eq!(round_trip::<Neg<PortRange>>("8080"), "8080");
+
+    assert!(matches!(
+        "nas.lan".parse(),
+        Ok(Or::<IpMask, Hostname>::Second(_))
+    ));
+    assert!(matches!(
+        "10.0.0.2".parse(),
+        Ok(Or::<IpMask, Hostname>::First(_))
+    ));
+    assert_eq!(
+        err::<Or<IpMask, MacAddr>>("nas"),
+        r#""nas" is not an IPv4 or IPv6 address, and "nas" is not a MAC address, expected six hex pairs like 00:11:22:33:44:55"#
+    );
+}
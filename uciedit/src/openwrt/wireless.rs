@@ -0,0 +1,361 @@
+//! `/etc/config/wireless`: the radios and the networks netifd brings up on them.
+
+use super::types::MacAddr;
+use crate::{bail, Error, Sections};
+use eyre::Context;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use uciedit_macros::UciSection;
+
+pub const PATH: &str = "/etc/config/wireless";
+
+#[derive(strum::EnumString, strum::Display, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum WifiMode {
+    #[default]
+    Ap,
+    Sta,
+    Adhoc,
+    Mesh,
+    Monitor,
+    Wds,
+}
+
+impl WifiMode {
+    /// How mac80211.sh names an interface of this mode: `ap` in `phy0-ap0`.
+    pub fn ifname_prefix(&self) -> &'static str {
+        match self {
+            WifiMode::Ap => "ap",
+            WifiMode::Sta => "sta",
+            WifiMode::Adhoc => "ibss",
+            WifiMode::Mesh => "mesh",
+            WifiMode::Monitor => "mon",
+            WifiMode::Wds => "wds",
+        }
+    }
+}
+
+#[derive(strum::EnumString, strum::Display, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "kebab-case")]
+pub enum EncryptionMethod {
+    #[default]
+    None,
+    WepOpen,
+    WepShared,
+    Psk,
+    Psk2,
+    PskMixed,
+    Sae,
+    SaeMixed,
+    Wpa,
+    Wpa2,
+    Wpa3,
+    WpaMixed,
+    Wpa3Mixed,
+    Owe,
+}
+
+impl EncryptionMethod {
+    /// Whether hostapd takes the passphrase from `key`, `wpa_psk_file` or `sae_password`.
+    pub fn uses_passphrase(&self) -> bool {
+        use EncryptionMethod::*;
+        matches!(self, Psk | Psk2 | PskMixed | Sae | SaeMixed)
+    }
+}
+
+/// `encryption`: a method like `psk2`, optionally followed by ciphers as in `psk2+ccmp`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Encryption {
+    pub method: EncryptionMethod,
+    pub ciphers: Vec<String>,
+}
+
+impl From<EncryptionMethod> for Encryption {
+    fn from(method: EncryptionMethod) -> Self {
+        Encryption {
+            method,
+            ciphers: Vec::new(),
+        }
+    }
+}
+
+impl FromStr for Encryption {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Error> {
+        let mut parts = s.split('+');
+        let method = parts.next().unwrap_or_default();
+        let Ok(method) = method.parse() else {
+            bail!("{method:?} is not an encryption method like psk2 or sae");
+        };
+        Ok(Encryption {
+            method,
+            ciphers: parts.map(str::to_owned).collect(),
+        })
+    }
+}
+
+impl Display for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.method.fmt(f)?;
+        for cipher in &self.ciphers {
+            write!(f, "+{cipher}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(strum::EnumString, strum::Display, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum MacFilter {
+    #[default]
+    Disable,
+    Allow,
+    Deny,
+}
+
+/// A radio.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "wifi-device")]
+pub struct WifiDevice {
+    /// `mac80211` for every driver current OpenWrt supports
+    #[uci(rename = "type")]
+    pub ty: Option<String>,
+    pub phy: Option<String>,
+    pub path: Option<String>,
+    pub macaddr: Option<MacAddr>,
+    pub band: Option<String>,
+    /// a channel number or `auto`
+    pub channel: Option<String>,
+    pub htmode: Option<String>,
+    pub country: Option<String>,
+    pub txpower: Option<u32>,
+    pub disabled: Option<bool>,
+}
+
+defaults!(WifiDevice {
+    ty: String = "mac80211".into(),
+    disabled: bool = false,
+});
+
+impl WifiDevice {
+    /// The wiphy of the radio named `name`: its `phy`, or `phy0` for `radio0` the way
+    /// `wifi config` numbers them.
+    pub fn phy_name(&self, name: &str) -> Option<String> {
+        if let Some(phy) = &self.phy {
+            return Some(phy.clone());
+        }
+        let index = name.strip_prefix("radio")?;
+        index.parse::<u32>().ok()?;
+        Some(format!("phy{index}"))
+    }
+}
+
+/// A network on a radio.
+#[derive(UciSection, Default, Clone, Debug, PartialEq)]
+#[uci(ty = "wifi-iface")]
+pub struct WifiIface {
+    /// the name of the [`WifiDevice`] section
+    pub device: String,
+    pub mode: Option<WifiMode>,
+    pub ssid: Option<String>,
+    pub bssid: Option<MacAddr>,
+    #[uci(split)]
+    pub network: Vec<String>,
+    pub encryption: Option<Encryption>,
+    pub key: Option<String>,
+    pub wpa_psk_file: Option<String>,
+    pub sae_password: Option<String>,
+    /// management frame protection: 0 off, 1 optional, 2 required
+    pub ieee80211w: Option<u8>,
+    pub isolate: Option<bool>,
+    pub hidden: Option<bool>,
+    pub disabled: Option<bool>,
+    pub ifname: Option<String>,
+    pub macfilter: Option<MacFilter>,
+    #[uci(split)]
+    pub maclist: Vec<MacAddr>,
+}
+
+defaults!(WifiIface {
+    mode: WifiMode = WifiMode::Ap,
+    encryption: Encryption = Encryption::default(),
+    isolate: bool = false,
+    hidden: bool = false,
+    disabled: bool = false,
+    macfilter: MacFilter = MacFilter::Disable,
+});
+
+/// The whole wireless config. Sections of other types are ignored.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct WirelessConfig {
+    /// the radios by section name
+    pub devices: Vec<(String, WifiDevice)>,
+    /// the networks by section name, which is `cfgXXXXXX` for anonymous ones
+    pub ifaces: Vec<(String, WifiIface)>,
+}
+
+impl WirelessConfig {
+    pub fn read(sections: Sections) -> Result<Self, Error> {
+        let mut config = WirelessConfig::default();
+        for section in sections.iter() {
+            let mut read = || -> Result<(), Error> {
+                match &*section.ty() {
+                    "wifi-device" => config.devices.push((section.id(), section.get()?)),
+                    "wifi-iface" => config.ifaces.push((section.id(), section.get()?)),
+                    _ => (),
+                }
+                Ok(())
+            };
+            read().wrap_err_with(|| format!("reading wireless section {}", section.id()))?;
+        }
+        Ok(config)
+    }
+
+    pub fn device(&self, name: &str) -> Option<&WifiDevice> {
+        self.devices
+            .iter()
+            .find_map(|(n, device)| (n == name).then_some(device))
+    }
+
+    pub fn iface(&self, name: &str) -> Option<&WifiIface> {
+        self.ifaces
+            .iter()
+            .find_map(|(n, iface)| (n == name).then_some(iface))
+    }
+
+    /// The name of the network interface netifd creates for the `wifi-iface` section `name`,
+    /// which is also the name of its hostapd control socket. Unless `ifname` is set, mac80211.sh
+    /// numbers the enabled interfaces of each mode on a radio: `phy0-ap0`, `phy0-ap1`,
+    /// `phy0-sta0`. Disabled interfaces and interfaces on disabled radios have none.
+    pub fn ifname(&self, name: &str) -> Option<String> {
+        let iface = self.iface(name)?;
+        let device = self.device(&iface.device)?;
+        if iface.disabled() || device.disabled() {
+            return None;
+        }
+        if let Some(ifname) = &iface.ifname {
+            return Some(ifname.clone());
+        }
+        let index = self
+            .ifaces
+            .iter()
+            .take_while(|(n, _)| n != name)
+            .filter(|(_, other)| {
+                other.device == iface.device
+                    && other.mode() == iface.mode()
+                    && !other.disabled()
+                    && other.ifname.is_none()
+            })
+            .count();
+        let phy = device.phy_name(&iface.device)?;
+        Some(format!("{phy}-{}{index}", iface.mode().ifname_prefix()))
+    }
+
+    /// The interface names of the enabled access points whose `wpa_psk_file` is `path`.
+    pub fn psk_file_ifnames(&self, path: &str) -> Vec<String> {
+        self.ifaces
+            .iter()
+            .filter(|(_, iface)| {
+                iface.mode() == WifiMode::Ap && iface.wpa_psk_file.as_deref() == Some(path)
+            })
+            .filter_map(|(name, _)| self.ifname(name))
+            .collect()
+    }
+}
+
+#[test]
+fn test_wireless_config() {
+    use crate::{parse_config_string, rewrite_config_string};
+
+    let config = "
+config wifi-device 'radio0'
+	option type 'mac80211'
+	option path 'platform/soc/18000000.wifi'
+	option channel '1'
+	option band '2g'
+	option htmode 'HE20'
+
+config wifi-device 'radio1'
+	option type 'mac80211'
+	option channel '36'
+	option band '5g'
+
+config wifi-iface 'default_radio0'
+	option device 'radio0'
+	option network 'lan'
+	option mode 'ap'
+	option ssid 'OpenWrt'
+	option encryption 'psk2+ccmp'
+	option wpa_psk_file '/etc/hostapd.wpa_psk'
+
+config wifi-iface 'guest_radio0'
+	option device 'radio0'
+	option network 'guest'
+	option ssid 'Guest'
+	option encryption 'sae-mixed'
+	option key 'hunter22'
+	option isolate '1'
+	option disabled '1'
+
+config wifi-iface 'iot_radio0'
+	option device 'radio0'
+	option network 'iot'
+	option ssid 'IoT'
+	option macfilter 'allow'
+	list maclist '00:11:22:33:44:55'
+
+config wifi-iface
+	option device 'radio0'
+	option mode 'sta'
+	option ssid 'Upstream'
+
+config wifi-iface 'default_radio1'
+	option device 'radio1'
+	option ssid 'OpenWrt'
+	option ifname 'wlan5'
+	option wpa_psk_file '/etc/hostapd.wpa_psk'
+";
+    let wireless = parse_config_string(config, WirelessConfig::read).unwrap();
+    let default = wireless.iface("default_radio0").unwrap();
+    assert_eq!(default.encryption().method, EncryptionMethod::Psk2);
+    assert_eq!(default.encryption().to_string(), "psk2+ccmp");
+    assert_eq!(default.network, ["lan"]);
+    let iot = wireless.iface("iot_radio0").unwrap();
+    assert_eq!(iot.macfilter(), MacFilter::Allow);
+    assert_eq!(iot.encryption().method, EncryptionMethod::None);
+
+    assert_eq!(
+        wireless.ifname("default_radio0").as_deref(),
+        Some("phy0-ap0")
+    );
+    assert_eq!(wireless.ifname("guest_radio0"), None);
+    assert_eq!(wireless.ifname("iot_radio0").as_deref(), Some("phy0-ap1"));
+    assert_eq!(
+        wireless.ifname(&wireless.ifaces[3].0).as_deref(),
+        Some("phy0-sta0")
+    );
+    assert_eq!(
+        wireless.psk_file_ifnames("/etc/hostapd.wpa_psk"),
+        ["phy0-ap0", "wlan5"]
+    );
+
+    // what the identity-psk README has users do by hand
+    let edited = rewrite_config_string(config.to_string(), |mut ctx| {
+        while ctx.step() {
+            if ctx.ty()? == "wifi-iface" && ctx.id()? == "iot_radio0" {
+                let mut iface: WifiIface = ctx.get()?;
+                iface.encryption = Some(EncryptionMethod::Psk2.into());
+                iface.wpa_psk_file = Some("/etc/hostapd.wpa_psk".into());
+                ctx.set(iface)?;
+            }
+        }
+        Ok(())
+    })
+    .unwrap();
+    let wireless = parse_config_string(&edited, WirelessConfig::read).unwrap();
+    assert_eq!(
+        wireless.psk_file_ifnames("/etc/hostapd.wpa_psk"),
+        ["phy0-ap0", "phy0-ap1", "wlan5"]
+    );
+}
@@ -0,0 +1,193 @@
+//! What was changed from the stock config. OpenWrt images keep the configs they shipped with
+//! read-only in `/rom/etc/config`, underneath the writable overlay that `/etc/config` lives on.
+//! Comparing the two directories package by package shows what was customized, and
+//! [`defaults_script`] turns that into a `/etc/uci-defaults` script that makes the same changes
+//! on a freshly flashed image.
+
+use crate::diff::Diff;
+use crate::{Error, UciDocument};
+use eyre::Context;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+/// Where OpenWrt keeps the configs the image shipped with.
+pub const ROM_CONFDIR: &str = "/rom/etc/config";
+
+/// How a package differs between two config directories.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackageDiff {
+    pub package: String,
+    pub status: PackageStatus,
+    /// The changes from the old config, which is empty if there was none.
+    pub diff: Diff,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PackageStatus {
+    /// Only in the new directory.
+    Added,
+    /// Only in the old directory.
+    Removed,
+    Changed,
+}
+
+/// The packages in a config directory, sorted by name. Like libuci, this skips hidden files,
+/// which is where editors and `uci commit` keep their temporary copies.
+pub fn packages(dir: &Path) -> Result<Vec<String>, Error> {
+    let mut packages = Vec::new();
+    let entries = fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let Ok(name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if !name.starts_with('.') && entry.file_type()?.is_file() {
+            packages.push(name);
+        }
+    }
+    packages.sort();
+    Ok(packages)
+}
+
+/// The packages that differ between the config directories `old` and `new`, such as
+/// [`ROM_CONFDIR`] and [`CONFDIR`](crate::delta::CONFDIR), sorted by name. Only the files
+/// count, not changes staged in `/tmp/.uci`.
+pub fn diff_dirs(old: &Path, new: &Path) -> Result<Vec<PackageDiff>, Error> {
+    let (old_packages, new_packages) = (packages(old)?, packages(new)?);
+    let mut all: Vec<&String> = old_packages.iter().chain(&new_packages).collect();
+    all.sort();
+    all.dedup();
+
+    let mut diffs = Vec::new();
+    for package in all {
+        let load = |dir: &Path, packages: &[String]| match packages.contains(package) {
+            true => UciDocument::load(dir.join(package)),
+            false => Ok(UciDocument::default()),
+        };
+        let diff = load(old, &old_packages)?.diff(&load(new, &new_packages)?)?;
+        let status = match (
+            old_packages.contains(package),
+            new_packages.contains(package),
+        ) {
+            (false, _) => PackageStatus::Added,
+            (_, false) => PackageStatus::Removed,
+            _ if diff.is_empty() => continue,
+            _ => PackageStatus::Changed,
+        };
+        diffs.push(PackageDiff {
+            package: package.clone(),
+            status,
+            diff,
+        });
+    }
+    Ok(diffs)
+}
+
+/// A `/etc/uci-defaults` script that turns the old configs of `diffs` into the new ones: it
+/// creates the added packages, applies every change in one `uci batch` and commits it, then
+/// deletes the removed packages. Scripts there run once on the first boot after flashing.
+///
+/// Some packages aren't in the image but written on first boot before the scripts run, like
+/// `network` by `config_generate`. Added packages are emptied first, so that their anonymous
+/// sections don't end up there twice.
+pub fn defaults_script(diffs: &[PackageDiff]) -> String {
+    let mut out =
+        String::from("#!/bin/sh\n# Generated by uciedit from the changes to the stock config.\n\n");
+    // a String can't fail to write
+    for diff in diffs.iter().filter(|d| d.status == PackageStatus::Added) {
+        let _ = writeln!(out, ": > /etc/config/{}", diff.package);
+    }
+    let changed = diffs.iter().filter(|d| d.status != PackageStatus::Removed);
+    let mut batch = String::new();
+    for diff in changed.clone() {
+        batch.push_str(&diff.diff.batch(&diff.package));
+    }
+    for diff in changed {
+        let _ = writeln!(batch, "commit {}", diff.package);
+    }
+    if !batch.is_empty() {
+        let _ = write!(out, "uci -q batch <<'EOF'\n{batch}EOF\n");
+    }
+    for diff in diffs.iter().filter(|d| d.status == PackageStatus::Removed) {
+        let _ = writeln!(out, "rm -f /etc/config/{}", diff.package);
+    }
+    out.push_str("\nexit 0\n");
+    out
+}
+
+#[test]
+fn test_overlay() {
+    let dir = std::env::temp_dir().join(format!("uciedit-{}-overlay", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let (rom, etc) = (dir.join("rom"), dir.join("etc"));
+    fs::create_dir_all(&rom).unwrap();
+    fs::create_dir_all(&etc).unwrap();
+    let system = "config system\n\toption hostname OpenWrt\n";
+    fs::write(rom.join("system"), system).unwrap();
+    fs::write(
+        etc.join("system"),
+        "config system\n\toption hostname 'ap1'\n",
+    )
+    .unwrap();
+    fs::write(rom.join("dhcp"), "config dnsmasq\n").unwrap();
+    fs::write(etc.join("dhcp"), "# untouched\nconfig dnsmasq\n").unwrap();
+    fs::write(rom.join("ubootenv"), "").unwrap();
+    fs::write(etc.join("secprof"), "config profile kids\n").unwrap();
+    fs::write(etc.join(".system.swp"), "junk").unwrap();
+
+    let diffs = diff_dirs(&rom, &etc).unwrap();
+    let summary: Vec<_> = diffs
+        .iter()
+        .map(|d| (d.package.as_str(), d.status))
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("secprof", PackageStatus::Added),
+            ("system", PackageStatus::Changed),
+            ("ubootenv", PackageStatus::Removed),
+        ]
+    );
+    assert_eq!(
+        defaults_script(&diffs),
+        "#!/bin/sh\n# Generated by uciedit from the changes to the stock config.\n\n\
+         : > /etc/config/secprof\n\
+         uci -q batch <<'EOF'\n\
+         set secprof.kids=profile\n\
+         set system.cfg01e48a.hostname='ap1'\n\
+         commit secprof\n\
+         commit system\n\
+         EOF\n\
+         rm -f /etc/config/ubootenv\n\
+         \n\
+         exit 0\n"
+    );
+
+    // a package generated on first boot is replaced rather than added to
+    fs::write(
+        etc.join("network"),
+        "config device\n\toption name 'br-lan'\n",
+    )
+    .unwrap();
+    let diffs = diff_dirs(&rom, &etc).unwrap();
+    let script = defaults_script(&diffs);
+    let target = dir.join("target");
+    fs::create_dir_all(&target).unwrap();
+    fs::write(
+        target.join("network"),
+        "config device\n\toption name 'br-lan'\n",
+    )
+    .unwrap();
+    let prelude = script.split("uci -q batch").next().unwrap();
+    let prelude = prelude.replace("/etc/config", &target.display().to_string());
+    let status = std::process::Command::new("sh")
+        .args(["-c", &prelude])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(fs::read_to_string(target.join("network")).unwrap(), "");
+    assert!(script.contains("add network device\nset network.@device[0].name='br-lan'\n"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,787 @@
+//! Addressing sections and options with the paths the `uci` command line tool takes, such as
+//! `lan.ipaddr` or `@rule[-1].name`. Paths are relative to the package being read or edited,
+//! so `uci get firewall.@rule[2].name` becomes `ctx.get_path("@rule[2].name")` on the context
+//! for `/etc/config/firewall`.
+
+use crate::{
+    bail, error, section_end, Arena, Error, Line, Lines, Sections, SectionsMut, Token, UciValue,
+};
+use std::fmt;
+use std::str::FromStr;
+
+/// Which section a [`UciPath`] refers to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SectionSelector {
+    /// A named section, `lan`.
+    Named(String),
+    /// The `index`th section of a type, `@rule[2]`. An empty type (`@[0]`) matches sections of
+    /// any type, and negative indexes count from the end.
+    Indexed { ty: String, index: isize },
+}
+
+/// A `section` or `section.option` path within a package.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UciPath {
+    pub section: SectionSelector,
+    pub option: Option<String>,
+}
+
+impl FromStr for UciPath {
+    type Err = Error;
+
+    fn from_str(path: &str) -> Result<Self, Error> {
+        let (section, option) = match path.strip_prefix('@') {
+            Some(ext) => {
+                let Some((ty, rest)) = ext.split_once('[') else {
+                    bail!("invalid path {path:?}: expected @type[index]")
+                };
+                let Some((index, rest)) = rest.split_once(']') else {
+                    bail!("invalid path {path:?}: unclosed [")
+                };
+                if !ty.is_empty() && !is_valid_type(ty) {
+                    bail!("invalid section type {ty:?} in {path:?}");
+                }
+                let index = index
+                    .parse()
+                    .map_err(|_| error!("invalid section index {index:?} in {path:?}"))?;
+                let option = match rest {
+                    "" => None,
+                    rest => match rest.strip_prefix('.') {
+                        Some(option) => Some(option),
+                        None => bail!("invalid path {path:?}: expected . after ]"),
+                    },
+                };
+                let ty = ty.to_owned();
+                (SectionSelector::Indexed { ty, index }, option)
+            }
+            None => {
+                let (name, option) = match path.split_once('.') {
+                    Some((name, option)) => (name, Some(option)),
+                    None => (path, None),
+                };
+                if !is_valid_name(name) {
+                    bail!("invalid section name {name:?} in {path:?}");
+                }
+                (SectionSelector::Named(name.to_owned()), option)
+            }
+        };
+        if let Some(option) = option {
+            if !is_valid_name(option) {
+                bail!("invalid option name {option:?} in {path:?}");
+            }
+        }
+        Ok(UciPath {
+            section,
+            option: option.map(str::to_owned),
+        })
+    }
+}
+
+impl fmt::Display for UciPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.section {
+            SectionSelector::Named(name) => write!(f, "{name}")?,
+            SectionSelector::Indexed { ty, index } => write!(f, "@{ty}[{index}]")?,
+        }
+        if let Some(option) = &self.option {
+            write!(f, ".{option}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Section and option names may only contain alphanumerics and `_`, as in libuci.
+pub fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+}
+
+/// Section types may contain any printable ASCII character, as in libuci.
+pub fn is_valid_type(ty: &str) -> bool {
+    !ty.is_empty() && ty.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// The line index of the section header `selector` refers to.
+pub(crate) fn find_section(lines: &Lines, selector: &SectionSelector) -> Option<usize> {
+    let headers = lines.iter().enumerate().filter_map(|(i, line)| match line {
+        Line::Section { ty, name } => Some((i, ty, name)),
+        _ => None,
+    });
+    match selector {
+        SectionSelector::Named(wanted) => {
+            let named = headers
+                .clone()
+                .find(|(_, _, name)| name.is_some_and(|name| name == **wanted));
+            let anonymous = || {
+                headers
+                    .filter(|(_, _, name)| name.is_none())
+                    .find(|&(i, _, _)| anonymous_id(lines, i) == *wanted)
+            };
+            named.or_else(anonymous).map(|(i, _, _)| i)
+        }
+        SectionSelector::Indexed { ty: wanted, index } => {
+            let matching: Vec<usize> = headers
+                .filter(|(_, ty, _)| wanted.is_empty() || **ty == **wanted)
+                .map(|(i, _, _)| i)
+                .collect();
+            let index = match *index {
+                index if index < 0 => matching.len().checked_sub(index.unsigned_abs())?,
+                index => index as usize,
+            };
+            matching.get(index).copied()
+        }
+    }
+}
+
+/// The `cfgXXXXXX` name libuci gives the anonymous section at line `index`: its position among
+/// all sections of the package and a hash of its type. `uci show` prints these with `-X`, and
+/// the delta files in `/tmp/.uci` use them to refer to anonymous sections.
+pub(crate) fn anonymous_id(lines: &Lines, index: usize) -> String {
+    let position = lines[..=index]
+        .iter()
+        .filter(|line| matches!(line, Line::Section { .. }))
+        .count();
+    let Line::Section { ty, .. } = &lines[index] else {
+        unreachable!("line {index} does not start a section")
+    };
+    anonymous_name(position, &ty.as_str())
+}
+
+/// The name libuci gives an anonymous section of type `ty` that is the `position`th section
+/// it allocated for the package, counting from 1.
+pub fn anonymous_name(position: usize, ty: &str) -> String {
+    // djb hash over C chars, which are signed on some platforms and unsigned on others
+    let mut hash: u32 = 5381;
+    for b in ty.bytes() {
+        hash = hash
+            .wrapping_mul(33)
+            .wrapping_add(b as std::ffi::c_char as i32 as u32);
+    }
+    hash &= 0x7fff_ffff;
+    format!("cfg{:02x}{:04x}", position, hash % (1 << 16))
+}
+
+/// The section name, or for anonymous sections the name libuci generates.
+pub(crate) fn section_id(lines: &Lines, index: usize) -> String {
+    match &lines[index] {
+        Line::Section {
+            name: Some(name), ..
+        } => name.as_str().into_owned(),
+        _ => anonymous_id(lines, index),
+    }
+}
+
+/// The lines of the section at `index`, including the comments right above it.
+pub(crate) fn section_range(lines: &Lines, index: usize) -> std::ops::RangeInclusive<usize> {
+    let mut first = index;
+    while first > 0
+        && matches!(
+            lines[first - 1],
+            Line::Comment { indent: false, .. } | Line::Skip
+        )
+    {
+        first -= 1;
+    }
+    first..=section_end(lines, index)
+}
+
+/// Takes the section at `index`, with the comments right above it, out of `lines` along with
+/// the blank line that separated it from the next one. Returns its lines and the index of its
+/// header among them.
+pub(crate) fn take_section<'a>(lines: &mut Lines<'a>, index: usize) -> (Vec<Line<'a>>, usize) {
+    let range = section_range(lines, index);
+    let start = *range.start();
+    (take_lines(lines, range), index - start)
+}
+
+/// Takes `range` out of `lines`, along with the blank line after it, or the one before it if
+/// it was at the end, so that removing a section doesn't leave two blank lines behind.
+pub(crate) fn take_lines<'a>(
+    lines: &mut Lines<'a>,
+    range: std::ops::RangeInclusive<usize>,
+) -> Vec<Line<'a>> {
+    let start = *range.start();
+    let taken = lines.splice(range, []).collect();
+    if matches!(lines.get(start), Some(Line::Empty)) {
+        lines.remove(start);
+    } else if start == lines.len() && start > 0 && matches!(lines[start - 1], Line::Empty) {
+        lines.remove(start - 1);
+    }
+    taken
+}
+
+/// Where the `n`th section starts, comments included.
+pub(crate) fn nth_section_start(lines: &Lines, n: usize) -> Option<usize> {
+    let (index, _) = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| matches!(line, Line::Section { .. }))
+        .nth(n)?;
+    Some(*section_range(lines, index).start())
+}
+
+/// Where a section placed right after the one at `index` starts: before the comments of the
+/// next section, or `None` at the end.
+pub(crate) fn after_section(lines: &Lines, index: usize) -> Option<usize> {
+    let end = section_end(lines, index);
+    let next = end
+        + 1
+        + lines[end + 1..]
+            .iter()
+            .position(|line| matches!(line, Line::Section { .. } | Line::Package { .. }))?;
+    match lines[next] {
+        Line::Section { .. } => Some(*section_range(lines, next).start()),
+        _ => Some(next),
+    }
+}
+
+/// Inserts the lines of a section at `at`, or at the end, with blank lines between it and its
+/// neighbours. Returns the index of its first line.
+pub(crate) fn insert_section<'a>(
+    lines: &mut Lines<'a>,
+    at: Option<usize>,
+    mut section: Vec<Line<'a>>,
+) -> usize {
+    let at = match at {
+        Some(at) if at < lines.len() => at,
+        _ => {
+            if !matches!(lines.last(), None | Some(Line::Empty)) {
+                lines.push(Line::Empty);
+            }
+            let start = lines.len();
+            lines.extend(section);
+            return start;
+        }
+    };
+    section.push(Line::Empty);
+    let mut start = at;
+    if at > 0 && !matches!(lines[at - 1], Line::Empty) {
+        section.insert(0, Line::Empty);
+        start += 1;
+    }
+    lines.splice(at..at, section);
+    start
+}
+
+/// The line indexes of a section's options and lists, header excluded.
+pub(crate) fn section_body(lines: &Lines, index: usize) -> std::ops::Range<usize> {
+    let end = lines[index + 1..]
+        .iter()
+        .position(|line| matches!(line, Line::Section { .. } | Line::Package { .. }))
+        .map_or(lines.len(), |len| index + 1 + len);
+    index + 1..end
+}
+
+/// The value of an option the way libuci folds it: a later `option` replaces everything before
+/// it, and a `list` item after an `option` turns it into a list.
+pub(crate) fn option_value(lines: &Lines, index: usize, name: &str) -> Option<UciValue> {
+    let mut value = None;
+    for line in &lines[section_body(lines, index)] {
+        match line {
+            Line::Option { option, value: v } if *option == *name => {
+                value = Some(UciValue::Option(v.as_str().into_owned()));
+            }
+            Line::List { list, item } if *list == *name => {
+                let item = item.as_str().into_owned();
+                value = Some(match value {
+                    None => UciValue::List(vec![item]),
+                    Some(UciValue::Option(first)) => UciValue::List(vec![first, item]),
+                    Some(UciValue::List(mut items)) => {
+                        items.push(item);
+                        UciValue::List(items)
+                    }
+                });
+            }
+            _ => (),
+        }
+    }
+    value
+}
+
+/// Replaces every `option` and `list` line of `option` in the section at `index` with a single
+/// `option` line, or appends one to the section.
+pub(crate) fn set_option<'a>(
+    lines: &mut Lines<'a>,
+    arena: &'a Arena,
+    index: usize,
+    option: &str,
+    value: &str,
+) {
+    let mut new = Some(Line::Option {
+        option: Token::from_string(option.to_owned(), arena),
+        value: Token::from_string(value.to_owned(), arena),
+    });
+    for i in section_body(lines, index) {
+        let line = &mut lines[i];
+        if !is_named(line, option) {
+            continue;
+        }
+        match (new.take(), &*line) {
+            // keep the original quoting when nothing changes
+            (Some(_), Line::Option { value: old, .. }) if *old == *value => (),
+            (Some(new), _) => *line = new,
+            (None, _) => *line = Line::Skip,
+        }
+    }
+    if let Some(new) = new {
+        let after = section_end(lines, index);
+        lines.insert(after + 1, new);
+    }
+}
+
+/// Removes every `option` and `list` line of `option` in the section at `index`. Returns false
+/// if there was none.
+pub(crate) fn delete_option(lines: &mut Lines, index: usize, option: &str) -> bool {
+    let mut deleted = false;
+    for i in section_body(lines, index) {
+        if is_named(&lines[i], option) {
+            lines[i] = Line::Skip;
+            deleted = true;
+        }
+    }
+    deleted
+}
+
+fn lookup(lines: &Lines, path: &str) -> Result<Option<UciValue>, Error> {
+    let path: UciPath = path.parse()?;
+    let Some(index) = find_section(lines, &path.section) else {
+        return Ok(None);
+    };
+    Ok(match &path.option {
+        Some(option) => option_value(lines, index, option),
+        None => match &lines[index] {
+            Line::Section { ty, .. } => Some(UciValue::Option(ty.as_str().into_owned())),
+            _ => unreachable!(),
+        },
+    })
+}
+
+/// Every option of a section with its value, in the order libuci keeps them: where each was
+/// first set.
+pub(crate) fn options(lines: &Lines, index: usize) -> Vec<(String, UciValue)> {
+    let mut names: Vec<String> = Vec::new();
+    for line in &lines[section_body(lines, index)] {
+        if let Line::Option { option: name, .. } | Line::List { list: name, .. } = line {
+            if !names.iter().any(|n| *name == **n) {
+                names.push(name.as_str().into_owned());
+            }
+        }
+    }
+    names
+        .into_iter()
+        .filter_map(|name| {
+            let value = option_value(lines, index, &name)?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+fn resolve(lines: &Lines, path: &str) -> Result<Option<String>, Error> {
+    let path: UciPath = path.parse()?;
+    Ok(find_section(lines, &path.section).map(|index| section_id(lines, index)))
+}
+
+pub(crate) fn is_named(line: &Line, name: &str) -> bool {
+    match line {
+        Line::Option { option, .. } => *option == *name,
+        Line::List { list, .. } => *list == *name,
+        _ => false,
+    }
+}
+
+impl Sections<'_> {
+    /// Looks up a path like `uci get`. A path without an option resolves to the section type.
+    pub fn get_path(&self, path: &str) -> Result<Option<UciValue>, Error> {
+        lookup(self.lines, path)
+    }
+
+    /// The section name, or the `cfgXXXXXX` name libuci uses for an anonymous section.
+    pub fn id(&self) -> Result<String, Error> {
+        if !self.started {
+            bail!("call step at least once");
+        }
+        Ok(section_id(self.lines, self.index))
+    }
+
+    /// The options of the current section, in the order `uci show` lists them.
+    pub fn options(&self) -> Result<Vec<(String, UciValue)>, Error> {
+        if !self.started {
+            bail!("call step at least once");
+        }
+        Ok(options(self.lines, self.index))
+    }
+
+    /// The name of the section `path` refers to, or its `cfgXXXXXX` name if it is anonymous.
+    /// Any option in the path is ignored.
+    pub fn resolve(&self, path: &str) -> Result<Option<String>, Error> {
+        resolve(self.lines, path)
+    }
+}
+
+/// The path based edits move lines around, so each of them first applies a pending
+/// [`remove`](SectionsMut::remove) and then resets the cursor as if [`step`](SectionsMut::step)
+/// had never been called.
+impl SectionsMut<'_, '_> {
+    /// Looks up a path like `uci get`. A path without an option resolves to the section type.
+    pub fn get_path(&self, path: &str) -> Result<Option<UciValue>, Error> {
+        lookup(self.lines, path)
+    }
+
+    /// The section name, or the `cfgXXXXXX` name libuci uses for an anonymous section.
+    pub fn id(&self) -> Result<String, Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        Ok(section_id(self.lines, self.index))
+    }
+
+    /// The options of the current section, in the order `uci show` lists them.
+    pub fn options(&self) -> Result<Vec<(String, UciValue)>, Error> {
+        if self.section_start.is_none() {
+            bail!("call step at least once");
+        }
+        Ok(options(self.lines, self.index))
+    }
+
+    /// The name of the section `path` refers to, or its `cfgXXXXXX` name if it is anonymous.
+    /// Any option in the path is ignored.
+    pub fn resolve(&self, path: &str) -> Result<Option<String>, Error> {
+        resolve(self.lines, path)
+    }
+
+    /// Like `uci set`. For an option, replaces any existing option or list of that name. For a
+    /// section, changes its type, or creates a named section if it does not exist yet.
+    pub fn set_path(&mut self, path: &str, value: &str) -> Result<(), Error> {
+        self.rewind();
+        let parsed: UciPath = path.parse()?;
+        let found = find_section(self.lines, &parsed.section);
+        let Some(option) = &parsed.option else {
+            if !is_valid_type(value) {
+                bail!("invalid section type {value:?}");
+            }
+            let ty = Token::from_string(value.to_owned(), self.arena);
+            match (found, &parsed.section) {
+                (Some(index), _) => {
+                    if let Line::Section { ty: old, .. } = &mut self.lines[index] {
+                        if *old != *value {
+                            *old = ty;
+                        }
+                    }
+                }
+                (None, SectionSelector::Named(name)) => {
+                    if !self.lines.is_empty() {
+                        self.lines.push(Line::Empty);
+                    }
+                    self.lines.push(Line::Section {
+                        ty,
+                        name: Some(Token::from_string(name.clone(), self.arena)),
+                    });
+                }
+                (None, _) => bail!("entry not found: {path}"),
+            }
+            return Ok(());
+        };
+        let Some(index) = found else {
+            bail!("entry not found: {path}")
+        };
+        set_option(self.lines, self.arena, index, option, value);
+        Ok(())
+    }
+
+    /// Like `uci delete`. Removes a whole section, including the comments right above it, or
+    /// every `option` and `list` line of an option. Returns false if there was nothing to delete.
+    pub fn delete_path(&mut self, path: &str) -> Result<bool, Error> {
+        self.rewind();
+        let parsed: UciPath = path.parse()?;
+        let Some(index) = find_section(self.lines, &parsed.section) else {
+            return Ok(false);
+        };
+        match &parsed.option {
+            Some(option) => Ok(delete_option(self.lines, index, option)),
+            None => {
+                take_lines(self.lines, section_range(self.lines, index));
+                Ok(true)
+            }
+        }
+    }
+
+    /// Like `uci add_list`. Appends an item after the existing items, turning a plain option of
+    /// the same name into a list.
+    pub fn add_list_path(&mut self, path: &str, value: &str) -> Result<(), Error> {
+        self.rewind();
+        let parsed: UciPath = path.parse()?;
+        let Some(option) = &parsed.option else {
+            bail!("add_list needs an option: {path}")
+        };
+        let Some(index) = find_section(self.lines, &parsed.section) else {
+            bail!("entry not found: {path}")
+        };
+
+        let mut last = None;
+        for i in section_body(self.lines, index) {
+            let (list, item) = match &self.lines[i] {
+                Line::Option {
+                    option: name,
+                    value,
+                } if *name == **option => (*name, *value),
+                Line::List { list, .. } if *list == **option => {
+                    last = Some(i);
+                    continue;
+                }
+                _ => continue,
+            };
+            // an option overrides everything before it
+            for j in index + 1..i {
+                if is_named(&self.lines[j], option) {
+                    self.lines[j] = Line::Skip;
+                }
+            }
+            self.lines[i] = Line::List { list, item };
+            last = Some(i);
+        }
+        let after = last.unwrap_or_else(|| section_end(self.lines, index));
+        self.lines.insert(
+            after + 1,
+            Line::List {
+                list: Token::from_string(option.clone(), self.arena),
+                item: Token::from_string(value.to_owned(), self.arena),
+            },
+        );
+        Ok(())
+    }
+
+    /// Like `uci del_list`. Removes every list item equal to `value`. Returns false if there was
+    /// none; plain options are left alone.
+    pub fn del_list_path(&mut self, path: &str, value: &str) -> Result<bool, Error> {
+        self.rewind();
+        let parsed: UciPath = path.parse()?;
+        let Some(option) = &parsed.option else {
+            bail!("del_list needs an option: {path}")
+        };
+        let Some(index) = find_section(self.lines, &parsed.section) else {
+            return Ok(false);
+        };
+        let mut deleted = false;
+        for i in section_body(self.lines, index) {
+            if let Line::List { list, item } = &self.lines[i] {
+                if *list == **option && *item == *value {
+                    self.lines[i] = Line::Skip;
+                    deleted = true;
+                }
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Like `uci rename`. Renames a section, or every `option` and `list` line of an option,
+    /// replacing any option that already has the new name.
+    pub fn rename_path(&mut self, path: &str, name: &str) -> Result<(), Error> {
+        self.rewind();
+        let parsed: UciPath = path.parse()?;
+        if !is_valid_name(name) {
+            bail!("invalid name {name:?}");
+        }
+        let Some(index) = find_section(self.lines, &parsed.section) else {
+            bail!("entry not found: {path}")
+        };
+        let new_name = Token::from_string(name.to_owned(), self.arena);
+        let Some(option) = &parsed.option else {
+            if let Some(other) = find_section(self.lines, &SectionSelector::Named(name.into())) {
+                if other != index {
+                    bail!("section {name:?} already exists");
+                }
+            }
+            if let Line::Section { name, .. } = &mut self.lines[index] {
+                *name = Some(new_name);
+            }
+            return Ok(());
+        };
+        if option == name {
+            return Ok(());
+        }
+        let mut renamed = false;
+        for i in section_body(self.lines, index) {
+            match &mut self.lines[i] {
+                line if is_named(line, name) => *line = Line::Skip,
+                Line::Option { option: o, .. } | Line::List { list: o, .. } if *o == **option => {
+                    *o = new_name;
+                    renamed = true;
+                }
+                _ => (),
+            }
+        }
+        if !renamed {
+            bail!("entry not found: {path}");
+        }
+        Ok(())
+    }
+
+    /// Like `uci reorder`. Moves a section, along with the comments right above it, so that it
+    /// becomes the `index`th section of the package.
+    pub fn reorder_path(&mut self, path: &str, index: usize) -> Result<(), Error> {
+        self.rewind();
+        let parsed: UciPath = path.parse()?;
+        if parsed.option.is_some() {
+            bail!("only sections can be reordered: {path}");
+        }
+        let Some(current) = find_section(self.lines, &parsed.section) else {
+            bail!("entry not found: {path}")
+        };
+        let (moved, _) = take_section(self.lines, current);
+        let target = nth_section_start(self.lines, index);
+        insert_section(self.lines, target, moved);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+const FIREWALL: &str = r"
+config defaults
+	option input 'REJECT'
+
+config zone lan
+	option name 'lan'
+	list network 'lan'
+
+# allow ping
+config rule
+	option name 'Allow-Ping'
+	option proto 'icmp'
+	option target 'ACCEPT'
+
+config rule
+	option name 'Allow-DHCP'
+	list proto 'udp'
+	option target 'ACCEPT'
+";
+
+#[test]
+fn test_get_path() {
+    use crate::parse_config_string;
+
+    let opt = |s: &str| Some(UciValue::Option(s.into()));
+    parse_config_string(FIREWALL, |ctx| {
+        assert_eq!(ctx.get_path("lan")?, opt("zone"));
+        assert_eq!(ctx.get_path("lan.name")?, opt("lan"));
+        assert_eq!(
+            ctx.get_path("lan.network")?,
+            Some(UciValue::List(vec!["lan".into()]))
+        );
+        assert_eq!(ctx.get_path("@rule[0].name")?, opt("Allow-Ping"));
+        assert_eq!(ctx.get_path("@rule[1].name")?, opt("Allow-DHCP"));
+        assert_eq!(ctx.get_path("@rule[-1].name")?, opt("Allow-DHCP"));
+        assert_eq!(ctx.get_path("@rule[-2].name")?, opt("Allow-Ping"));
+        assert_eq!(ctx.get_path("@[0].input")?, opt("REJECT"));
+        assert_eq!(ctx.get_path("@rule[2].name")?, None);
+        assert_eq!(ctx.get_path("@rule[-3].name")?, None);
+        assert_eq!(ctx.get_path("wan.name")?, None);
+        assert_eq!(ctx.get_path("lan.missing")?, None);
+        assert!(ctx.get_path("@rule.name").is_err());
+        assert!(ctx.get_path("@rule[x].name").is_err());
+        assert!(ctx.get_path("bad-name.name").is_err());
+        Ok(())
+    })
+    .unwrap();
+
+    let round_trip: UciPath = "@wifi-iface[-1].ssid".parse().unwrap();
+    assert_eq!(round_trip.to_string(), "@wifi-iface[-1].ssid");
+}
+
+#[test]
+fn test_edit_paths() {
+    use crate::rewrite_config_string;
+
+    let edited = rewrite_config_string(FIREWALL.to_string(), |mut ctx| {
+        ctx.set_path("@rule[0].target", "DROP")?;
+        ctx.set_path("@rule[0].name", "Allow-Ping")?;
+        ctx.set_path("@rule[-1].proto", "udp")?;
+        ctx.add_list_path("lan.network", "guest")?;
+        ctx.add_list_path("@rule[0].proto", "icmpv6")?;
+        assert!(ctx.del_list_path("lan.network", "lan")?);
+        assert!(!ctx.del_list_path("lan.network", "missing")?);
+        assert!(ctx.delete_path("@defaults[0]")?);
+        assert!(!ctx.delete_path("lan.missing")?);
+        ctx.set_path("wan", "zone")?;
+        ctx.set_path("wan.name", "wan side")?;
+        ctx.set_path("lan", "zone")?;
+        assert!(ctx.set_path("@zone[5].name", "x").is_err());
+        assert!(ctx.set_path("missing.name", "x").is_err());
+        Ok(())
+    })
+    .unwrap();
+
+    let expected = r"
+config zone lan
+	option name 'lan'
+	list network guest
+
+# allow ping
+config rule
+	option name 'Allow-Ping'
+	list proto 'icmp'
+	list proto icmpv6
+	option target DROP
+
+config rule
+	option name 'Allow-DHCP'
+	option proto udp
+	option target 'ACCEPT'
+
+config zone wan
+	option name 'wan side'
+";
+    println!("===Edited==={edited}===Expected==={expected}=====");
+    assert_eq!(edited, expected);
+}
+
+#[test]
+fn test_anonymous_ids_rename_reorder() {
+    use crate::{parse_config_string, rewrite_config_string};
+
+    // the names `uci show -X firewall` prints for the sections above
+    parse_config_string(FIREWALL, |mut ctx| {
+        let mut ids = Vec::new();
+        while ctx.step() {
+            ids.push(ctx.id()?);
+        }
+        assert_eq!(ids, ["cfg01e63d", "lan", "cfg0392bd", "cfg0492bd"]);
+        assert_eq!(
+            ctx.get_path("cfg0492bd.name")?,
+            Some(UciValue::Option("Allow-DHCP".into()))
+        );
+        Ok(())
+    })
+    .unwrap();
+
+    let edited = rewrite_config_string(FIREWALL.to_string(), |mut ctx| {
+        ctx.reorder_path("@rule[1]", 0)?;
+        ctx.reorder_path("lan", 10)?;
+        ctx.rename_path("@rule[-1].proto", "family")?;
+        ctx.rename_path("@defaults[0]", "defaults")?;
+        assert!(ctx.rename_path("@rule[0]", "defaults").is_err());
+        assert!(ctx.rename_path("@rule[0].missing", "x").is_err());
+        Ok(())
+    })
+    .unwrap();
+
+    let expected = r"
+config rule
+	option name 'Allow-DHCP'
+	list proto 'udp'
+	option target 'ACCEPT'
+
+config defaults defaults
+	option input 'REJECT'
+
+# allow ping
+config rule
+	option name 'Allow-Ping'
+	option family 'icmp'
+	option target 'ACCEPT'
+
+config zone lan
+	option name 'lan'
+	list network 'lan'
+";
+    println!("===Edited==={edited}===Expected==={expected}=====");
+    assert_eq!(edited, expected);
+}
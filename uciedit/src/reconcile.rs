@@ -0,0 +1,241 @@
+//! Declaring which sections should exist instead of editing them one by one. A daemon that owns
+//! a few rules in `/etc/config/firewall` lists them, and [`SectionsMut::reconcile`] updates the
+//! ones that are there, appends the missing ones and reports what it did, so that the service
+//! only needs a reload when something changed.
+
+use crate::query::section_id;
+use crate::{Error, Line, SectionsMut, UciPatch, UciSection};
+
+/// The ids of the sections an edit touched: their names, or `cfgXXXXXX` for anonymous ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub updated: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.updated.is_empty() && self.removed.is_empty()
+    }
+
+    /// Adds the changes of another edit of the same config.
+    pub fn extend(&mut self, other: Changes) {
+        self.added.extend(other.added);
+        self.updated.extend(other.updated);
+        self.removed.extend(other.removed);
+    }
+}
+
+/// Like the path based edits, these first apply a pending [`remove`](SectionsMut::remove) and
+/// then walk the config from the start, leaving the cursor as if [`step`](SectionsMut::step)
+/// had returned false.
+impl<'a> SectionsMut<'_, 'a> {
+    /// Writes `section` over the section named `name`, or appends it under that name.
+    pub fn upsert<S: UciSection<'a>>(&mut self, name: &str, section: S) -> Result<Changes, Error> {
+        self.reconcile_named(None, [(name.to_owned(), section)], |_| false)
+    }
+
+    /// Makes the sections of type `ty` match `desired`, pairing them up by `key`. The first
+    /// section with the key of a desired one is updated in place, keeping its position, name,
+    /// comments and any options `S` doesn't declare. Desired sections without a match, or
+    /// without a key, are appended as anonymous sections. Sections that match none of them are
+    /// removed if `owned` says they carry the caller's marker, and kept otherwise. Sections
+    /// that don't read as `S` are left alone.
+    pub fn reconcile<S, K>(
+        &mut self,
+        ty: &str,
+        desired: impl IntoIterator<Item = S>,
+        key: impl Fn(&S) -> Option<K>,
+        owned: impl Fn(&S) -> bool,
+    ) -> Result<Changes, Error>
+    where
+        S: UciSection<'a>,
+        K: PartialEq,
+    {
+        let desired = desired
+            .into_iter()
+            .map(|section| (key(&section), None, section))
+            .collect();
+        let write = |ctx: &mut Self, _: &S, section| ctx.set(section);
+        self.reconcile_by(Some(ty), desired, |_, section| key(section), owned, write)
+    }
+
+    /// Like [`reconcile`](Self::reconcile), but only writes what `patch` makes of an existing
+    /// section and the desired one it matched, so that options the caller doesn't manage keep
+    /// whatever value a user gave them. Missing sections are still appended whole.
+    pub fn reconcile_patch<S, K, P>(
+        &mut self,
+        ty: &str,
+        desired: impl IntoIterator<Item = S>,
+        key: impl Fn(&S) -> Option<K>,
+        owned: impl Fn(&S) -> bool,
+        patch: impl Fn(&S, S) -> P,
+    ) -> Result<Changes, Error>
+    where
+        S: UciSection<'a>,
+        K: PartialEq,
+        P: UciPatch<'a>,
+    {
+        let desired = desired
+            .into_iter()
+            .map(|section| (key(&section), None, section))
+            .collect();
+        let write = |ctx: &mut Self, existing: &S, section| ctx.patch(patch(existing, section));
+        self.reconcile_by(Some(ty), desired, |_, section| key(section), owned, write)
+    }
+
+    /// Like [`reconcile`](Self::reconcile), but pairs sections up by their name and appends
+    /// missing ones under it. With a `ty` of `None`, named sections of any type are matched.
+    pub fn reconcile_named<S: UciSection<'a>>(
+        &mut self,
+        ty: Option<&str>,
+        desired: impl IntoIterator<Item = (String, S)>,
+        owned: impl Fn(&S) -> bool,
+    ) -> Result<Changes, Error> {
+        let desired = desired
+            .into_iter()
+            .map(|(name, section)| (Some(name.clone()), Some(name), section))
+            .collect();
+        let write = |ctx: &mut Self, _: &S, section| ctx.set(section);
+        self.reconcile_by(ty, desired, |name, _| name.map(str::to_owned), owned, write)
+    }
+
+    fn reconcile_by<S, K>(
+        &mut self,
+        ty: Option<&str>,
+        desired: Vec<(Option<K>, Option<String>, S)>,
+        key: impl Fn(Option<&str>, &S) -> Option<K>,
+        owned: impl Fn(&S) -> bool,
+        write: impl Fn(&mut Self, &S, S) -> Result<(), Error>,
+    ) -> Result<Changes, Error>
+    where
+        S: UciSection<'a>,
+        K: PartialEq,
+    {
+        self.rewind();
+        let mut changes = Changes::default();
+        let mut pending: Vec<_> = desired.into_iter().map(Some).collect();
+        while self.step() {
+            let section_ty = self.ty()?;
+            if ty.is_some_and(|ty| section_ty != ty) {
+                continue;
+            }
+            let Ok(existing) = self.get::<S>() else {
+                continue;
+            };
+            let found = key(self.name()?.as_deref(), &existing).and_then(|key| {
+                pending
+                    .iter()
+                    .position(|d| matches!(d, Some((Some(k), ..)) if *k == key))
+            });
+            match found {
+                Some(i) => {
+                    let (_, _, section) = pending[i].take().unwrap();
+                    if self.update(|ctx| write(ctx, &existing, section))? {
+                        changes.updated.push(self.id()?);
+                    }
+                }
+                None if owned(&existing) => {
+                    changes.removed.push(self.id()?);
+                    self.remove()?;
+                }
+                None => (),
+            }
+        }
+        for (_, name, section) in pending.into_iter().flatten() {
+            self.push(section, name)?;
+            let index = self
+                .lines
+                .iter()
+                .rposition(|line| matches!(line, Line::Section { .. }))
+                .unwrap();
+            changes.added.push(section_id(self.lines, index));
+        }
+        Ok(changes)
+    }
+
+    /// Runs `write` on the current section, and says whether that changed any value. Requoting
+    /// a value the same way doesn't count.
+    fn update(
+        &mut self,
+        write: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<bool, Error> {
+        let before = (self.ty()?, self.options()?);
+        write(self)?;
+        Ok((self.ty()?, self.options()?) != before)
+    }
+}
+
+#[test]
+fn test_reconcile() {
+    use crate::{rewrite_config_string, UciSection};
+
+    #[derive(UciSection, Clone, Debug, Default, PartialEq)]
+    #[uci(ty = "rule")]
+    struct Rule {
+        name: Option<String>,
+        target: String,
+    }
+
+    let rule = |name: &str, target: &str| Rule {
+        name: Some(name.into()),
+        target: target.into(),
+    };
+    let desired = [rule("a", "ACCEPT"), rule("b", "REJECT")];
+    let config = "config rule\n\toption name 'a'\n\toption target 'DROP'\n\toption family 'ipv4'\n\nconfig rule\n\toption name 'old by us'\n\toption target 'DROP'\n\nconfig rule\n\toption name 'theirs'\n\toption target 'DROP'\n\nconfig zone 'lan'\n\toption name 'lan'\n";
+    let reconcile = |config: &str| {
+        let mut changes = None;
+        let edited = rewrite_config_string(config.to_owned(), |mut ctx| {
+            changes = Some(ctx.reconcile(
+                "rule",
+                desired.clone(),
+                |rule| rule.name.clone(),
+                |rule| rule.name.as_deref().is_some_and(|n| n.ends_with("by us")),
+            )?);
+            Ok(())
+        })
+        .unwrap();
+        (edited, changes.unwrap())
+    };
+
+    let (edited, changes) = reconcile(config);
+    assert_eq!(
+        edited,
+        "config rule\n\toption name a\n\toption target ACCEPT\n\toption family 'ipv4'\n\nconfig rule\n\toption name 'theirs'\n\toption target 'DROP'\n\nconfig zone 'lan'\n\toption name 'lan'\n\nconfig rule\n\toption name b\n\toption target REJECT\n"
+    );
+    assert_eq!(changes.updated, ["cfg0192bd"]);
+    assert_eq!(changes.removed.len(), 1);
+    assert_eq!(changes.added.len(), 1);
+
+    let (again, changes) = reconcile(&edited);
+    assert_eq!(again, edited);
+    assert!(changes.is_empty());
+
+    let named = rewrite_config_string(edited, |mut ctx| {
+        assert!(ctx.upsert("b", rule("b", "REJECT"))?.added == ["b"]);
+        assert!(ctx.upsert("b", rule("b", "REJECT"))?.is_empty());
+        assert!(ctx.upsert("b", rule("b", "DROP"))?.updated == ["b"]);
+        Ok(())
+    })
+    .unwrap();
+    assert!(named.ends_with("config rule b\n\toption name b\n\toption target DROP\n"));
+
+    let patched = rewrite_config_string(named, |mut ctx| {
+        let target = |_: &Rule, rule: Rule| RulePatch {
+            target: Some(Some(rule.target)),
+            ..Default::default()
+        };
+        let changes = ctx.reconcile_patch(
+            "rule",
+            [rule("a", "DROP")],
+            |rule| rule.name.clone(),
+            |_| false,
+            target,
+        )?;
+        assert_eq!(changes.updated, ["cfg0192bd"]);
+        Ok(())
+    })
+    .unwrap();
+    assert!(patched.starts_with("config rule\n\toption name a\n\toption target DROP\n"));
+}
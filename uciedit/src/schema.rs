@@ -0,0 +1,320 @@
+//! What sections a config holds, for code that doesn't speak Rust. `#[derive(UciSection)]` also
+//! implements [`UciSchema`], which lists the options of each section type, and
+//! [`json_schema`] and [`typescript`] turn that into files a web UI can check its forms and
+//! `uci` calls against.
+//!
+//! Both describe sections the way rpcd's `uci` object and LuCI hand them over: every option is
+//! a string, or an array of strings for a list. The type the Rust side parses it as is given as
+//! a LuCI validation datatype, such as `uinteger` or `ipaddr`.
+
+use std::fmt::{self, Write};
+
+/// The section types a [`UciSection`](crate::UciSection) reads, and their options.
+pub trait UciSchema {
+    fn schema() -> Vec<SectionSchema>;
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SectionSchema {
+    pub ty: &'static str,
+    pub options: Vec<OptionSchema>,
+    /// Whether options the type doesn't declare are kept in a `#[uci(extra)]` field.
+    pub extra: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OptionSchema {
+    pub name: &'static str,
+    pub kind: OptionKind,
+    /// Required options have no default and can't be left out.
+    pub required: bool,
+    /// The LuCI validation datatype of the value, or of each item of a list.
+    pub datatype: &'static str,
+    /// The Rust type of the field, as written.
+    pub rust_type: &'static str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OptionKind {
+    Option,
+    List,
+    /// A list that may also be one option of space separated words, see `#[uci(split)]`.
+    Split,
+}
+
+/// The LuCI validation datatype for values parsed as the Rust type `name`, without any module
+/// path or generics. Types LuCI has no validator for are just a `string`.
+pub fn datatype_of(name: &str) -> &'static str {
+    match name {
+        "bool" => "bool",
+        "u8" | "u16" | "u32" | "u64" | "u128" | "usize" => "uinteger",
+        "i8" | "i16" | "i32" | "i64" | "i128" | "isize" => "integer",
+        "f32" | "f64" => "float",
+        "Ipv4Addr" => "ip4addr",
+        "Ipv6Addr" => "ip6addr",
+        "IpAddr" => "ipaddr",
+        "IpMask" => "ipmask",
+        "MacAddr" => "macaddr",
+        "Hostname" => "hostname",
+        "PortRange" => "portrange",
+        _ => "string",
+    }
+}
+
+const GENERATED: &str = "Generated from the Rust section types by uciedit, do not edit.";
+
+/// A JSON Schema (draft 2020-12) for the values of `uci get <package>`: an object of sections
+/// by name, each of one of the given types.
+pub fn json_schema(package: &str, sections: &[SectionSchema]) -> String {
+    let string = || Json::object([("type", Json::from("string"))]);
+    let strings = || Json::object([("type", Json::from("array")), ("items", string())]);
+
+    let mut defs = Vec::new();
+    for section in sections {
+        let mut properties = vec![
+            (
+                ".anonymous",
+                Json::object([("type", Json::from("boolean"))]),
+            ),
+            (".index", Json::object([("type", Json::from("integer"))])),
+            (".name", string()),
+            (".type", Json::object([("const", Json::from(section.ty))])),
+        ];
+        let mut required = vec![Json::from(".type")];
+        for option in &section.options {
+            let mut property = match option.kind {
+                OptionKind::Option => vec![("type", Json::from("string"))],
+                OptionKind::List => vec![("type", Json::from("array")), ("items", string())],
+                OptionKind::Split => vec![("anyOf", Json::Array(vec![string(), strings()]))],
+            };
+            property.push(("x-datatype", Json::from(option.datatype)));
+            property.push(("x-rust-type", Json::from(option.rust_type)));
+            properties.push((option.name, Json::object(property)));
+            if option.required {
+                required.push(Json::from(option.name));
+            }
+        }
+        let def = Json::object([
+            ("type", Json::from("object")),
+            ("properties", Json::object(properties)),
+            ("required", Json::Array(required)),
+        ]);
+        defs.push((section.ty, def));
+    }
+    let any_section = sections
+        .iter()
+        .map(|section| Json::object([("$ref", Json::from(format!("#/$defs/{}", section.ty)))]))
+        .collect();
+
+    let schema = Json::object([
+        (
+            "$schema",
+            Json::from("https://json-schema.org/draft/2020-12/schema"),
+        ),
+        ("$comment", Json::from(GENERATED)),
+        ("title", Json::from(package)),
+        ("type", Json::from("object")),
+        (
+            "additionalProperties",
+            Json::object([("oneOf", Json::Array(any_section))]),
+        ),
+        ("$defs", Json::object(defs)),
+    ]);
+    format!("{schema}\n")
+}
+
+/// TypeScript declarations for the sections of `package`: an interface per section type,
+/// named like `SecprofProfile`, and a union of them all.
+pub fn typescript(package: &str, sections: &[SectionSchema]) -> String {
+    let prefix = pascal_case(package);
+    let mut out = format!("// {GENERATED}\n\n");
+    let mut names = Vec::new();
+    for section in sections {
+        let name = format!("{prefix}{}", pascal_case(section.ty));
+        // a String can't fail to write
+        let _ = write_interface(&mut out, package, &name, section);
+        names.push(name);
+    }
+    let _ = writeln!(out, "export type {prefix}Section = {};", names.join(" | "));
+    out
+}
+
+fn write_interface(
+    out: &mut String,
+    package: &str,
+    name: &str,
+    section: &SectionSchema,
+) -> fmt::Result {
+    writeln!(
+        out,
+        "/** A `{}` section of /etc/config/{package}. */",
+        section.ty
+    )?;
+    writeln!(out, "export interface {name} {{")?;
+    writeln!(out, "    '.anonymous': boolean;")?;
+    writeln!(out, "    '.index': number;")?;
+    writeln!(out, "    '.name': string;")?;
+    writeln!(out, "    '.type': {};", ts_string(section.ty))?;
+    for option in &section.options {
+        let ty = match option.kind {
+            OptionKind::Option => "string",
+            OptionKind::List => "string[]",
+            OptionKind::Split => "string | string[]",
+        };
+        let optional = if option.required { "" } else { "?" };
+        writeln!(out, "    /** {} */", option.datatype)?;
+        writeln!(out, "    {}{optional}: {ty};", ts_key(option.name))?;
+    }
+    if section.extra {
+        writeln!(
+            out,
+            "    [option: string]: boolean | number | string | string[] | undefined;"
+        )?;
+    }
+    writeln!(out, "}}\n")
+}
+
+fn ts_string(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+/// Option names are usually identifiers, but a `#[uci(rename)]` may make them anything.
+fn ts_key(name: &str) -> String {
+    let ident = name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+        && name.starts_with(|c: char| !c.is_ascii_digit());
+    match ident {
+        true => name.to_owned(),
+        false => ts_string(name),
+    }
+}
+
+/// `wpapassword` becomes `Wpapassword`, and `rule-6` becomes `Rule6`.
+fn pascal_case(s: &str) -> String {
+    s.split(|c: char| !c.is_ascii_alphanumeric())
+        .flat_map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase())
+                .into_iter()
+                .chain(chars)
+        })
+        .collect()
+}
+
+/// Just enough JSON to write a schema, with the keys in the order they are given.
+enum Json {
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn object<'k>(entries: impl IntoIterator<Item = (&'k str, Json)>) -> Self {
+        let entries = entries.into_iter();
+        Json::Object(entries.map(|(key, value)| (key.into(), value)).collect())
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        let pad = "  ".repeat(indent + 1);
+        match self {
+            Json::String(s) => {
+                f.write_char('"')?;
+                for c in s.chars() {
+                    match c {
+                        '"' => f.write_str("\\\"")?,
+                        '\\' => f.write_str("\\\\")?,
+                        '\n' => f.write_str("\\n")?,
+                        c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+                        c => f.write_char(c)?,
+                    }
+                }
+                f.write_char('"')
+            }
+            Json::Array(items) if items.is_empty() => f.write_str("[]"),
+            Json::Array(items) => {
+                f.write_str("[\n")?;
+                for (i, item) in items.iter().enumerate() {
+                    f.write_str(&pad)?;
+                    item.write(f, indent + 1)?;
+                    f.write_str(if i + 1 < items.len() { ",\n" } else { "\n" })?;
+                }
+                write!(f, "{}]", "  ".repeat(indent))
+            }
+            Json::Object(entries) if entries.is_empty() => f.write_str("{}"),
+            Json::Object(entries) => {
+                f.write_str("{\n")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    write!(f, "{pad}{}: ", Json::from(key.as_str()))?;
+                    value.write(f, indent + 1)?;
+                    f.write_str(if i + 1 < entries.len() { ",\n" } else { "\n" })?;
+                }
+                write!(f, "{}}}", "  ".repeat(indent))
+            }
+        }
+    }
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Self {
+        Json::String(s.into())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Self {
+        Json::String(s)
+    }
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[test]
+fn test_schema() {
+    use crate::UciSection;
+
+    #[derive(UciSection)]
+    #[allow(dead_code)]
+    enum Secprof {
+        Profile {
+            lan_access: bool,
+            #[uci(split)]
+            lan_whitelist: Vec<String>,
+            limit: Option<u32>,
+        },
+        #[uci(ty = "wpapassword")]
+        WpaPassword {
+            #[uci(default)]
+            password: String,
+        },
+    }
+
+    let sections = Secprof::schema();
+    assert_eq!(sections[0].ty, "profile");
+    assert_eq!(
+        sections[0].options[2],
+        OptionSchema {
+            name: "limit",
+            kind: OptionKind::Option,
+            required: false,
+            datatype: "uinteger",
+            rust_type: "Option<u32>",
+        }
+    );
+    assert!(sections[0].options[0].required && !sections[1].options[0].required);
+
+    let ts = typescript("secprof", &sections);
+    assert!(ts.contains("export interface SecprofWpapassword {\n"));
+    assert!(ts.contains("    '.type': 'profile';\n    /** bool */\n    lan_access: string;\n"));
+    assert!(ts.contains("    lan_whitelist?: string | string[];\n"));
+    assert!(ts.ends_with("export type SecprofSection = SecprofProfile | SecprofWpapassword;\n"));
+
+    let json = json_schema("secprof", &sections);
+    assert!(json
+        .contains("      \"required\": [\n        \".type\",\n        \"lan_access\"\n      ]\n"));
+    assert!(json.contains("\"$ref\": \"#/$defs/wpapassword\""));
+}
@@ -0,0 +1,911 @@
+//! Reads and writes sections through serde, for types that already derive `Serialize` and
+//! `Deserialize`. Wrapping one in [`Serde`] makes it a [`UciSection`]:
+//!
+//! - Scalars are options, and sequences are lists. A sequence read from an option gets its
+//!   whitespace separated words, like OpenWrt's shell scripts split them.
+//! - Booleans read the way [`parse_bool`] does and are written as `1` or `0`.
+//! - `None` and `()` remove the option when writing.
+//! - Unit enum variants are written as their name.
+//! - The keys `.type` and `.name` hold the section type and name, the way `ubus call uci get`
+//!   reports them. Anonymous sections have no `.name`, and appending needs a `.type`.
+//!
+//! Writing a section in place changes only the options whose value changed, and keeps the
+//! options the type doesn't serialize.
+
+use crate::__private::ExtraLines;
+use crate::query::{is_named, is_valid_name, is_valid_type, options, section_body};
+use crate::{
+    bail, parse_bool, section_end, Arena, Error, Line, Lines, Token, UciSection, UciValue,
+};
+use ::serde::de::value::{MapDeserializer, SeqDeserializer};
+use ::serde::de::{self, DeserializeOwned, Deserializer, IntoDeserializer, Visitor};
+use ::serde::forward_to_deserialize_any;
+use ::serde::ser::{self, Impossible, Serialize, Serializer};
+use std::fmt;
+
+/// The key holding the section type.
+pub const TYPE: &str = ".type";
+/// The key holding the name of a named section.
+pub const NAME: &str = ".name";
+
+/// A serde type read from and written to a section.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Serde<T>(pub T);
+
+impl<'a, T: Serialize + DeserializeOwned> UciSection<'a> for Serde<T> {
+    fn read(lines: &Lines<'a>, index: usize) -> Result<Self, Error> {
+        let Some(Line::Section { ty, name }) = lines.get(index) else {
+            bail!("line {index} does not start a section")
+        };
+        let mut entries = vec![(TYPE.to_owned(), Value(UciValue::Option(ty.as_str().into())))];
+        if let Some(name) = name {
+            entries.push((
+                NAME.to_owned(),
+                Value(UciValue::Option(name.as_str().into())),
+            ));
+        }
+        entries.extend(
+            options(lines, index)
+                .into_iter()
+                .map(|(name, value)| (name, Value(value))),
+        );
+        T::deserialize(MapDeserializer::new(entries.into_iter()))
+            .map(Serde)
+            .map_err(Error::new)
+    }
+
+    fn write(&self, lines: &mut Lines<'a>, arena: &'a Arena, index: usize) -> Result<(), Error> {
+        let section = Section::serialize(&self.0)?;
+        let Some(Line::Section { ty, name }) = lines.get_mut(index) else {
+            bail!("line {index} does not start a section")
+        };
+        if let Some(new_ty) = &section.ty {
+            if *ty != **new_ty {
+                *ty = Token::from_string(new_ty.clone(), arena);
+            }
+        }
+        if let Some(new_name) = &section.name {
+            if name.is_none_or(|name| name != **new_name) {
+                *name = Some(Token::from_string(new_name.clone(), arena));
+            }
+        }
+
+        let mut values = ExtraLines::new(&section.values(), arena);
+        let body = section_body(lines, index);
+        for line in &mut lines[body] {
+            let Some((_, field)) = section.fields.iter().find(|(name, _)| is_named(line, name))
+            else {
+                continue;
+            };
+            *line = match (field, &*line) {
+                (Field::Missing, _) => Line::Skip,
+                // keep `yes` or `'on'` as they are when the value doesn't change
+                (Field::Bool(new), Line::Option { value, .. })
+                    if parse_bool(&value.as_str()).is_ok_and(|old| old == *new) =>
+                {
+                    values.next(line);
+                    continue;
+                }
+                _ => values.next(line).unwrap_or(Line::Skip),
+            };
+        }
+        let end = section_end(lines, index);
+        lines.splice(end + 1..end + 1, values.rest());
+        Ok(())
+    }
+
+    fn append(
+        &self,
+        lines: &mut Lines<'a>,
+        arena: &'a Arena,
+        name: Option<&'a str>,
+    ) -> Result<(), Error> {
+        let section = Section::serialize(&self.0)?;
+        let Some(ty) = section.ty.clone() else {
+            bail!("appending a section needs its {TYPE:?}")
+        };
+        let name = name.or(section.name.clone().map(|name| arena.alloc(name).as_str()));
+        let values = ExtraLines::new(&section.values(), arena);
+
+        if !lines.is_empty() {
+            lines.push(Line::Empty);
+        }
+        lines.push(Line::Section {
+            ty: Token::from_string(ty, arena),
+            name: name.map(|name| Token::from_str(name, arena)),
+        });
+        lines.extend(values.rest());
+        Ok(())
+    }
+}
+
+/// The errors serde reports, which become an [`Error`] once they leave this module.
+#[derive(Debug)]
+struct Message(String);
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Message {}
+
+impl de::Error for Message {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Message(msg.to_string())
+    }
+}
+
+impl ser::Error for Message {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Message(msg.to_string())
+    }
+}
+
+/// Reads an option value or a list item.
+struct Scalar(String);
+
+macro_rules! parse_scalar {
+    ($($method:ident => $visit:ident,)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Message> {
+            match self.0.parse() {
+                Ok(value) => visitor.$visit(value),
+                Err(_) => Err(de::Error::invalid_value(de::Unexpected::Str(&self.0), &visitor)),
+            }
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for Scalar {
+    type Error = Message;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Message> {
+        visitor.visit_string(self.0)
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Message> {
+        match parse_bool(&self.0) {
+            Ok(value) => visitor.visit_bool(value),
+            Err(_) => Err(de::Error::invalid_value(
+                de::Unexpected::Str(&self.0),
+                &visitor,
+            )),
+        }
+    }
+
+    parse_scalar! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Message> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Message> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Message> {
+        let words = self
+            .0
+            .split_whitespace()
+            .map(|word| Scalar(word.to_owned()));
+        SeqDeserializer::new(words).deserialize_any(visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Message> {
+        visitor.visit_enum(self.0.into_deserializer())
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit unit_struct tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl IntoDeserializer<'_, Message> for Scalar {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Reads the value of an option or list.
+struct Value(UciValue);
+
+macro_rules! scalar_value {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Message> {
+            match self.0 {
+                UciValue::Option(value) => Scalar(value).$method(visitor),
+                list => Value(list).deserialize_any(visitor),
+            }
+        }
+    )*};
+}
+
+impl<'de> Deserializer<'de> for Value {
+    type Error = Message;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Message> {
+        match self.0 {
+            UciValue::Option(value) => visitor.visit_string(value),
+            UciValue::List(items) => {
+                SeqDeserializer::new(items.into_iter().map(Scalar)).deserialize_any(visitor)
+            }
+        }
+    }
+
+    scalar_value! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_seq
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Message> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Message> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Message> {
+        match self.0 {
+            UciValue::Option(value) => Scalar(value).deserialize_enum(name, variants, visitor),
+            list => Value(list).deserialize_any(visitor),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 str string bytes byte_buf unit unit_struct tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+impl IntoDeserializer<'_, Message> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// What a field serializes to.
+enum Field {
+    /// `None` or `()`: the option is removed
+    Missing,
+    Bool(bool),
+    Option(String),
+    List(Vec<String>),
+}
+
+/// A serialized section.
+struct Section {
+    ty: Option<String>,
+    name: Option<String>,
+    fields: Vec<(String, Field)>,
+}
+
+impl Section {
+    fn serialize(value: &impl Serialize) -> Result<Self, Error> {
+        let fields = value.serialize(SectionSerializer).map_err(Error::new)?;
+        let mut section = Section {
+            ty: None,
+            name: None,
+            fields: Vec::new(),
+        };
+        for (key, field) in fields {
+            let special = match key.as_str() {
+                TYPE => &mut section.ty,
+                NAME => &mut section.name,
+                _ => {
+                    if !is_valid_name(&key) {
+                        bail!("{key:?} is not a valid option name");
+                    }
+                    section.fields.push((key, field));
+                    continue;
+                }
+            };
+            match field {
+                Field::Missing => (),
+                Field::Option(value) => *special = Some(value),
+                _ => bail!("{key} must be a string"),
+            }
+        }
+        if section.ty.as_ref().is_some_and(|ty| !is_valid_type(ty)) {
+            bail!("{:?} is not a valid section type", section.ty.unwrap());
+        }
+        if section
+            .name
+            .as_ref()
+            .is_some_and(|name| !is_valid_name(name))
+        {
+            bail!("{:?} is not a valid section name", section.name.unwrap());
+        }
+        Ok(section)
+    }
+
+    /// The options and lists to write, in the order they were serialized.
+    fn values(&self) -> Vec<(String, UciValue)> {
+        self.fields
+            .iter()
+            .filter_map(|(name, field)| {
+                let value = match field {
+                    Field::Missing => return None,
+                    Field::Bool(value) => UciValue::Option(if *value { "1" } else { "0" }.into()),
+                    Field::Option(value) => UciValue::Option(value.clone()),
+                    Field::List(items) => UciValue::List(items.clone()),
+                };
+                Some((name.clone(), value))
+            })
+            .collect()
+    }
+}
+
+fn unsupported(what: &str) -> Message {
+    Message(format!("{what} can't be written to a UCI config"))
+}
+
+/// Writes the whole section, which has to be a struct or a map.
+struct SectionSerializer;
+
+/// Collects the fields of a section.
+struct SectionFields {
+    fields: Vec<(String, Field)>,
+    key: Option<String>,
+}
+
+impl Serializer for SectionSerializer {
+    type Ok = Vec<(String, Field)>;
+    type Error = Message;
+    type SerializeSeq = Impossible<Self::Ok, Message>;
+    type SerializeTuple = Impossible<Self::Ok, Message>;
+    type SerializeTupleStruct = Impossible<Self::Ok, Message>;
+    type SerializeTupleVariant = Impossible<Self::Ok, Message>;
+    type SerializeMap = SectionFields;
+    type SerializeStruct = SectionFields;
+    type SerializeStructVariant = Impossible<Self::Ok, Message>;
+
+    fn serialize_bool(self, _: bool) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of bool"))
+    }
+    fn serialize_i8(self, _: i8) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of i8"))
+    }
+    fn serialize_i16(self, _: i16) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of i16"))
+    }
+    fn serialize_i32(self, _: i32) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of i32"))
+    }
+    fn serialize_i64(self, _: i64) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of i64"))
+    }
+    fn serialize_u8(self, _: u8) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of u8"))
+    }
+    fn serialize_u16(self, _: u16) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of u16"))
+    }
+    fn serialize_u32(self, _: u32) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of u32"))
+    }
+    fn serialize_u64(self, _: u64) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of u64"))
+    }
+    fn serialize_f32(self, _: f32) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of f32"))
+    }
+    fn serialize_f64(self, _: f64) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of f64"))
+    }
+    fn serialize_char(self, _: char) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of char"))
+    }
+    fn serialize_str(self, _: &str) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of str"))
+    }
+    fn serialize_bytes(self, _: &[u8]) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of bytes"))
+    }
+    fn serialize_none(self) -> Result<Self::Ok, Message> {
+        Err(unsupported("a missing section"))
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Self::Ok, Message> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Self::Ok, Message> {
+        Err(unsupported("a section of ()"))
+    }
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<Self::Ok, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<Self::Ok, Message> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Self::Ok, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Message> {
+        Err(unsupported("a section of a sequence"))
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Message> {
+        Err(unsupported("a section of a tuple"))
+    }
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Message> {
+        Ok(SectionFields {
+            fields: Vec::new(),
+            key: None,
+        })
+    }
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self::SerializeStruct, Message> {
+        self.serialize_map(None)
+    }
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Message> {
+        Err(unsupported(name))
+    }
+}
+
+impl ser::SerializeMap for SectionFields {
+    type Ok = Vec<(String, Field)>;
+    type Error = Message;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), Message> {
+        self.key = Some(key.serialize(ScalarSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Message> {
+        let key = self.key.take().expect("serialize_key is called first");
+        self.fields.push((key, value.serialize(FieldSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Message> {
+        Ok(self.fields)
+    }
+}
+
+impl ser::SerializeStruct for SectionFields {
+    type Ok = Vec<(String, Field)>;
+    type Error = Message;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &str,
+        value: &T,
+    ) -> Result<(), Message> {
+        self.fields
+            .push((key.to_owned(), value.serialize(FieldSerializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Self::Ok, Message> {
+        Ok(self.fields)
+    }
+}
+
+/// Writes the value of an option or list.
+struct FieldSerializer;
+
+/// Collects the items of a list.
+struct ListItems(Vec<String>);
+
+macro_rules! scalar_field {
+    ($($method:ident: $ty:ty,)*) => {$(
+        fn $method(self, value: $ty) -> Result<Field, Message> {
+            ScalarSerializer.$method(value).map(Field::Option)
+        }
+    )*};
+}
+
+impl Serializer for FieldSerializer {
+    type Ok = Field;
+    type Error = Message;
+    type SerializeSeq = ListItems;
+    type SerializeTuple = ListItems;
+    type SerializeTupleStruct = Impossible<Field, Message>;
+    type SerializeTupleVariant = Impossible<Field, Message>;
+    type SerializeMap = Impossible<Field, Message>;
+    type SerializeStruct = Impossible<Field, Message>;
+    type SerializeStructVariant = Impossible<Field, Message>;
+
+    fn serialize_bool(self, value: bool) -> Result<Field, Message> {
+        Ok(Field::Bool(value))
+    }
+
+    scalar_field! {
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+        serialize_bytes: &[u8],
+    }
+
+    fn serialize_none(self) -> Result<Field, Message> {
+        Ok(Field::Missing)
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Field, Message> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Field, Message> {
+        Ok(Field::Missing)
+    }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<Field, Message> {
+        Ok(Field::Missing)
+    }
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<Field, Message> {
+        ScalarSerializer
+            .serialize_unit_variant(name, index, variant)
+            .map(Field::Option)
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<Field, Message> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<Field, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<ListItems, Message> {
+        Ok(ListItems(Vec::new()))
+    }
+    fn serialize_tuple(self, _: usize) -> Result<ListItems, Message> {
+        Ok(ListItems(Vec::new()))
+    }
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Message> {
+        Err(unsupported("a map inside a section"))
+    }
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Message> {
+        Err(unsupported(name))
+    }
+}
+
+impl ser::SerializeSeq for ListItems {
+    type Ok = Field;
+    type Error = Message;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Message> {
+        self.0.push(value.serialize(ScalarSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<Field, Message> {
+        Ok(Field::List(self.0))
+    }
+}
+
+impl ser::SerializeTuple for ListItems {
+    type Ok = Field;
+    type Error = Message;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Message> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> Result<Field, Message> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+/// Writes an option value, a list item or a map key.
+struct ScalarSerializer;
+
+macro_rules! display_scalar {
+    ($($method:ident: $ty:ty,)*) => {$(
+        fn $method(self, value: $ty) -> Result<String, Message> {
+            Ok(value.to_string())
+        }
+    )*};
+}
+
+impl Serializer for ScalarSerializer {
+    type Ok = String;
+    type Error = Message;
+    type SerializeSeq = Impossible<String, Message>;
+    type SerializeTuple = Impossible<String, Message>;
+    type SerializeTupleStruct = Impossible<String, Message>;
+    type SerializeTupleVariant = Impossible<String, Message>;
+    type SerializeMap = Impossible<String, Message>;
+    type SerializeStruct = Impossible<String, Message>;
+    type SerializeStructVariant = Impossible<String, Message>;
+
+    fn serialize_bool(self, value: bool) -> Result<String, Message> {
+        Ok(if value { "1" } else { "0" }.into())
+    }
+
+    display_scalar! {
+        serialize_i8: i8,
+        serialize_i16: i16,
+        serialize_i32: i32,
+        serialize_i64: i64,
+        serialize_u8: u8,
+        serialize_u16: u16,
+        serialize_u32: u32,
+        serialize_u64: u64,
+        serialize_f32: f32,
+        serialize_f64: f64,
+        serialize_char: char,
+        serialize_str: &str,
+    }
+
+    fn serialize_bytes(self, _: &[u8]) -> Result<String, Message> {
+        Err(unsupported("bytes"))
+    }
+    fn serialize_none(self) -> Result<String, Message> {
+        Err(unsupported("a missing list item"))
+    }
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, Message> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<String, Message> {
+        Err(unsupported("a list item of ()"))
+    }
+    fn serialize_unit_struct(self, name: &'static str) -> Result<String, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<String, Message> {
+        Ok(variant.to_owned())
+    }
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<String, Message> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: &T,
+    ) -> Result<String, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Self::SerializeSeq, Message> {
+        Err(unsupported("a list of lists"))
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Self::SerializeTuple, Message> {
+        Err(unsupported("a list of tuples"))
+    }
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleStruct, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeTupleVariant, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Self::SerializeMap, Message> {
+        Err(unsupported("a list of maps"))
+    }
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStruct, Message> {
+        Err(unsupported(name))
+    }
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        _: u32,
+        _: &'static str,
+        _: usize,
+    ) -> Result<Self::SerializeStructVariant, Message> {
+        Err(unsupported(name))
+    }
+}
+
+#[test]
+fn test_serde_sections() {
+    use crate::rewrite_config_string;
+    use ::serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    enum Policy {
+        #[serde(rename = "ACCEPT")]
+        Accept,
+        #[serde(rename = "REJECT")]
+        Reject,
+    }
+
+    #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+    struct Zone {
+        #[serde(rename = ".type")]
+        ty: String,
+        #[serde(rename = ".name", skip_serializing_if = "Option::is_none")]
+        name: Option<String>,
+        input: Policy,
+        masq: bool,
+        network: Vec<String>,
+        mtu: Option<u16>,
+    }
+
+    let original = "config zone lan\n\toption input 'ACCEPT'\n\toption masq no\n\tlist network lan\n\toption family ipv4\n\toption mtu 1400\n\nconfig zone\n\toption input REJECT\n\toption masq 'on'\n\toption network 'wan wan6'\n";
+    let edited = rewrite_config_string(original.to_string(), |mut ctx| {
+        let mut zones = Vec::new();
+        while ctx.step() {
+            let Serde(mut zone) = ctx.get::<Serde<Zone>>()?;
+            zones.push(zone.clone());
+            if zone.name.as_deref() == Some("lan") {
+                zone.network.push("guest".into());
+                zone.mtu = None;
+                ctx.set(Serde(zone))?;
+            }
+        }
+        assert_eq!(
+            zones,
+            [
+                Zone {
+                    ty: "zone".into(),
+                    name: Some("lan".into()),
+                    input: Policy::Accept,
+                    masq: false,
+                    network: vec!["lan".into()],
+                    mtu: Some(1400),
+                },
+                Zone {
+                    ty: "zone".into(),
+                    name: None,
+                    input: Policy::Reject,
+                    masq: true,
+                    network: vec!["wan".into(), "wan6".into()],
+                    mtu: None,
+                },
+            ]
+        );
+
+        ctx.push(
+            Serde(Zone {
+                ty: "zone".into(),
+                name: Some("iot".into()),
+                input: Policy::Reject,
+                masq: false,
+                network: vec!["iot".into()],
+                mtu: None,
+            }),
+            None::<&str>,
+        )
+    })
+    .unwrap();
+    assert_eq!(
+        edited,
+        "config zone lan\n\toption input 'ACCEPT'\n\toption masq no\n\tlist network lan\n\toption family ipv4\n\tlist network guest\n\nconfig zone\n\toption input REJECT\n\toption masq 'on'\n\toption network 'wan wan6'\n\nconfig zone iot\n\toption input REJECT\n\toption masq 0\n\tlist network iot\n"
+    );
+}
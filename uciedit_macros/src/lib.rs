@@ -1,7 +1,10 @@
-use darling::FromDeriveInput;
+use darling::{FromDeriveInput, FromField, FromMeta, FromVariant};
 use proc_macro2::TokenStream;
 use quote::{format_ident, quote, ToTokens};
-use syn::{parse_macro_input, parse_quote, Data, DeriveInput, GenericArgument, Ident, Path, Type};
+use syn::{
+    parse_macro_input, parse_quote, Data, DataEnum, DeriveInput, Expr, ExprLit, Fields,
+    GenericArgument, Ident, Lit, Path, Type, Visibility,
+};
 
 #[derive(FromDeriveInput, Default)]
 #[darling(default, attributes(uci))]
@@ -9,17 +12,85 @@ struct UciSectionOpts {
     ty: Option<String>,
 }
 
+#[derive(FromVariant)]
+#[darling(attributes(uci))]
+struct UciVariantOpts {
+    ident: Ident,
+    #[darling(default)]
+    ty: Option<String>,
+}
+
+#[derive(FromField)]
+#[darling(attributes(uci))]
+struct UciFieldOpts {
+    ident: Option<Ident>,
+    vis: Visibility,
+    ty: Type,
+    #[darling(default)]
+    rename: Option<String>,
+    #[darling(default)]
+    default: Option<FieldDefault>,
+    #[darling(default)]
+    skip: bool,
+    #[darling(default)]
+    inpt: bool,
+    #[darling(default)]
+    split: bool,
+    #[darling(default)]
+    extra: bool,
+    #[darling(default)]
+    flatten: bool,
+}
+
+/// What a missing option reads as: `#[uci(default)]` or `#[uci(default = expr)]`.
+#[derive(Clone)]
+enum FieldDefault {
+    Trait,
+    Expr(Expr),
+}
+
+impl FromMeta for FieldDefault {
+    fn from_word() -> darling::Result<Self> {
+        Ok(FieldDefault::Trait)
+    }
+
+    fn from_expr(expr: &Expr) -> darling::Result<Self> {
+        Ok(FieldDefault::Expr(expr.clone()))
+    }
+}
+
+#[derive(Clone)]
 struct UciField {
     placehold: Ident,
     field: Ident,
+    vis: Visibility,
+    ty: Type,
+    /// How `write` gets at the value: `self.field`, or a binding for enum variants.
+    access: TokenStream,
     name: String,
     is_opt: bool,
     is_vec: bool,
     is_inpt: bool,
+    is_bool: bool,
+    /// A `Vec` that may also be written as one option of space separated words.
+    is_split: bool,
+    default: Option<FieldDefault>,
     crat: Path,
 }
 
 impl UciField {
+    /// Parses a `&str` expression into the field's value type.
+    fn parse_value(&self, text: TokenStream) -> TokenStream {
+        let crat = &self.crat;
+        if self.is_bool {
+            quote!(#crat::parse_bool(#text)?)
+        } else if self.is_inpt {
+            quote!(#crat::inpt(#text).map_err(|e| #crat::error!("{e}"))?)
+        } else {
+            quote!(std::str::FromStr::from_str(#text)?)
+        }
+    }
+
     fn read_decl(&self) -> TokenStream {
         let UciField { placehold, .. } = self;
         if self.is_vec {
@@ -30,23 +101,30 @@ impl UciField {
     }
 
     fn read_option_arm(&self) -> TokenStream {
+        if self.is_split {
+            let UciField {
+                placehold, name, ..
+            } = self;
+            let word = self.parse_value(quote!(word));
+            // like a list, but an option replaces whatever came before it
+            return quote! {
+                #name => {
+                    #placehold.clear();
+                    for word in value.as_str().split_whitespace() {
+                        #placehold.push(#word);
+                    }
+                }
+            };
+        }
         if self.is_vec {
             return TokenStream::new();
         }
         let UciField {
-            placehold,
-            name,
-            crat,
-            ..
+            placehold, name, ..
         } = self;
-        if self.is_inpt {
-            quote! {
-                #name if #placehold.is_none() => #placehold = Some(#crat::inpt(&value.as_str()).map_err(|e| #crat::error!("{e}"))?),
-            }
-        } else {
-            quote! {
-                #name if #placehold.is_none() => #placehold = Some(std::str::FromStr::from_str(&value.as_str())?),
-            }
+        let value = self.parse_value(quote!(&value.as_str()));
+        quote! {
+            #name if #placehold.is_none() => #placehold = Some(#value),
         }
     }
 
@@ -55,19 +133,11 @@ impl UciField {
             return TokenStream::new();
         }
         let UciField {
-            placehold,
-            name,
-            crat,
-            ..
+            placehold, name, ..
         } = self;
-        if self.is_inpt {
-            quote! {
-                #name => #placehold.push(#crat::inpt(&item.as_str()).map_err(|e| #crat::error!("{e}"))?),
-            }
-        } else {
-            quote! {
-                #name => #placehold.push(std::str::FromStr::from_str(&item.as_str())?),
-            }
+        let item = self.parse_value(quote!(&item.as_str()));
+        quote! {
+            #name => #placehold.push(#item),
         }
     }
 
@@ -79,52 +149,190 @@ impl UciField {
             ..
         } = self;
         if self.is_opt || self.is_vec {
-            quote! { #field: #placehold, }
-        } else {
-            let msg = format!("missing field {}", field);
-            quote! { #field: #placehold.ok_or(#crat::error!(#msg))?, }
+            return quote! { #field: #placehold, };
+        }
+        match &self.default {
+            None => {
+                let msg = format!("missing field {}", field);
+                quote! { #field: #placehold.ok_or(#crat::error!(#msg))?, }
+            }
+            Some(FieldDefault::Trait) => quote! { #field: #placehold.unwrap_or_default(), },
+            // a string default is written the way the option would be
+            Some(FieldDefault::Expr(Expr::Lit(ExprLit {
+                lit: Lit::Str(text),
+                ..
+            }))) => {
+                let value = self.parse_value(quote!(#text));
+                quote! {
+                    #field: match #placehold {
+                        Some(value) => value,
+                        None => #value,
+                    },
+                }
+            }
+            Some(FieldDefault::Expr(expr)) => {
+                quote! { #field: #placehold.unwrap_or_else(|| #expr), }
+            }
         }
     }
 
-    fn write_decl(&self) -> TokenStream {
+    /// Declares an iterator over the lines to write. `existing` is true when rewriting the
+    /// section at `index`, whose style is kept.
+    fn write_decl(&self, existing: bool) -> TokenStream {
         let UciField {
             placehold,
-            field,
+            access,
             crat,
             name,
             ..
         } = self;
+        let display = |value: TokenStream| match self.is_bool {
+            true => quote!(#crat::Token::from_str(if #value { "1" } else { "0" }, arena)),
+            false => quote!(#crat::Token::from_display(&#value, arena)),
+        };
         match (self.is_opt, self.is_vec) {
-            (false, false) => quote! {
-                let mut #placehold = Some(#crat::Line::Option {
-                    option: #crat::Token::from_str(#name, arena),
-                    value: #crat::Token::from_display(&self.#field, arena),
-                })
-                .into_iter();
-            },
-            (true, false) => quote! {
-                let mut #placehold = self.#field.iter().map(|value| #crat::Line::Option {
-                    option: #crat::Token::from_str(#name, arena),
-                    value: #crat::Token::from_display(value, arena),
-                });
-            },
-            (false, true) => quote! {
-                let mut #placehold = self.#field.iter().map(|item| #crat::Line::List {
-                    list: #crat::Token::from_str(#name, arena),
-                    item: #crat::Token::from_display(item, arena),
-                });
-            },
+            (false, false) => {
+                let value = display(access.clone());
+                quote! {
+                    let mut #placehold = Some(#crat::Line::Option {
+                        option: #crat::Token::from_str(#name, arena),
+                        value: #value,
+                    })
+                    .into_iter();
+                }
+            }
+            (true, false) => {
+                let value = display(quote!(*value));
+                quote! {
+                    let mut #placehold = #access.iter().map(|value| #crat::Line::Option {
+                        option: #crat::Token::from_str(#name, arena),
+                        value: #value,
+                    });
+                }
+            }
+            (false, true) if self.is_split && existing => {
+                let word = match self.is_bool {
+                    true => quote!((if *item { "1" } else { "0" }).to_owned()),
+                    false => quote!(item.to_string()),
+                };
+                let item = display(quote!(*item));
+                quote! {
+                    let mut #placehold = if #crat::__private::is_option(lines, index, #name) {
+                        let words: Vec<String> = #access.iter().map(|item| #word).collect();
+                        words
+                            .first()
+                            .map(|_| #crat::Line::Option {
+                                option: #crat::Token::from_str(#name, arena),
+                                value: #crat::Token::from_string(words.join(" "), arena),
+                            })
+                            .into_iter()
+                            .collect()
+                    } else {
+                        #access
+                            .iter()
+                            .map(|item| #crat::Line::List {
+                                list: #crat::Token::from_str(#name, arena),
+                                item: #item,
+                            })
+                            .collect::<Vec<_>>()
+                    }
+                    .into_iter();
+                }
+            }
+            (false, true) => {
+                let item = display(quote!(*item));
+                quote! {
+                    let mut #placehold = #access.iter().map(|item| #crat::Line::List {
+                        list: #crat::Token::from_str(#name, arena),
+                        item: #item,
+                    });
+                }
+            }
             _ => panic!("can not be both Option and Vec"),
         }
     }
 
+    /// The field's type in the patch struct: `Option<Vec<T>>`, or `Option<Option<T>>` so that
+    /// `Some(None)` can delete the option.
+    fn patch_ty(&self) -> TokenStream {
+        let ty = &self.ty;
+        match (self.is_opt, self.is_vec) {
+            (false, false) => quote!(Option<Option<#ty>>),
+            _ => quote!(Option<#ty>),
+        }
+    }
+
+    /// Replaces the option's lines if the patch sets the field.
+    fn patch_stmt(&self) -> TokenStream {
+        let UciField {
+            placehold,
+            field,
+            name,
+            is_bool,
+            crat,
+            ..
+        } = self;
+        // the value is an `&Option<T>` or `&Vec<T>`, which `write_decl` knows how to write
+        let value = UciField {
+            access: quote!((*value)),
+            is_opt: !self.is_vec,
+            ..self.clone()
+        };
+        let decl = value.write_decl(true);
+        quote! {
+            if let Some(value) = &self.#field {
+                #decl
+                let new = #placehold.collect();
+                #crat::__private::patch_option(lines, index, #name, new, #is_bool);
+            }
+        }
+    }
+
+    /// The field's entry in `UciSchema::schema`.
+    fn schema(&self) -> TokenStream {
+        let UciField { name, crat, ty, .. } = self;
+        let kind = match (self.is_vec, self.is_split) {
+            (false, _) => quote!(Option),
+            (true, false) => quote!(List),
+            (true, true) => quote!(Split),
+        };
+        let required = !self.is_opt && !self.is_vec && self.default.is_none();
+        let inner = match self.is_opt || self.is_vec {
+            true => generic_arg(ty).unwrap_or(ty),
+            false => ty,
+        };
+        let datatype = type_name(inner);
+        let rust_type = ty.to_token_stream().to_string().replace(' ', "");
+        quote! {
+            #crat::schema::OptionSchema {
+                name: #name,
+                kind: #crat::schema::OptionKind::#kind,
+                required: #required,
+                datatype: #crat::schema::datatype_of(#datatype),
+                rust_type: #rust_type,
+            }
+        }
+    }
+
     fn write_option_arm(&self) -> TokenStream {
-        if self.is_vec {
+        if self.is_vec && !self.is_split {
             return TokenStream::new();
         }
         let UciField {
-            placehold, name, ..
+            placehold,
+            name,
+            crat,
+            ..
         } = self;
+        if self.is_bool {
+            // keep `yes` or `'on'` as they are when the value doesn't change
+            return quote! {
+                #name => match #placehold.next() {
+                    Some(new) if #crat::__private::same_bool(line, &new) => continue,
+                    new => new,
+                },
+            };
+        }
         quote! {
             #name => #placehold.next(),
         }
@@ -143,7 +351,59 @@ impl UciField {
     }
 }
 
-fn chained_write_iters(fields: &[UciField]) -> Option<TokenStream> {
+/// The fields of a struct or enum variant.
+struct SectionFields {
+    fields: Vec<UciField>,
+    skipped: Vec<Ident>,
+    /// The `#[uci(extra)]` field and how `write` gets at it.
+    extra: Option<(Ident, TokenStream)>,
+}
+
+impl SectionFields {
+    fn schema(&self, ty: &str, crat: &Path) -> TokenStream {
+        let options = self.fields.iter().map(UciField::schema);
+        let extra = self.extra.is_some();
+        quote! {
+            #crat::schema::SectionSchema {
+                ty: #ty,
+                options: vec![#(#options),*],
+                extra: #extra,
+            }
+        }
+    }
+
+    fn bound(&self) -> Vec<&Ident> {
+        let fields = self.fields.iter().map(|f| &f.field);
+        fields
+            .chain(self.extra.iter().map(|(field, _)| field))
+            .collect()
+    }
+
+    /// The match arm for options and lists no field declares.
+    fn undeclared_arm(&self, found: TokenStream) -> TokenStream {
+        match &self.extra {
+            Some(_) => quote! { _ => #found, },
+            None => quote! { _ => continue, },
+        }
+    }
+
+    fn extra_write_decl(&self, crat: &Path) -> TokenStream {
+        let Some((_, access)) = &self.extra else {
+            return TokenStream::new();
+        };
+        quote! { let mut extra = #crat::__private::ExtraLines::new(&#access, arena); }
+    }
+
+    fn write_chain(&self) -> TokenStream {
+        let chain = chained_write_iters(&self.fields);
+        match &self.extra {
+            Some(_) => quote! { #chain.chain(extra.rest()) },
+            None => chain,
+        }
+    }
+}
+
+fn chained_write_iters(fields: &[UciField]) -> TokenStream {
     let mut chained = None;
     for UciField { placehold, .. } in fields.iter().rev() {
         chained = Some(match chained {
@@ -151,30 +411,47 @@ fn chained_write_iters(fields: &[UciField]) -> Option<TokenStream> {
             None => quote! { #placehold },
         });
     }
-    chained
+    chained.unwrap_or(quote! { std::iter::empty() })
 }
 
-fn read_body(fields: &[UciField], struc: Ident, _ty: String, crat: Path) -> TokenStream {
+fn read_body(section: &SectionFields, struc: TokenStream, ty: &str, crat: &Path) -> TokenStream {
+    let SectionFields {
+        fields, skipped, ..
+    } = section;
     let decl = fields.iter().map(UciField::read_decl);
     let option_arm = fields.iter().map(UciField::read_option_arm);
     let list_arm = fields.iter().map(UciField::read_list_arm);
     let init = fields.iter().map(UciField::read_init);
+    let not_section_err = format!("line {{index}} is not a {ty} section");
+    let undeclared =
+        section.undeclared_arm(quote!(#crat::__private::fold_extra(&mut extra, &lines[index])));
+    let (extra_decl, extra_init) = match &section.extra {
+        Some((field, _)) => (
+            quote! { let mut extra = Vec::new(); },
+            quote! { #field: extra.into_iter().collect(), },
+        ),
+        None => (TokenStream::new(), TokenStream::new()),
+    };
     quote! {
-        let Some(#crat::Line::Section { .. }) = lines.get(index) else {
+        let Some(#crat::Line::Section { ty, .. }) = lines.get(index) else {
             #crat::bail!("line {index} does not start a section")
         };
+        if ty.as_str() != #ty {
+            #crat::bail!(#not_section_err)
+        }
         #(#decl)*
+        #extra_decl
 
         loop {
             index += 1;
             match lines.get(index) {
                 Some(#crat::Line::Option { option, value }) => match &*option.as_str() {
                     #(#option_arm)*
-                    _ => continue,
+                    #undeclared
                 },
                 Some(#crat::Line::List { list, item }) => match &*list.as_str() {
                     #(#list_arm)*
-                    _ => continue,
+                    #undeclared
                 },
                 None | Some(#crat::Line::Section { .. }) => break,
                 _ => continue,
@@ -183,15 +460,20 @@ fn read_body(fields: &[UciField], struc: Ident, _ty: String, crat: Path) -> Toke
 
         Ok(#struc {
             #(#init)*
+            #extra_init
+            #(#skipped: Default::default(),)*
         })
     }
 }
 
-fn write_body(fields: &[UciField], _struc: Ident, ty: String, crat: Path) -> TokenStream {
-    let decl = fields.iter().map(UciField::write_decl);
+fn write_body(section: &SectionFields, ty: &str, crat: &Path) -> TokenStream {
+    let fields = &section.fields;
+    let decl = fields.iter().map(|field| field.write_decl(true));
+    let extra_decl = section.extra_write_decl(crat);
     let option_arm = fields.iter().map(UciField::write_option_arm);
     let list_arm = fields.iter().map(UciField::write_list_arm);
-    let chain = chained_write_iters(fields);
+    let undeclared = section.undeclared_arm(quote!(extra.next(line)));
+    let chain = section.write_chain();
     let not_section_err = format!("line {{index}} is not a {ty} section");
     quote! {
         let Some(#crat::Line::Section { ty, .. }) = lines.get(index) else {
@@ -202,6 +484,7 @@ fn write_body(fields: &[UciField], _struc: Ident, ty: String, crat: Path) -> Tok
         }
 
         #(#decl)*
+        #extra_decl
 
         let mut insert_after = index;
         loop {
@@ -215,11 +498,11 @@ fn write_body(fields: &[UciField], _struc: Ident, ty: String, crat: Path) -> Tok
             *line = match line {
                 #crat::Line::Option { option, .. } => match &*option.as_str() {
                     #(#option_arm)*
-                    _ => continue,
+                    #undeclared
                 },
                 #crat::Line::List { list, .. } => match &*list.as_str() {
                     #(#list_arm)*
-                    _ => continue,
+                    #undeclared
                 },
                 #crat::Line::Section { .. } => break,
                 _ => continue,
@@ -237,11 +520,13 @@ fn write_body(fields: &[UciField], _struc: Ident, ty: String, crat: Path) -> Tok
     }
 }
 
-fn append_body(fields: &[UciField], _struc: Ident, ty: String, crat: Path) -> TokenStream {
-    let decl = fields.iter().map(UciField::write_decl);
-    let chain = chained_write_iters(fields);
+fn append_body(section: &SectionFields, ty: &str, crat: &Path) -> TokenStream {
+    let decl = section.fields.iter().map(|field| field.write_decl(false));
+    let extra_decl = section.extra_write_decl(crat);
+    let chain = section.write_chain();
     quote! {
         #(#decl)*
+        #extra_decl
 
         if !lines.is_empty() {
             lines.push(#crat::Line::Empty);
@@ -257,6 +542,47 @@ fn append_body(fields: &[UciField], _struc: Ident, ty: String, crat: Path) -> To
     }
 }
 
+/// Whether the option holds a `bool`, directly or in an `Option` or `Vec`.
+fn is_bool(ty: &Type) -> bool {
+    let Type::Path(path) = ty else {
+        return false;
+    };
+    let Some(segment) = path.path.segments.last() else {
+        return false;
+    };
+    if segment.ident == "bool" {
+        return true;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return false;
+    };
+    (segment.ident == "Option" || segment.ident == "Vec")
+        && matches!(args.args.first(), Some(GenericArgument::Type(inner)) if is_bool(inner))
+}
+
+/// The `T` of `Option<T>` or `Vec<T>`.
+fn generic_arg(ty: &Type) -> Option<&Type> {
+    let Type::Path(path) = ty else {
+        return None;
+    };
+    let syn::PathArguments::AngleBracketed(args) = &path.path.segments.last()?.arguments else {
+        return None;
+    };
+    match args.args.first() {
+        Some(GenericArgument::Type(inner)) => Some(inner),
+        _ => None,
+    }
+}
+
+/// The last segment of a type's path without generics, `IpMask` for `types::IpMask`.
+fn type_name(ty: &Type) -> String {
+    match ty {
+        Type::Path(path) => path.path.segments.last().map(|s| s.ident.to_string()),
+        _ => None,
+    }
+    .unwrap_or_default()
+}
+
 fn is_collection_with_generic(ty: &Type, collection: &str) -> bool {
     if let Type::Path(path) = ty {
         if let Some(segment) = path.path.segments.first() {
@@ -274,45 +600,252 @@ fn is_collection_with_generic(ty: &Type, collection: &str) -> bool {
     false
 }
 
+/// Sorts the fields of a struct or enum variant into options, skipped fields and the catch-all.
+fn uci_fields(
+    fields: Fields,
+    crat: &Path,
+    access: impl Fn(&Ident) -> TokenStream,
+) -> SectionFields {
+    let mut uci_fields = Vec::new();
+    let mut skipped = Vec::new();
+    let mut extra = None;
+    for f in fields {
+        let opts = UciFieldOpts::from_field(&f).expect("Wrong field options");
+        let i = opts.ident.expect("only named fields are supported");
+        if opts.skip {
+            skipped.push(i);
+            continue;
+        }
+        if opts.extra || opts.flatten {
+            if extra.is_some() {
+                panic!("{i}: only one field can collect the undeclared options");
+            }
+            let access = access(&i);
+            extra = Some((i, access));
+            continue;
+        }
+        let is_opt = is_collection_with_generic(&opts.ty, "Option");
+        let is_vec = is_collection_with_generic(&opts.ty, "Vec");
+        if opts.default.is_some() && (is_opt || is_vec) {
+            panic!("{i}: only required fields can have a default");
+        }
+        if opts.split && !is_vec {
+            panic!("{i}: only Vec fields can be split");
+        }
+        uci_fields.push(UciField {
+            placehold: format_ident!("field_{}", i),
+            access: access(&i),
+            field: i.clone(),
+            vis: opts.vis,
+            ty: opts.ty.clone(),
+            name: opts.rename.unwrap_or_else(|| i.to_string()),
+            is_opt,
+            is_vec,
+            is_inpt: opts.inpt,
+            is_bool: is_bool(&opts.ty),
+            is_split: opts.split,
+            default: opts.default,
+            crat: crat.clone(),
+        });
+    }
+    SectionFields {
+        fields: uci_fields,
+        skipped,
+        extra,
+    }
+}
+
+/// Generates the `FooPatch` struct of a struct `Foo` and its `UciPatch` impl.
+fn patch_struct(
+    section: &SectionFields,
+    input: &DeriveInput,
+    ty: &str,
+    crat: &Path,
+) -> TokenStream {
+    let struc = &input.ident;
+    let vis = &input.vis;
+    let patch = format_ident!("{}Patch", struc);
+    let doc = format!(
+        "A partial update of a [`{struc}`] section. A field that is `None` leaves the option \
+         as it is, and `Some(None)` or an empty `Vec` deletes it."
+    );
+    let fields = section.fields.iter().map(|f| {
+        let (vis, field, ty) = (&f.vis, &f.field, f.patch_ty());
+        quote!(#vis #field: #ty,)
+    });
+    let stmts = section.fields.iter().map(UciField::patch_stmt);
+    let not_section_err = format!("line {{index}} is not a {ty} section");
+
+    let generics = &input.generics;
+    let (_, type_generics, where_clause) = generics.split_for_impl();
+    let mut lt_generics = generics.clone();
+    lt_generics.params.push(parse_quote! { 'a });
+    let (impl_generics, _, _) = lt_generics.split_for_impl();
+    quote! {
+        #[doc = #doc]
+        #[allow(dead_code)]
+        #[derive(Default)]
+        #vis struct #patch #generics #where_clause {
+            #(#fields)*
+        }
+
+        impl #impl_generics #crat::UciPatch<'a> for #patch #type_generics #where_clause {
+            fn patch(
+                &self,
+                lines: &mut #crat::Lines<'a>,
+                arena: &'a #crat::Arena,
+                index: usize,
+            ) -> Result<(), #crat::Error> {
+                let Some(#crat::Line::Section { ty, .. }) = lines.get(index) else {
+                    #crat::bail!("line {index} does not start a section")
+                };
+                if ty.as_str() != #ty {
+                    #crat::bail!(#not_section_err)
+                }
+                #(#stmts)*
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Generates `read`, `write` and `append` bodies for an enum with a variant per section type.
+fn enum_bodies(
+    data: DataEnum,
+    enu: &Ident,
+    crat: &Path,
+) -> (
+    TokenStream,
+    TokenStream,
+    TokenStream,
+    Vec<String>,
+    Vec<TokenStream>,
+) {
+    let mut types = Vec::new();
+    let mut schemas = Vec::new();
+    let mut read_arms = Vec::new();
+    let mut write_arms = Vec::new();
+    let mut append_arms = Vec::new();
+    for v in data.variants {
+        let opts = UciVariantOpts::from_variant(&v).expect("Wrong variant options");
+        let variant = opts.ident;
+        if matches!(v.fields, Fields::Unnamed(_)) {
+            panic!("{variant}: only variants with named fields are supported");
+        }
+        let ty = opts.ty.unwrap_or(variant.to_string().to_lowercase());
+        let section = uci_fields(v.fields, crat, |field| quote!((*#field)));
+        let bound = section.bound();
+
+        let read = read_body(&section, quote!(#enu::#variant), &ty, crat);
+        read_arms.push(quote! { #ty => { #read } });
+        let write = write_body(&section, &ty, crat);
+        write_arms.push(quote! { #enu::#variant { #(#bound,)* .. } => { #write } });
+        let append = append_body(&section, &ty, crat);
+        append_arms.push(quote! { #enu::#variant { #(#bound,)* .. } => { #append } });
+        schemas.push(section.schema(&ty, crat));
+        types.push(ty);
+    }
+    let unknown_err = format!(
+        "unknown section type {{other:?}}, expected one of: {}",
+        types.join(", ")
+    );
+    let read = quote! {
+        let Some(#crat::Line::Section { ty, .. }) = lines.get(index) else {
+            #crat::bail!("line {index} does not start a section")
+        };
+        match &*ty.as_str() {
+            #(#read_arms)*
+            other => #crat::bail!(#unknown_err),
+        }
+    };
+    let write = quote! {
+        match self {
+            #(#write_arms)*
+        }
+    };
+    let append = quote! {
+        match self {
+            #(#append_arms)*
+        }
+    };
+    (read, write, append, types, schemas)
+}
+
+/// Implements `UciSection` for a struct with named fields. Each field is an option named after
+/// it; `Option` fields may be missing and `Vec` fields are read from and written as `list`
+/// lines. The section type defaults to the lowercased struct name, or is set with
+/// `#[uci(ty = "...")]`. Reading or writing a section of another type is an error.
+///
+/// Fields take these attributes:
+/// - `#[uci(rename = "src-ip")]` reads and writes the option under another name.
+/// - `#[uci(default)]` or `#[uci(default = expr)]` is used when a required option is missing.
+///   A string literal is parsed like an option value would be, so `default = "ACCEPT"` works for
+///   any `FromStr` type.
+/// - `#[uci(skip)]` leaves the field out of the section; it reads as `Default::default()`.
+/// - `#[uci(inpt)]` parses values with `inpt` instead of `FromStr`.
+/// - `#[uci(extra)]`, or `#[uci(flatten)]`, on an `IndexMap<String, UciValue>` (re-exported by
+///   uciedit) or `Vec<(String, UciValue)>` field collects every option and list no other field
+///   declares, in the order they come in, so that writing or appending the section reproduces
+///   them. Rewriting a section in place keeps the order of the lines already there.
+/// - `#[uci(split)]` lets a `Vec` field also be a single option of space separated words, as in
+///   `option proto 'tcp udp'`. Rewriting a section keeps whichever style it already uses.
+///
+/// `bool` fields read any of OpenWrt's spellings (`1`, `yes`, `on`, `true`, `enabled` and their
+/// opposites) and are written as `1` or `0`, unless the option already says the same thing.
+///
+/// A struct `Foo` also gets a `FooPatch` with the same fields and visibility, each an
+/// `Option<Option<T>>`, or `Option<Vec<T>>` for lists: `None` leaves the option alone, and
+/// `Some(None)` or an empty `Vec` deletes it. `SectionsMut::patch` writes only what a patch sets,
+/// so a read-modify-write doesn't have to carry every other option along.
+///
+/// The derive also implements `uciedit::schema::UciSchema`, describing each section type's
+/// options for other languages.
+///
+/// On an enum, each variant has named fields like a struct and stands for one section type,
+/// again the lowercased variant name or `#[uci(ty = "...")]` on the variant. Reading picks the
+/// variant from the section type, and writing will not change a section's type.
 #[proc_macro_derive(UciSection, attributes(uci))]
 pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
     let opts = UciSectionOpts::from_derive_input(&input).expect("Wrong options");
 
     let crat: Path = parse_quote! { ::uciedit };
-    let struc = input.ident;
-    let ty = opts.ty.unwrap_or(struc.to_string().to_lowercase());
-    let Data::Struct(struct_data) = input.data else {
-        panic!("only structs are supported")
-    };
-    let fields: Vec<_> = struct_data
-        .fields
-        .into_iter()
-        .map(|f| {
-            let i = f.ident.unwrap();
-            UciField {
-                placehold: format_ident!("field_{}", i),
-                field: i.clone(),
-                name: i.to_string(),
-                is_opt: is_collection_with_generic(&f.ty, "Option"),
-                is_vec: is_collection_with_generic(&f.ty, "Vec"),
-                is_inpt: false,
-                crat: crat.clone(),
+    let struc = input.ident.clone();
+    let mut patch = TokenStream::new();
+    let (read_body, write_body, append_body, types, schemas) = match input.data.clone() {
+        Data::Struct(struct_data) => {
+            let ty = opts.ty.unwrap_or(struc.to_string().to_lowercase());
+            let section = uci_fields(struct_data.fields, &crat, |field| quote!(self.#field));
+            patch = patch_struct(&section, &input, &ty, &crat);
+            let schema = section.schema(&ty, &crat);
+            (
+                read_body(&section, quote!(#struc), &ty, &crat),
+                write_body(&section, &ty, &crat),
+                append_body(&section, &ty, &crat),
+                vec![ty],
+                vec![schema],
+            )
+        }
+        Data::Enum(enum_data) => {
+            if opts.ty.is_some() {
+                panic!("set the section type on each variant of an enum");
             }
-        })
-        .collect();
-
-    let read_body = read_body(&fields, struc.clone(), ty.clone(), crat.clone());
-    let write_body = write_body(&fields, struc.clone(), ty.clone(), crat.clone());
-    let append_body = append_body(&fields, struc.clone(), ty.clone(), crat.clone());
+            enum_bodies(enum_data, &struc, &crat)
+        }
+        Data::Union(_) => panic!("only structs and enums are supported"),
+    };
 
-    let (_, type_generics, where_clause) = input.generics.split_for_impl();
+    let (plain_generics, type_generics, where_clause) = input.generics.split_for_impl();
     let mut lt_generics = input.generics.clone();
     lt_generics.params.push(parse_quote! { 'a });
     let (impl_generics, _, _) = lt_generics.split_for_impl();
 
     quote! {
         impl #impl_generics #crat::UciSection<'a> for #struc #type_generics #where_clause {
+            fn types() -> Option<&'static [&'static str]> {
+                Some(&[#(#types),*])
+            }
+
             fn read(lines: &#crat::Lines<'a>, mut index: usize) -> Result<Self, #crat::Error> {
                 #read_body
             }
@@ -338,6 +871,13 @@ pub fn derive(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
             }
         }
 
+        impl #plain_generics #crat::schema::UciSchema for #struc #type_generics #where_clause {
+            fn schema() -> Vec<#crat::schema::SectionSchema> {
+                vec![#(#schemas),*]
+            }
+        }
+
+        #patch
     }
     .into_token_stream()
     .into()
@@ -0,0 +1,29 @@
+// Generated from the Rust section types by uciedit, do not edit.
+
+/** A `profile` section of /etc/config/secprof. */
+export interface SecprofProfile {
+    '.anonymous': boolean;
+    '.index': number;
+    '.name': string;
+    '.type': 'profile';
+    /** string */
+    lan_access: string;
+    /** string */
+    wan_access: string;
+    /** string */
+    lan_whitelist?: string | string[];
+}
+
+/** A `wpapassword` section of /etc/config/secprof. */
+export interface SecprofWpapassword {
+    '.anonymous': boolean;
+    '.index': number;
+    '.name': string;
+    '.type': 'wpapassword';
+    /** string */
+    password: string;
+    /** string */
+    profile: string;
+}
+
+export type SecprofSection = SecprofProfile | SecprofWpapassword;